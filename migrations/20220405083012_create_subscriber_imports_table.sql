@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE subscriber_imports (
+    import_id uuid NOT NULL,
+    user_id uuid NOT NULL REFERENCES users(user_id),
+    initial_status TEXT NOT NULL,
+    inserted_rows INTEGER NOT NULL DEFAULT 0,
+    duplicate_rows INTEGER NOT NULL DEFAULT 0,
+    rejected_rows INTEGER NOT NULL DEFAULT 0,
+    created_at timestamptz NOT NULL,
+    completed_at timestamptz NULL,
+    PRIMARY KEY (import_id)
+);
+
+CREATE TABLE subscriber_import_rejections (
+    import_id uuid NOT NULL REFERENCES subscriber_imports(import_id),
+    row_number INTEGER NOT NULL,
+    email TEXT NOT NULL,
+    name TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    PRIMARY KEY (import_id, row_number)
+);
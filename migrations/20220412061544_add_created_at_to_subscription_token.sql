@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Tokens issued before this migration start their lifetime now.
+ALTER TABLE subscription_token
+    ADD COLUMN created_at timestamptz NOT NULL DEFAULT now();
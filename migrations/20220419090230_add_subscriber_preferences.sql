@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Subscribers can pause delivery until a given point in time.
+ALTER TABLE subscriptions ADD COLUMN paused_until timestamptz NULL;
+-- A token with a new email confirms an email change
+-- instead of a new subscription.
+ALTER TABLE subscription_token ADD COLUMN new_email TEXT NULL;
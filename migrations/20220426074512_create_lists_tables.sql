@@ -0,0 +1,31 @@
+-- Add migration script here
+CREATE TABLE lists (
+    list_id uuid NOT NULL,
+    PRIMARY KEY (list_id),
+    name TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    -- Lists used when a subscription or an issue does not name any
+    is_default BOOLEAN NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE TABLE list_memberships (
+    list_id uuid NOT NULL REFERENCES lists (list_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    created_at timestamptz NOT NULL,
+    -- Set once the subscriber clicks on a confirmation link
+    confirmed_at timestamptz NULL,
+    PRIMARY KEY (list_id, subscriber_id)
+);
+
+-- Everybody who subscribed so far did so to the one list we had
+INSERT INTO lists (list_id, name, is_default)
+VALUES ('5a1c9e4e-2f0b-4a8e-9d6a-2d3c7b1f0e84', 'Newsletter', true);
+
+INSERT INTO list_memberships (list_id, subscriber_id, created_at, confirmed_at)
+SELECT
+    '5a1c9e4e-2f0b-4a8e-9d6a-2d3c7b1f0e84',
+    id,
+    subscribed_at,
+    CASE WHEN status = 'confirmed' THEN subscribed_at END
+FROM subscriptions;
@@ -0,0 +1,8 @@
+-- Add migration script here
+ALTER TABLE subscriptions
+    ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}',
+    ADD COLUMN custom_fields JSONB NOT NULL DEFAULT '{}';
+CREATE INDEX subscriptions_tags_idx ON subscriptions USING GIN (tags);
+
+-- The segment definition an issue was sent to, if any
+ALTER TABLE newsletter_issues ADD COLUMN segment TEXT NULL;
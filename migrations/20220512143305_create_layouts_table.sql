@@ -0,0 +1,40 @@
+-- Add migration script here
+CREATE TABLE layouts (
+    layout_id uuid NOT NULL,
+    PRIMARY KEY (layout_id),
+    name TEXT NOT NULL UNIQUE,
+    -- A full HTML document with a {{ content }} slot
+    html TEXT NOT NULL,
+    -- Used for the emails sent by the application, like confirmations
+    is_default BOOLEAN NOT NULL DEFAULT false,
+    created_at timestamptz NOT NULL DEFAULT now(),
+    updated_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX layouts_single_default ON layouts (is_default)
+WHERE is_default;
+
+INSERT INTO layouts (layout_id, name, html, is_default)
+VALUES (
+    'c3d2a8f0-6b1e-4f5a-9c7d-8e4b2a1f3d60',
+    'Default',
+    '<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <style>
+        body { font-family: Helvetica, Arial, sans-serif; color: #222222; }
+        .container { max-width: 600px; margin: 0 auto; }
+    </style>
+</head>
+<body>
+    <div class="container">
+        {{ content }}
+    </div>
+</body>
+</html>',
+    true
+);
+
+ALTER TABLE newsletter_issues
+    ADD COLUMN layout_id uuid NULL REFERENCES layouts (layout_id);
@@ -0,0 +1,24 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN tracking_enabled BOOLEAN NOT NULL DEFAULT false;
+
+-- The links of a tracked issue, the only destinations its redirects accept
+CREATE TABLE newsletter_issue_links (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    url TEXT NOT NULL,
+    PRIMARY KEY (newsletter_issue_id, url)
+);
+
+CREATE TABLE email_events (
+    event_id uuid PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    -- 'open' or 'click'
+    kind TEXT NOT NULL,
+    url TEXT NULL,
+    occurred_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX email_events_newsletter_issue_id ON email_events (newsletter_issue_id);
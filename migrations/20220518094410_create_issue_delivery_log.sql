@@ -0,0 +1,59 @@
+-- Add migration script here
+ALTER TABLE issue_delivery_queue
+    ADD COLUMN n_retries SMALLINT NOT NULL DEFAULT 0,
+    ADD COLUMN execute_after timestamptz NOT NULL DEFAULT now();
+
+-- One row per recipient, once the worker is done with them
+CREATE TABLE issue_delivery_log (
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    subscriber_email TEXT NOT NULL,
+    -- 'sent' or 'failed'
+    outcome TEXT NOT NULL,
+    n_attempts SMALLINT NOT NULL,
+    error TEXT NULL,
+    logged_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (newsletter_issue_id, subscriber_email)
+);
+
+CREATE VIEW newsletter_issue_stats AS
+SELECT
+    i.newsletter_issue_id,
+    i.title,
+    i.published_at,
+    i.tracking_enabled,
+    (
+        SELECT COUNT(*) FROM issue_delivery_queue q
+        WHERE q.newsletter_issue_id = i.newsletter_issue_id
+        AND q.n_retries = 0
+    ) AS queued,
+    (
+        SELECT COUNT(*) FROM issue_delivery_queue q
+        WHERE q.newsletter_issue_id = i.newsletter_issue_id
+        AND q.n_retries > 0
+    ) AS retrying,
+    l.sent,
+    l.failed,
+    l.started_at,
+    l.ended_at,
+    e.unique_opens,
+    e.unique_clicks
+FROM newsletter_issues i
+CROSS JOIN LATERAL (
+    SELECT
+        COUNT(*) FILTER (WHERE outcome = 'sent') AS sent,
+        COUNT(*) FILTER (WHERE outcome = 'failed') AS failed,
+        MIN(logged_at) AS started_at,
+        MAX(logged_at) AS ended_at
+    FROM issue_delivery_log
+    WHERE newsletter_issue_id = i.newsletter_issue_id
+) l
+CROSS JOIN LATERAL (
+    SELECT
+        COUNT(DISTINCT subscriber_id)
+            FILTER (WHERE kind = 'open') AS unique_opens,
+        COUNT(DISTINCT subscriber_id)
+            FILTER (WHERE kind = 'click') AS unique_clicks
+    FROM email_events
+    WHERE newsletter_issue_id = i.newsletter_issue_id
+) e;
@@ -0,0 +1,61 @@
+-- Add migration script here
+-- 'active', 'paused' or 'cancelled'
+ALTER TABLE newsletter_issues
+    ADD COLUMN delivery_status TEXT NOT NULL DEFAULT 'active';
+
+CREATE TABLE issue_delivery_actions (
+    action_id uuid PRIMARY KEY,
+    newsletter_issue_id uuid NOT NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    -- 'pause', 'resume' or 'cancel'
+    action TEXT NOT NULL,
+    user_id uuid NOT NULL REFERENCES users (user_id),
+    -- Recipients the issue had been sent to at the time
+    n_sent BIGINT NOT NULL,
+    -- Recipients still in the queue, removed from it when cancelling
+    n_remaining BIGINT NOT NULL,
+    performed_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE OR REPLACE VIEW newsletter_issue_stats AS
+SELECT
+    i.newsletter_issue_id,
+    i.title,
+    i.published_at,
+    i.tracking_enabled,
+    (
+        SELECT COUNT(*) FROM issue_delivery_queue q
+        WHERE q.newsletter_issue_id = i.newsletter_issue_id
+        AND q.n_retries = 0
+    ) AS queued,
+    (
+        SELECT COUNT(*) FROM issue_delivery_queue q
+        WHERE q.newsletter_issue_id = i.newsletter_issue_id
+        AND q.n_retries > 0
+    ) AS retrying,
+    l.sent,
+    l.failed,
+    l.started_at,
+    l.ended_at,
+    e.unique_opens,
+    e.unique_clicks,
+    i.delivery_status
+FROM newsletter_issues i
+CROSS JOIN LATERAL (
+    SELECT
+        COUNT(*) FILTER (WHERE outcome = 'sent') AS sent,
+        COUNT(*) FILTER (WHERE outcome = 'failed') AS failed,
+        MIN(logged_at) AS started_at,
+        MAX(logged_at) AS ended_at
+    FROM issue_delivery_log
+    WHERE newsletter_issue_id = i.newsletter_issue_id
+) l
+CROSS JOIN LATERAL (
+    SELECT
+        COUNT(DISTINCT subscriber_id)
+            FILTER (WHERE kind = 'open') AS unique_opens,
+        COUNT(DISTINCT subscriber_id)
+            FILTER (WHERE kind = 'click') AS unique_clicks
+    FROM email_events
+    WHERE newsletter_issue_id = i.newsletter_issue_id
+) e;
@@ -0,0 +1,12 @@
+-- Add migration script here
+ALTER TABLE newsletter_issues
+    ADD COLUMN show_in_archive BOOLEAN NOT NULL DEFAULT false,
+    ADD COLUMN slug TEXT NULL;
+
+-- Issues published before the archive existed are not in it,
+-- they still get a slug to keep the column mandatory
+UPDATE newsletter_issues SET slug = newsletter_issue_id::text;
+
+ALTER TABLE newsletter_issues
+    ALTER COLUMN slug SET NOT NULL,
+    ADD CONSTRAINT newsletter_issues_slug_key UNIQUE (slug);
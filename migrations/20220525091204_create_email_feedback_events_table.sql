@@ -0,0 +1,18 @@
+-- Add migration script here
+-- Bounces, spam complaints and subscription changes reported by Postmark.
+-- Kept by address, Postmark may report on addresses we no longer know.
+CREATE TABLE email_feedback_events (
+    event_id uuid PRIMARY KEY,
+    email TEXT NOT NULL,
+    subscriber_id uuid NULL REFERENCES subscriptions (id) ON DELETE SET NULL,
+    -- 'bounce', 'spam_complaint' or 'subscription_change'
+    kind TEXT NOT NULL,
+    description TEXT NULL,
+    -- The subscription status set because of the event, if any
+    new_status TEXT NULL,
+    payload JSONB NOT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX email_feedback_events_email ON email_feedback_events (email);
@@ -0,0 +1,28 @@
+-- Add migration script here
+-- Addresses and domains that must never be mailed, whatever their
+-- subscription status
+CREATE TABLE suppressions (
+    suppression_id uuid PRIMARY KEY,
+    -- 'address' or 'domain', stored in lowercase
+    kind TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    -- 'admin', 'bounce', 'complaint' or 'unsubscribe'
+    source TEXT NOT NULL,
+    created_by uuid NULL REFERENCES users (user_id),
+    created_at timestamptz NOT NULL DEFAULT now(),
+    UNIQUE (kind, value)
+);
+
+-- The addresses left out because of a suppression
+CREATE TABLE suppressed_sends (
+    email TEXT NOT NULL,
+    -- 'subscribe', 'enqueue' or 'delivery'
+    context TEXT NOT NULL,
+    newsletter_issue_id uuid NULL
+        REFERENCES newsletter_issues (newsletter_issue_id),
+    reason TEXT NOT NULL,
+    logged_at timestamptz NOT NULL DEFAULT now()
+);
+
+CREATE INDEX suppressed_sends_logged_at ON suppressed_sends (logged_at);
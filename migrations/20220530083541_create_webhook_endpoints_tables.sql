@@ -0,0 +1,43 @@
+-- Add migration script here
+CREATE TABLE webhook_endpoints (
+    endpoint_id uuid PRIMARY KEY,
+    url TEXT NOT NULL,
+    -- Signs the payloads, shared with the receiver
+    secret TEXT NOT NULL,
+    -- The events sent to the endpoint, e.g. 'subscriber.confirmed'
+    events TEXT[] NOT NULL,
+    created_at timestamptz NOT NULL DEFAULT now()
+);
+
+-- One row per event and endpoint, until it is delivered or given up on
+CREATE TABLE webhook_delivery_queue (
+    event_id uuid NOT NULL,
+    endpoint_id uuid NOT NULL
+        REFERENCES webhook_endpoints (endpoint_id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    payload JSONB NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (event_id, endpoint_id)
+);
+
+-- One row per delivery attempt
+CREATE TABLE webhook_delivery_log (
+    event_id uuid NOT NULL,
+    endpoint_id uuid NOT NULL
+        REFERENCES webhook_endpoints (endpoint_id) ON DELETE CASCADE,
+    event TEXT NOT NULL,
+    attempt SMALLINT NOT NULL,
+    -- 'delivered', 'retrying' or 'failed'
+    outcome TEXT NOT NULL,
+    status_code SMALLINT NULL,
+    error TEXT NULL,
+    attempted_at timestamptz NOT NULL DEFAULT now(),
+    PRIMARY KEY (event_id, endpoint_id, attempt)
+);
+
+CREATE INDEX webhook_delivery_log_endpoint_id_idx
+    ON webhook_delivery_log (endpoint_id, attempted_at);
+
+-- Set once the last recipient of an issue is done with
+ALTER TABLE newsletter_issues ADD COLUMN delivery_finished_at timestamptz NULL;
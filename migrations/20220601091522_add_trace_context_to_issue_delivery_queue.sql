@@ -0,0 +1,4 @@
+-- Add migration script here
+-- The W3C trace context of the request that published the issue, so the
+-- delivery spans continue its trace. Empty for tasks queued before this.
+ALTER TABLE issue_delivery_queue ADD COLUMN trace_context JSONB NULL;
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- One row per delivery worker process, its heartbeat is refreshed while
+-- its loop is running
+CREATE TABLE workers (
+    worker_id uuid PRIMARY KEY,
+    started_at timestamptz NOT NULL DEFAULT now(),
+    heartbeat_at timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,8 @@
+-- Add migration script here
+-- How many consumers take tasks from the queues in the worker process
+ALTER TABLE workers ADD COLUMN concurrency SMALLINT NOT NULL DEFAULT 1;
+
+-- The worker that delivered the issue, for the throughput of each worker
+ALTER TABLE issue_delivery_log ADD COLUMN worker_id uuid NULL;
+CREATE INDEX issue_delivery_log_worker_id_idx
+    ON issue_delivery_log (worker_id, logged_at);
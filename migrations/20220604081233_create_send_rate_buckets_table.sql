@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Token buckets limiting the sends of every delivery worker, one for the
+-- provider and one per recipient domain. Tokens go negative when sends
+-- are booked ahead of time.
+CREATE TABLE send_rate_buckets (
+    bucket TEXT PRIMARY KEY,
+    tokens DOUBLE PRECISION NOT NULL,
+    refilled_at timestamptz NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Add migration script here
+-- The state of the circuit breaker around the email provider, shared by
+-- every delivery worker
+CREATE TABLE circuit_breakers (
+    name TEXT PRIMARY KEY,
+    -- 'closed', 'open' or 'half_open'
+    state TEXT NOT NULL,
+    consecutive_failures INT NOT NULL,
+    changed_at timestamptz NOT NULL
+);
+INSERT INTO circuit_breakers (name, state, consecutive_failures, changed_at)
+VALUES ('email_provider', 'closed', 0, now());
@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Set instead of `completed_at` when an import stops halfway, the batches
+-- stored until then are kept
+ALTER TABLE subscriber_imports
+    ADD COLUMN failed_at timestamptz NULL,
+    ADD COLUMN error TEXT NULL;
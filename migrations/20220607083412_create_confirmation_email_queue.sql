@@ -0,0 +1,9 @@
+-- Add migration script here
+-- Confirmation emails of imported subscribers, sent by the worker
+CREATE TABLE confirmation_email_queue (
+    subscriber_id uuid PRIMARY KEY
+        REFERENCES subscriptions (id) ON DELETE CASCADE,
+    subscription_token TEXT NOT NULL,
+    n_retries SMALLINT NOT NULL DEFAULT 0,
+    execute_after timestamptz NOT NULL DEFAULT now()
+);
@@ -0,0 +1,5 @@
+-- Add migration script here
+-- When the send probing the provider was claimed, while half-open.
+-- `changed_at` keeps the time the circuit opened, so that a probe that
+-- does not send can hand the circuit back without a new cooldown.
+ALTER TABLE circuit_breakers ADD COLUMN probe_claimed_at timestamptz NULL;
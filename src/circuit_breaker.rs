@@ -0,0 +1,299 @@
+//! The circuit breaker around the email provider. After enough failed
+//! sends in a row the circuit opens and the delivery workers stop taking
+//! tasks, instead of waiting for each of them to time out. Once the
+//! cooldown is over a single send is let through as a probe, claimed right
+//! before it goes out: the circuit closes if it succeeds and opens again
+//! if the provider fails. A probe that ends without an answer from the
+//! provider hands the circuit back as it was.
+//!
+//! The state is stored in Postgres so that every worker process pauses
+//! together and the API can report it.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::configuration::CircuitBreakerSettings;
+use crate::metrics::CIRCUIT_BREAKER_TRANSITIONS;
+
+const EMAIL_PROVIDER: &str = "email_provider";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open,
+    // A probe is being sent
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub const ALL: [CircuitState; 3] =
+        [Self::Closed, Self::Open, Self::HalfOpen];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half_open",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self, sqlx::Error> {
+        match s {
+            "closed" => Ok(Self::Closed),
+            "open" => Ok(Self::Open),
+            "half_open" => Ok(Self::HalfOpen),
+            other => Err(sqlx::Error::Decode(
+                format!("Unknown circuit breaker state: {}", other).into(),
+            )),
+        }
+    }
+}
+
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: i32,
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Given right before a send, see [`CircuitBreaker::permit_send`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SendPermit {
+    Closed,
+    // The send probes whether the provider is back
+    Probe,
+}
+
+pub struct CircuitBreaker {
+    failure_threshold: i32,
+    cooldown: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(settings: &CircuitBreakerSettings) -> Self {
+        Self {
+            failure_threshold: settings.failure_threshold.max(1) as i32,
+            cooldown: Duration::from_secs(settings.cooldown_seconds),
+        }
+    }
+
+    /// Whether a task can be taken from the queue: always while the
+    /// circuit is closed, and for a probe once the cooldown is over.
+    /// Nothing is claimed until the task is about to be sent, see
+    /// [`CircuitBreaker::permit_send`].
+    #[tracing::instrument(skip_all)]
+    pub async fn allow(&self, pool: &PgPool) -> Result<bool, sqlx::Error> {
+        let row = sqlx::query!(
+            r#"
+            SELECT (
+                state = 'closed' OR (
+                    changed_at <= now() - make_interval(secs => $2) AND (
+                        state = 'open' OR
+                        probe_claimed_at <= now() - make_interval(secs => $2)
+                    )
+                )
+            ) AS "allowed!"
+            FROM circuit_breakers
+            WHERE name = $1
+            "#,
+            EMAIL_PROVIDER,
+            self.cooldown.as_secs_f64()
+        )
+        .fetch_one(pool)
+        .await?;
+        Ok(row.allowed)
+    }
+
+    /// Right before a send: always permitted while the circuit is closed,
+    /// otherwise the send is the probe if no other consumer claimed it
+    /// first. A probe that never reports back is replaced after another
+    /// cooldown.
+    #[tracing::instrument(skip_all)]
+    pub async fn permit_send(
+        &self,
+        pool: &PgPool,
+    ) -> Result<Option<SendPermit>, sqlx::Error> {
+        if get_circuit_status(pool).await?.state == CircuitState::Closed {
+            return Ok(Some(SendPermit::Closed));
+        }
+        let probe = sqlx::query!(
+            r#"
+            UPDATE circuit_breakers
+            SET state = 'half_open', probe_claimed_at = now()
+            WHERE
+                name = $1 AND
+                changed_at <= now() - make_interval(secs => $2) AND (
+                    state = 'open' OR (
+                        state = 'half_open' AND
+                        probe_claimed_at <= now() - make_interval(secs => $2)
+                    )
+                )
+            RETURNING consecutive_failures
+            "#,
+            EMAIL_PROVIDER,
+            self.cooldown.as_secs_f64()
+        )
+        .fetch_optional(pool)
+        .await?;
+        match probe {
+            Some(probe) => {
+                transitioned(
+                    CircuitState::HalfOpen,
+                    probe.consecutive_failures,
+                );
+                Ok(Some(SendPermit::Probe))
+            }
+            // Still cooling down, or another consumer is probing
+            None => Ok(None),
+        }
+    }
+
+    /// The permitted send told nothing about the provider, e.g. it was
+    /// rate limited. A probe hands the circuit back to the next one, the
+    /// cooldown is not started again.
+    #[tracing::instrument(skip_all)]
+    pub async fn release(
+        &self,
+        pool: &PgPool,
+        permit: SendPermit,
+    ) -> Result<(), sqlx::Error> {
+        if permit == SendPermit::Closed {
+            return Ok(());
+        }
+        sqlx::query!(
+            r#"
+            UPDATE circuit_breakers
+            SET state = 'open', probe_claimed_at = NULL
+            WHERE name = $1 AND state = 'half_open'
+            "#,
+            EMAIL_PROVIDER
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// A send went through, the provider is up.
+    #[tracing::instrument(skip_all)]
+    pub async fn record_success(
+        &self,
+        pool: &PgPool,
+    ) -> Result<(), sqlx::Error> {
+        // Nothing is written in the usual case of a closed circuit
+        let closed = sqlx::query!(
+            r#"
+            UPDATE circuit_breakers
+            SET
+                state = 'closed',
+                consecutive_failures = 0,
+                probe_claimed_at = NULL,
+                changed_at = CASE
+                    WHEN state = 'closed' THEN changed_at
+                    ELSE now()
+                END
+            WHERE
+                name = $1 AND
+                (state <> 'closed' OR consecutive_failures > 0)
+            RETURNING changed_at = now() AS "was_open!"
+            "#,
+            EMAIL_PROVIDER
+        )
+        .fetch_optional(pool)
+        .await?;
+        if let Some(closed) = closed {
+            if closed.was_open {
+                transitioned(CircuitState::Closed, 0);
+            }
+        }
+        Ok(())
+    }
+
+    /// A send failed because of the provider rather than of the email.
+    #[tracing::instrument(skip_all)]
+    pub async fn record_failure(
+        &self,
+        pool: &PgPool,
+    ) -> Result<(), sqlx::Error> {
+        // A failed probe opens the circuit again for another cooldown,
+        // failures of sends that were in flight when it opened are counted
+        // without extending it
+        let row = sqlx::query!(
+            r#"
+            UPDATE circuit_breakers
+            SET
+                consecutive_failures = consecutive_failures + 1,
+                state = CASE
+                    WHEN state = 'half_open' THEN 'open'
+                    WHEN state = 'closed' AND consecutive_failures + 1 >= $2
+                        THEN 'open'
+                    ELSE state
+                END,
+                changed_at = CASE
+                    WHEN state = 'half_open' THEN now()
+                    WHEN state = 'closed' AND consecutive_failures + 1 >= $2
+                        THEN now()
+                    ELSE changed_at
+                END,
+                probe_claimed_at = NULL
+            WHERE name = $1
+            RETURNING
+                state,
+                consecutive_failures,
+                changed_at = now() AS "changed!"
+            "#,
+            EMAIL_PROVIDER,
+            self.failure_threshold
+        )
+        .fetch_one(pool)
+        .await?;
+        if row.changed {
+            transitioned(
+                CircuitState::parse(&row.state)?,
+                row.consecutive_failures,
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The current state of the circuit breaker around the email provider.
+pub async fn get_circuit_status(
+    pool: &PgPool,
+) -> Result<CircuitStatus, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT state, consecutive_failures, changed_at
+        FROM circuit_breakers
+        WHERE name = $1
+        "#,
+        EMAIL_PROVIDER
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(CircuitStatus {
+        state: CircuitState::parse(&row.state)?,
+        consecutive_failures: row.consecutive_failures,
+        changed_at: row.changed_at,
+    })
+}
+
+fn transitioned(state: CircuitState, consecutive_failures: i32) {
+    CIRCUIT_BREAKER_TRANSITIONS
+        .with_label_values(&[state.as_str()])
+        .inc();
+    match state {
+        CircuitState::Open => tracing::error!(
+            consecutive_failures,
+            "The email provider keeps failing, the circuit breaker opened \
+            and deliveries are paused."
+        ),
+        CircuitState::HalfOpen => tracing::warn!(
+            consecutive_failures,
+            "The circuit breaker is half-open, probing the email provider."
+        ),
+        CircuitState::Closed => tracing::info!(
+            "The email provider is back, the circuit breaker closed and \
+            deliveries resumed."
+        ),
+    }
+}
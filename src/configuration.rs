@@ -1,18 +1,28 @@
 //! src/configuration.rs
 
 use secrecy::{ExposeSecret, Secret};
-use serde_aux::field_attributes::deserialize_number_from_string;
+use serde_aux::field_attributes::{
+    deserialize_number_from_string, deserialize_option_number_from_string,
+};
 use sqlx::{
     postgres::{PgConnectOptions, PgSslMode},
     ConnectOptions,
 };
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    domain::SubscriberEmail, email_client::EmailClient,
+    html_sanitizer::HtmlSanitizer,
+};
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub sanitization: SanitizationSettings,
+    pub postmark_webhook: PostmarkWebhookSettings,
+    pub metrics: MetricsSettings,
+    pub telemetry: TelemetrySettings,
+    pub worker: WorkerSettings,
     // We have not created a stand-alone settings struct for Redis,
     // let's see if we need more than the uri first!
     // The URI is marked as secret because it may embed a password.
@@ -25,6 +35,32 @@ pub struct EmailClientSettings {
     pub sender_email: String,
     pub authorization_token: Secret<String>,
     pub timeout_milliseconds: u64,
+    pub rate_limit: RateLimitSettings,
+    pub circuit_breaker: CircuitBreakerSettings,
+}
+
+/// How fast the delivery worker may send, across all its consumers and
+/// processes. Both rates must be above zero.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct RateLimitSettings {
+    #[serde(deserialize_with = "deserialize_send_rate")]
+    pub messages_per_second: f64,
+    // For the recipients sharing a domain, e.g. `gmail.com`
+    #[serde(deserialize_with = "deserialize_send_rate")]
+    pub messages_per_second_per_domain: f64,
+}
+
+// Refuse to start rather than never send, or divide by zero
+fn deserialize_send_rate<'de, D>(deserializer: D) -> Result<f64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let rate: f64 = deserialize_number_from_string(deserializer)?;
+    if rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(serde::de::Error::custom("Send rates must be above zero."))
+    }
 }
 
 impl EmailClientSettings {
@@ -49,6 +85,78 @@ impl EmailClientSettings {
     }
 }
 
+/// When the delivery workers stop sending after the provider failed.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CircuitBreakerSettings {
+    // Consecutive failed sends that open the circuit
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub failure_threshold: u32,
+    // How long deliveries are paused before a send is attempted again
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub cooldown_seconds: u64,
+}
+
+/// The credentials Postmark uses to call our webhook, either with basic
+/// auth or by sending the password in the `X-Webhook-Secret` header.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct PostmarkWebhookSettings {
+    pub username: String,
+    pub password: Secret<String>,
+}
+
+/// The delivery worker running alongside the API.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct WorkerSettings {
+    // Consumers taking tasks from the queues at the same time, at least one
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub concurrency: u16,
+}
+
+/// Where the Prometheus metrics are served: on their own port, out of
+/// reach of the public, or on `/metrics` of the application port behind
+/// a bearer token. Without either they are not served.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct MetricsSettings {
+    #[serde(
+        default,
+        deserialize_with = "deserialize_option_number_from_string"
+    )]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub bearer_token: Option<Secret<String>>,
+}
+
+/// Where spans are exported with OTLP over HTTP, e.g.
+/// `http://localhost:4318/v1/traces`. Without an endpoint trace contexts
+/// are still propagated, but the spans only end up in the logs.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TelemetrySettings {
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+}
+
+/// The HTML allowed in newsletter issues, anything else is removed
+/// when they are published.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct SanitizationSettings {
+    pub allowed_tags: Vec<String>,
+    pub allowed_attributes: Vec<String>,
+    pub allowed_url_schemes: Vec<String>,
+    // Rewrite `http` links and images to `https`
+    pub upgrade_insecure_urls: bool,
+}
+
+impl SanitizationSettings {
+    pub fn sanitizer(self) -> HtmlSanitizer {
+        HtmlSanitizer::new(
+            self.allowed_tags,
+            self.allowed_attributes,
+            self.allowed_url_schemes,
+            self.upgrade_insecure_urls,
+        )
+    }
+}
+
 #[derive(serde:: Deserialize, Debug, Clone)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
@@ -56,6 +164,16 @@ pub struct ApplicationSettings {
     pub host: String,
     pub base_url: String,
     pub hmac_secret: Secret<String>,
+    // How long a subscription confirmation link stays valid
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub subscription_token_expiry_hours: i64,
+    pub newsletter_name: String,
+}
+
+impl ApplicationSettings {
+    pub fn subscription_token_expiry(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.subscription_token_expiry_hours)
+    }
 }
 
 #[derive(serde::Deserialize, Debug, Clone)]
@@ -144,3 +262,33 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimitSettings;
+
+    #[test]
+    fn send_rates_can_be_given_as_strings() {
+        let settings: RateLimitSettings =
+            serde_json::from_value(serde_json::json!({
+                "messages_per_second": "10",
+                "messages_per_second_per_domain": 0.5
+            }))
+            .unwrap();
+        assert_eq!(settings.messages_per_second, 10.0);
+        assert_eq!(settings.messages_per_second_per_domain, 0.5);
+    }
+
+    #[test]
+    fn send_rates_must_be_above_zero() {
+        for rate in [0.0, -1.0] {
+            let settings = serde_json::from_value::<RateLimitSettings>(
+                serde_json::json!({
+                    "messages_per_second": rate,
+                    "messages_per_second_per_domain": 1.0
+                }),
+            );
+            assert!(settings.is_err());
+        }
+    }
+}
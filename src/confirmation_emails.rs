@@ -0,0 +1,304 @@
+//! Confirmation emails of imported subscribers. They are queued with the
+//! rows of the import and sent by the delivery worker, so that a large
+//! import does not hold the request open while they go out.
+use std::time::Duration;
+
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    circuit_breaker::CircuitBreaker,
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName},
+    email_client::{EmailClient, SendEmailError},
+    issue_delivery_worker::ExecutionOutcome,
+    layouts::get_default_layout,
+    rate_limiter::SendRateLimiter,
+    routes::send_confirmation_email,
+    suppressions::{
+        skip_transactional_email, TransactionalEmailError, TransactionalSend,
+    },
+};
+
+// How many times a confirmation email is sent before giving up
+const MAX_SEND_ATTEMPTS: i16 = 5;
+// Delay before the first retry, doubled after every failed attempt
+const RETRY_BACKOFF_SECONDS: f64 = 30.0;
+// How long to hold back when the provider rate limits us without telling
+// for how long
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+// An email set aside while waiting for our turn to send is left alone by
+// the other consumers for this long after it
+const SET_ASIDE_MARGIN: Duration = Duration::from_secs(60);
+
+/// Queue a confirmation email for each subscriber, with the matching
+/// token. They are only sent once the transaction is committed.
+#[tracing::instrument(skip_all, fields(n_emails = subscriber_ids.len()))]
+pub async fn enqueue_confirmation_emails(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    subscription_tokens: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO confirmation_email_queue (
+            subscriber_id,
+            subscription_token
+        )
+        SELECT * FROM UNNEST($1::uuid[], $2::text[])
+        "#,
+        subscriber_ids,
+        subscription_tokens
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Task {
+    subscriber_id: Uuid,
+    subscription_token: String,
+    n_retries: i16,
+    email: String,
+    name: String,
+    status: String,
+}
+
+/// Send the next confirmation email in the queue.
+#[tracing::instrument(skip_all)]
+pub async fn try_send_confirmation_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    circuit_breaker: &CircuitBreaker,
+    base_url: &str,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    if !circuit_breaker.allow(pool).await? {
+        return Ok(ExecutionOutcome::Paused);
+    }
+    let (mut transaction, task) = match dequeue_task(pool).await? {
+        Some(next) => next,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    // Confirmed in the meantime, e.g. by importing them again as such
+    if task.status != "pending_confirmation" {
+        delete_task(&mut transaction, &task).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+    let new_subscriber = match parse_subscriber(&task) {
+        Ok(new_subscriber) => new_subscriber,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping an imported subscriber. \
+                Their stored contact details are invalid",
+            );
+            delete_task(&mut transaction, &task).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+
+    let email = new_subscriber.email.as_ref();
+    if skip_transactional_email(pool, email, "subscribe").await? {
+        delete_task(&mut transaction, &task).await?;
+        transaction.commit().await?;
+        return Ok(ExecutionOutcome::TaskCompleted);
+    }
+
+    let layout = get_default_layout(pool).await?;
+    let permit = match circuit_breaker.permit_send(pool).await? {
+        Some(permit) => permit,
+        // Left in the queue as it was
+        None => return Ok(ExecutionOutcome::Paused),
+    };
+    // The send is booked with the rate limiter only once it is certain. If
+    // it is not our turn yet, the email is set aside and unlocked while we
+    // wait.
+    let wait = rate_limiter.reserve(pool, &new_subscriber.email).await?;
+    if !wait.is_zero() {
+        postpone_task(&mut transaction, &task, wait + SET_ASIDE_MARGIN).await?;
+        transaction.commit().await?;
+        tokio::time::sleep(wait).await;
+        transaction = match take_task(pool, &task).await? {
+            Some(transaction) => transaction,
+            None => {
+                circuit_breaker.release(pool, permit).await?;
+                return Ok(ExecutionOutcome::TaskCompleted);
+            }
+        };
+    }
+    match send_confirmation_email(
+        pool,
+        email_client,
+        new_subscriber,
+        base_url,
+        &task.subscription_token,
+        layout.as_ref(),
+    )
+    .await
+    {
+        Ok(TransactionalSend::Sent) => {
+            circuit_breaker.record_success(pool).await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+        Ok(TransactionalSend::Suppressed) => {
+            circuit_breaker.release(pool, permit).await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+        Err(TransactionalEmailError::Suppressions(e)) => {
+            circuit_breaker.release(pool, permit).await?;
+            return Err(e.into());
+        }
+        Err(TransactionalEmailError::Send(SendEmailError::RateLimited {
+            retry_after,
+        })) => {
+            let delay = retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+            circuit_breaker.release(pool, permit).await?;
+            rate_limiter.back_off(pool, delay).await?;
+            postpone_task(&mut transaction, &task, delay).await?;
+        }
+        Err(TransactionalEmailError::Send(e)) => {
+            if e.is_provider_failure() {
+                circuit_breaker.record_failure(pool).await?;
+            } else {
+                circuit_breaker.release(pool, permit).await?;
+            }
+            if task.n_retries + 1 < MAX_SEND_ATTEMPTS {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email, retrying later.",
+                );
+                retry_task(&mut transaction, &task).await?;
+            } else {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to send a confirmation email to an imported \
+                    subscriber, giving up.",
+                );
+                delete_task(&mut transaction, &task).await?;
+            }
+        }
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+fn parse_subscriber(task: &Task) -> Result<NewSubscriber, String> {
+    let email = SubscriberEmail::parse(task.email.clone())?;
+    let name = SubscriberName::parse(task.name.clone())?;
+    Ok(NewSubscriber { email, name })
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_task(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT
+            q.subscriber_id,
+            q.subscription_token,
+            q.n_retries,
+            s.email,
+            s.name,
+            s.status
+        FROM confirmation_email_queue q
+        JOIN subscriptions s ON s.id = q.subscriber_id
+        WHERE q.execute_after <= now()
+        ORDER BY q.execute_after
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+// Lock an email again, unless it was removed since
+#[tracing::instrument(skip_all)]
+async fn take_task(
+    pool: &PgPool,
+    task: &Task,
+) -> Result<Option<PgTransaction>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let row = sqlx::query!(
+        r#"
+        SELECT subscriber_id FROM confirmation_email_queue
+        WHERE subscriber_id = $1
+        FOR UPDATE
+        SKIP LOCKED
+        "#,
+        task.subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(row.map(|_| transaction))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM confirmation_email_queue
+        WHERE subscriber_id = $1
+        "#,
+        task.subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+// Leave the email in the queue for another attempt, after a backoff
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    let backoff = RETRY_BACKOFF_SECONDS * 2f64.powi(task.n_retries.into());
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $2)
+        WHERE subscriber_id = $1
+        "#,
+        task.subscriber_id,
+        backoff
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE confirmation_email_queue
+        SET execute_after = now() + make_interval(secs => $2)
+        WHERE subscriber_id = $1
+        "#,
+        task.subscriber_id,
+        delay.as_secs_f64()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
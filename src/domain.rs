@@ -1,7 +1,21 @@
+mod layout;
+mod markdown;
 mod new_subscriber;
+mod preferences_token;
+mod segment;
+mod subscriber_attributes;
 mod subscriber_email;
 mod subscriber_name;
+mod template;
+mod tracking_token;
 
+pub use layout::*;
+pub use markdown::*;
 pub use new_subscriber::*;
+pub use preferences_token::*;
+pub use segment::*;
+pub use subscriber_attributes::*;
 pub use subscriber_email::*;
 pub use subscriber_name::*;
+pub use template::*;
+pub use tracking_token::*;
@@ -0,0 +1,307 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{
+    element, rewrite_str, text, ElementContentHandlers, RewriteStrSettings,
+    Selector,
+};
+
+use crate::domain::Template;
+
+/// An HTML email layout: header, footer and styles around a
+/// `{{ content }}` slot. Layouts can use merge tags, like issues.
+#[derive(Debug)]
+pub struct Layout(String);
+
+impl Layout {
+    pub fn parse(s: String) -> Result<Layout, String> {
+        let slot = match content_slots(&s)[..] {
+            [slot] => slot,
+            [] => return Err("The layout has no {{ content }} slot.".into()),
+            _ => {
+                return Err(
+                    "The layout has more than one {{ content }} slot.".into()
+                )
+            }
+        };
+        let without_slot = format!("{}{}", &s[..slot.0], &s[slot.1..]);
+        Template::parse(&without_slot)?;
+        inline_css(&without_slot)?;
+        Ok(Self(s))
+    }
+
+    /// Put some HTML content in the layout,
+    /// then inline the stylesheets in `style` attributes.
+    pub fn render(&self, content: &str) -> Result<String, String> {
+        let (start, end) = content_slots(&self.0)[0];
+        inline_css(&format!(
+            "{}{}{}",
+            &self.0[..start],
+            content,
+            &self.0[end..]
+        ))
+    }
+}
+
+impl AsRef<str> for Layout {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Byte ranges of the `{{ content }}` tags
+fn content_slots(s: &str) -> Vec<(usize, usize)> {
+    let mut slots = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = s[offset..].find("{{").map(|i| offset + i) {
+        let end = match s[start..].find("}}") {
+            Some(i) => start + i + 2,
+            None => break,
+        };
+        if s[start + 2..end - 2].trim() == "content" {
+            slots.push((start, end));
+        }
+        offset = end;
+    }
+    slots
+}
+
+struct CssRule {
+    selector: Selector,
+    specificity: (usize, usize, usize),
+    declarations: String,
+}
+
+/// Move the rules of `<style>` elements to the `style` attributes of the
+/// elements they match, as many mail clients ignore stylesheets.
+/// Rules that cannot be inlined, like media queries or pseudo-classes,
+/// are kept in a single stylesheet in the head of the document.
+fn inline_css(html: &str) -> Result<String, String> {
+    let mut css = String::new();
+    rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![text!("style", |t| {
+                css.push_str(t.as_str());
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    let (mut rules, kept) = parse_stylesheet(&css);
+    // The declarations of every matching rule are prepended to the
+    // `style` attribute: the most specific rules go first, so that they
+    // end up last and win, and inline styles win over all of them.
+    // Sorting is stable, the latest of two equally specific rules wins.
+    rules.reverse();
+    rules.sort_by_key(|r| Reverse(r.specificity));
+
+    let mut handlers = vec![element!("style", |el| {
+        el.remove();
+        Ok(())
+    })];
+    for rule in rules {
+        let declarations = rule.declarations;
+        handlers.push((
+            Cow::Owned(rule.selector),
+            ElementContentHandlers::default().element(
+                move |el: &mut Element| {
+                    let style = match el.get_attribute("style") {
+                        Some(style) => format!("{}; {}", declarations, style),
+                        None => declarations.clone(),
+                    };
+                    el.set_attribute("style", &style)?;
+                    Ok(())
+                },
+            ),
+        ));
+    }
+    let mut has_head = false;
+    if !kept.is_empty() {
+        handlers.push(element!("head", |el| {
+            has_head = true;
+            el.append(&format!("<style>{}</style>", kept), ContentType::Html);
+            Ok(())
+        }));
+    }
+    let inlined = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..RewriteStrSettings::default()
+        },
+    )
+    .map_err(|e| e.to_string())?;
+
+    if kept.is_empty() || has_head {
+        Ok(inlined)
+    } else {
+        Ok(format!("<style>{}</style>{}", kept, inlined))
+    }
+}
+
+// Split a stylesheet in the rules to inline and the rest of it
+fn parse_stylesheet(css: &str) -> (Vec<CssRule>, String) {
+    let css = strip_comments(css);
+    let mut rules = Vec::new();
+    let mut kept = String::new();
+    let mut rest = css.as_str();
+    while let Some(open) = rest.find('{') {
+        let close = match matching_brace(&rest[open..]) {
+            Some(i) => open + i,
+            None => break,
+        };
+        let prelude = rest[..open].trim();
+        let block = rest[open + 1..close].trim();
+        if prelude.starts_with('@') {
+            kept.push_str(&format!("{} {{ {} }}\n", prelude, block));
+        } else {
+            let declarations = block.trim_end_matches(';').trim();
+            for selector in prelude.split(',').map(str::trim) {
+                match selector.parse::<Selector>() {
+                    Ok(parsed) if !declarations.is_empty() => {
+                        rules.push(CssRule {
+                            selector: parsed,
+                            specificity: specificity(selector),
+                            declarations: declarations.to_owned(),
+                        })
+                    }
+                    Ok(_) => {}
+                    Err(_) => kept
+                        .push_str(&format!("{} {{ {} }}\n", selector, block)),
+                }
+            }
+        }
+        rest = &rest[close + 1..];
+    }
+    (rules, kept.trim().to_owned())
+}
+
+fn strip_comments(css: &str) -> String {
+    let mut stripped = String::new();
+    let mut rest = css;
+    while let Some(start) = rest.find("/*") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+// Index of the brace closing the block opened at the start of `s`
+fn matching_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Counts of ids, classes and attributes, and element names
+fn specificity(selector: &str) -> (usize, usize, usize) {
+    let ids = selector.matches('#').count();
+    let classes = selector.matches('.').count()
+        + selector.matches('[').count()
+        + selector.matches(':').count();
+    let elements = selector
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '+' || c == '~')
+        .filter(|s| s.starts_with(|c: char| c.is_ascii_alphabetic()))
+        .count();
+    (ids, classes, elements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Layout;
+    use claim::{assert_err, assert_ok};
+
+    fn render(layout: &str, content: &str) -> String {
+        Layout::parse(layout.into())
+            .unwrap()
+            .render(content)
+            .unwrap()
+    }
+
+    #[test]
+    fn a_layout_needs_exactly_one_content_slot() {
+        assert_ok!(Layout::parse("<div>{{content}}</div>".into()));
+        assert_err!(Layout::parse("<div></div>".into()));
+        assert_err!(Layout::parse(
+            "<div>{{ content }}</div>{{ content }}".into()
+        ));
+    }
+
+    #[test]
+    fn merge_tags_in_layouts_are_validated() {
+        assert_ok!(Layout::parse(
+            r#"{{ content }}<a href="{{ unsubscribe_url }}">Unsubscribe</a>"#
+                .into()
+        ));
+        assert_err!(Layout::parse("{{ content }}{{ unsubscribe }}".into()));
+    }
+
+    #[test]
+    fn content_is_put_in_the_slot() {
+        assert_eq!(
+            render(
+                "<main>{{ content }}</main><footer>Bye</footer>",
+                "<p>Hi</p>"
+            ),
+            "<main><p>Hi</p></main><footer>Bye</footer>"
+        );
+    }
+
+    #[test]
+    fn stylesheets_are_inlined() {
+        assert_eq!(
+            render(
+                "<style>p { color: red; } .big, h1 { font-size: 2em }</style>\
+                {{ content }}",
+                r#"<h1>Title</h1><p class="big">Hi</p>"#
+            ),
+            r#"<h1 style="font-size: 2em">Title</h1><p class="big" style="color: red; font-size: 2em">Hi</p>"#
+        );
+    }
+
+    #[test]
+    fn specific_rules_and_inline_styles_win() {
+        assert_eq!(
+            render(
+                "<style>.note { color: blue } p { color: red } \
+                p { color: green }</style>{{ content }}",
+                r#"<p class="note" style="margin: 0">Hi</p>"#
+            ),
+            r#"<p class="note" style="color: red; color: green; color: blue; margin: 0">Hi</p>"#
+        );
+    }
+
+    #[test]
+    fn rules_that_cannot_be_inlined_are_kept_in_the_head() {
+        assert_eq!(
+            render(
+                "<html><head><style>/* links */ a { color: red } \
+                a:hover { color: blue } @media (max-width: 600px) { \
+                a { color: green } }</style></head><body>{{ content }}</body></html>",
+                r#"<a href="https://example.com">Link</a>"#
+            ),
+            "<html><head><style>a:hover { color: blue }\n\
+            @media (max-width: 600px) { a { color: green } }</style></head><body>\
+            <a href=\"https://example.com\" style=\"color: red\">Link</a></body></html>"
+        );
+    }
+}
@@ -0,0 +1,337 @@
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag};
+
+/// The Markdown source of a newsletter issue.
+/// Both the HTML and the plain-text bodies are generated from it.
+#[derive(Debug)]
+pub struct Markdown(String);
+
+impl Markdown {
+    pub fn parse(s: String) -> Result<Markdown, String> {
+        if s.trim().is_empty() {
+            return Err("The Markdown content is empty.".into());
+        }
+        Ok(Self(s))
+    }
+
+    /// Render the HTML body of the issue.
+    /// Raw HTML is allowed in Markdown: sanitize the output like any other
+    /// HTML content.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, Parser::new(&self.0));
+        restore_merge_tags(&html)
+    }
+
+    /// Render a plain-text body meant to be read as is: headings are
+    /// underlined and links are listed as footnotes at the end.
+    pub fn to_text(&self) -> String {
+        let mut writer = TextWriter::default();
+        for event in Parser::new(&self.0) {
+            writer.handle(event);
+        }
+        writer.finish()
+    }
+}
+
+impl AsRef<str> for Markdown {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// Link destinations are percent-encoded in the HTML,
+// merge tags must be decoded to be filled in.
+fn restore_merge_tags(html: &str) -> String {
+    let mut restored = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find("%7B%7B") {
+        let end = match rest[start..].find("%7D%7D") {
+            Some(end) => start + end + "%7D%7D".len(),
+            None => break,
+        };
+        restored.push_str(&rest[..start]);
+        match urlencoding::decode(&rest[start..end]) {
+            Ok(tag) => restored.push_str(&htmlescape::encode_minimal(&tag)),
+            Err(_) => restored.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    restored.push_str(rest);
+    restored
+}
+
+#[derive(PartialEq, PartialOrd)]
+enum Break {
+    Line,
+    BlankLine,
+}
+
+// Written at the start of the lines of a block quote or of a list item.
+// List items are marked on their first line only, then indented.
+struct LinePrefix {
+    first: String,
+    rest: String,
+    used: bool,
+}
+
+#[derive(Default)]
+struct TextWriter {
+    output: String,
+    // Breaks are only written before the next text,
+    // so that nested blocks do not pile up blank lines
+    pending_break: Option<Break>,
+    prefixes: Vec<LinePrefix>,
+    // The next number of each open list, None for bullet lists
+    lists: Vec<Option<u64>>,
+    heading_start: usize,
+    code_block: Option<String>,
+    // Destination and start of the text of each open link
+    open_links: Vec<(String, usize)>,
+    footnotes: Vec<String>,
+}
+
+impl TextWriter {
+    fn handle(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) => match &mut self.code_block {
+                Some(code) => code.push_str(&text),
+                None => self.write(&text),
+            },
+            Event::Code(code) => self.write(&code),
+            Event::SoftBreak | Event::HardBreak => self.write("\n"),
+            Event::Rule => {
+                self.request_break(Break::BlankLine);
+                self.write(&"-".repeat(10));
+                self.request_break(Break::BlankLine);
+            }
+            // Raw HTML has no plain-text rendering
+            _ => {}
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.request_break(Break::BlankLine),
+            Tag::Heading(..) => {
+                self.request_break(Break::BlankLine);
+                self.write("");
+                self.heading_start = self.output.len();
+            }
+            Tag::BlockQuote => {
+                self.request_break(Break::BlankLine);
+                self.push_prefix("> ".into(), "> ".into());
+            }
+            Tag::CodeBlock(_) => {
+                self.request_break(Break::BlankLine);
+                self.code_block = Some(String::new());
+            }
+            Tag::List(first_number) => {
+                if self.lists.is_empty() {
+                    self.request_break(Break::BlankLine);
+                } else {
+                    self.request_break(Break::Line);
+                }
+                self.lists.push(first_number);
+            }
+            Tag::Item => {
+                self.request_break(Break::Line);
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".into(),
+                };
+                let indent = " ".repeat(marker.len());
+                self.push_prefix(marker, indent);
+            }
+            Tag::Link(_, destination, _) | Tag::Image(_, destination, _) => {
+                self.write("");
+                self.open_links
+                    .push((destination.to_string(), self.output.len()));
+            }
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => self.request_break(Break::BlankLine),
+            Tag::Heading(level, ..) => {
+                let width = self.output[self.heading_start..].chars().count();
+                let underline =
+                    if level == HeadingLevel::H1 { "=" } else { "-" };
+                self.request_break(Break::Line);
+                self.write(&underline.repeat(width));
+                self.request_break(Break::BlankLine);
+            }
+            Tag::BlockQuote => {
+                self.prefixes.pop();
+                self.request_break(Break::BlankLine);
+            }
+            Tag::CodeBlock(_) => {
+                if let Some(code) = self.code_block.take() {
+                    self.push_prefix("    ".into(), "    ".into());
+                    self.write(code.trim_end_matches('\n'));
+                    self.prefixes.pop();
+                }
+                self.request_break(Break::BlankLine);
+            }
+            Tag::List(_) => {
+                self.lists.pop();
+                self.request_break(Break::BlankLine);
+            }
+            Tag::Item => {
+                self.prefixes.pop();
+                self.request_break(Break::Line);
+            }
+            Tag::Link(..) | Tag::Image(..) => {
+                if let Some((destination, start)) = self.open_links.pop() {
+                    // Autolinks already show their destination
+                    if self.output[start..] != destination {
+                        self.footnotes.push(destination);
+                        self.write(&format!(" [{}]", self.footnotes.len()));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if !self.footnotes.is_empty() {
+            self.output.push_str("\n\n");
+            for (i, destination) in self.footnotes.iter().enumerate() {
+                self.output
+                    .push_str(&format!("[{}] {}\n", i + 1, destination));
+            }
+        }
+        self.output.trim_end().to_owned()
+    }
+
+    fn request_break(&mut self, line_break: Break) {
+        if self.pending_break.as_ref() < Some(&line_break) {
+            self.pending_break = Some(line_break);
+        }
+    }
+
+    fn push_prefix(&mut self, first: String, rest: String) {
+        self.prefixes.push(LinePrefix {
+            first,
+            rest,
+            used: false,
+        });
+    }
+
+    fn write(&mut self, text: &str) {
+        if let Some(line_break) = self.pending_break.take() {
+            if !self.output.is_empty() {
+                if line_break == Break::BlankLine {
+                    self.output.push('\n');
+                    let blank_line: String = self
+                        .prefixes
+                        .iter()
+                        .filter(|p| p.used)
+                        .map(|p| p.rest.as_str())
+                        .collect();
+                    self.output.push_str(blank_line.trim_end());
+                }
+                self.output.push('\n');
+            }
+            self.write_prefixes();
+        }
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.output.push('\n');
+                self.write_prefixes();
+            }
+            self.output.push_str(line);
+        }
+    }
+
+    fn write_prefixes(&mut self) {
+        for prefix in &mut self.prefixes {
+            if prefix.used {
+                self.output.push_str(&prefix.rest);
+            } else {
+                self.output.push_str(&prefix.first);
+                prefix.used = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Markdown;
+    use claim::assert_err;
+
+    fn markdown(s: &str) -> Markdown {
+        Markdown::parse(s.into()).unwrap()
+    }
+
+    #[test]
+    fn empty_markdown_is_rejected() {
+        assert_err!(Markdown::parse("  \n".into()));
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_html() {
+        let html = markdown("# Title\n\nSome *emphasis*.").to_html();
+        assert_eq!(html, "<h1>Title</h1>\n<p>Some <em>emphasis</em>.</p>\n");
+    }
+
+    #[test]
+    fn headings_are_underlined_in_the_text() {
+        let text = markdown("# Title\n\n## Section\n\nBody").to_text();
+        assert_eq!(text, "Title\n=====\n\nSection\n-------\n\nBody");
+    }
+
+    #[test]
+    fn links_become_footnotes_in_the_text() {
+        let text = markdown(
+            "Read [the post](https://example.com/post) \
+            or [the book](https://example.com/book).\n\n\
+            Visit <https://example.com>.",
+        )
+        .to_text();
+        assert_eq!(
+            text,
+            "Read the post [1] or the book [2].\n\n\
+            Visit https://example.com.\n\n\
+            [1] https://example.com/post\n\
+            [2] https://example.com/book"
+        );
+    }
+
+    #[test]
+    fn lists_and_quotes_are_indented_in_the_text() {
+        let text = markdown(
+            "- one\n- two\n  - nested\n\n1. first\n2. second\n\n> quoted\n> text",
+        )
+        .to_text();
+        assert_eq!(
+            text,
+            "- one\n- two\n  - nested\n\n1. first\n2. second\n\n> quoted\n> text"
+        );
+    }
+
+    #[test]
+    fn code_blocks_are_indented_in_the_text() {
+        let text =
+            markdown("Run:\n\n```\ncargo test\ncargo run\n```").to_text();
+        assert_eq!(text, "Run:\n\n    cargo test\n    cargo run");
+    }
+
+    #[test]
+    fn merge_tags_are_kept() {
+        let source = markdown(
+            "Hi {{ subscriber.name }}, [unsubscribe](<{{ unsubscribe_url }}>)",
+        );
+        assert!(source.to_html().contains(r#"href="{{ unsubscribe_url }}""#));
+        assert!(source.to_html().contains("Hi {{ subscriber.name }}"));
+        assert!(source.to_text().contains("[1] {{ unsubscribe_url }}"));
+    }
+}
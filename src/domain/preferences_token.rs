@@ -0,0 +1,134 @@
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A signed, expiring token identifying a subscriber.
+///
+/// It is sent out in magic links and lets subscribers manage their own
+/// preferences without an account. The string form is
+/// `{subscriber_id}.{expires_at}.{signature}`, where the signature is an
+/// HMAC-SHA256 of the first two parts keyed with the application secret.
+#[derive(Debug)]
+pub struct PreferencesToken {
+    subscriber_id: Uuid,
+    // Unix timestamp, in seconds
+    expires_at: i64,
+}
+
+impl PreferencesToken {
+    pub fn new(subscriber_id: Uuid, lifetime: chrono::Duration) -> Self {
+        Self {
+            subscriber_id,
+            expires_at: (Utc::now() + lifetime).timestamp(),
+        }
+    }
+
+    pub fn subscriber_id(&self) -> Uuid {
+        self.subscriber_id
+    }
+
+    pub fn sign(&self, secret: &Secret<String>) -> String {
+        let payload = self.payload();
+        let signature =
+            hex::encode(mac(&payload, secret).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Returns the token if `s` carries a valid signature and has not
+    /// expired yet.
+    pub fn parse(
+        s: &str,
+        secret: &Secret<String>,
+    ) -> Result<PreferencesToken, String> {
+        let invalid = || "The preferences link is not valid.".to_string();
+        let mut parts = s.splitn(3, '.');
+        let (subscriber_id, expires_at, signature) =
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(a), Some(b), Some(c)) => (a, b, c),
+                _ => return Err(invalid()),
+            };
+        let token = Self {
+            subscriber_id: subscriber_id.parse().map_err(|_| invalid())?,
+            expires_at: expires_at.parse().map_err(|_| invalid())?,
+        };
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        mac(&token.payload(), secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        if token.expires_at < Utc::now().timestamp() {
+            return Err("The preferences link has expired.".into());
+        }
+        Ok(token)
+    }
+
+    fn payload(&self) -> String {
+        format!("{}.{}", self.subscriber_id, self.expires_at)
+    }
+}
+
+fn mac(payload: &str, secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::PreferencesToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_signed_token_is_parsed_successfully() {
+        let subscriber_id = Uuid::new_v4();
+        let token =
+            PreferencesToken::new(subscriber_id, chrono::Duration::hours(1));
+        let parsed = PreferencesToken::parse(&token.sign(&secret()), &secret());
+        assert_ok!(&parsed);
+        assert_eq!(parsed.unwrap().subscriber_id(), subscriber_id);
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let token =
+            PreferencesToken::new(Uuid::new_v4(), chrono::Duration::hours(1));
+        let other_secret = Secret::new("another-key".to_string());
+        assert_err!(PreferencesToken::parse(
+            &token.sign(&other_secret),
+            &secret()
+        ));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let token =
+            PreferencesToken::new(Uuid::new_v4(), chrono::Duration::hours(1));
+        let signed = token.sign(&secret());
+        let signature = signed.rsplit('.').next().unwrap();
+        let tampered = format!("{}.{}.{}", Uuid::new_v4(), i64::MAX, signature);
+        assert_err!(PreferencesToken::parse(&tampered, &secret()));
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let token =
+            PreferencesToken::new(Uuid::new_v4(), chrono::Duration::hours(-1));
+        assert_err!(PreferencesToken::parse(&token.sign(&secret()), &secret()));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(PreferencesToken::parse("", &secret()));
+        assert_err!(PreferencesToken::parse("a.b.c", &secret()));
+    }
+}
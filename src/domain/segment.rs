@@ -0,0 +1,643 @@
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+use crate::domain::{is_valid_field_name, is_valid_tag};
+
+// Keep pathological inputs from blowing up the recursive descent parser
+const MAX_LENGTH: usize = 2000;
+const MAX_DEPTH: usize = 32;
+
+const STATUSES: [&str; 3] =
+    ["pending_confirmation", "confirmed", "unsubscribed"];
+
+/// A subset of subscribers, written in a small definition language:
+///
+/// ```text
+/// tag beta and subscribed_at >= 2022-03-01
+/// (field.plan = "pro" or field.seats > 10) and not tag churned
+/// ```
+///
+/// Conditions are `tag <name>`, `status = <status>`,
+/// `subscribed_at <op> <date>` and `field.<name> <op> <value>`, where a value
+/// is a quoted string, a number, `true` or `false`. They can be combined
+/// with `and`, `or`, `not` and parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    source: String,
+    condition: Condition,
+}
+
+/// A value bound to one of the placeholders of a compiled segment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlParameter {
+    Text(String),
+    Float(f64),
+    Timestamp(DateTime<Utc>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Condition {
+    Tag(String),
+    Status(Comparison, String),
+    SubscribedAt(Comparison, DateTime<Utc>),
+    Field(String, Comparison, Literal),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Op(Comparison),
+    Str(String),
+    Word(String),
+}
+
+impl Segment {
+    pub fn parse(s: &str) -> Result<Segment, String> {
+        if s.len() > MAX_LENGTH {
+            return Err(format!(
+                "A segment cannot be longer than {} characters.",
+                MAX_LENGTH
+            ));
+        }
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+            depth: 0,
+        };
+        let condition = parser.expression()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {} in segment.", describe(token)));
+        }
+        Ok(Segment {
+            source: s.trim().to_owned(),
+            condition,
+        })
+    }
+
+    /// Compile the segment to a SQL predicate over the `subscriptions`
+    /// table, aliased as `s`.
+    ///
+    /// Values are never inlined: they are returned in placeholder order,
+    /// numbered from `first_placeholder`, and must be bound by the caller.
+    pub fn to_sql(
+        &self,
+        first_placeholder: usize,
+    ) -> (String, Vec<SqlParameter>) {
+        let mut compiler = Compiler {
+            first_placeholder,
+            parameters: Vec::new(),
+        };
+        let predicate = compiler.condition(&self.condition);
+        (predicate, compiler.parameters)
+    }
+}
+
+impl AsRef<str> for Segment {
+    fn as_ref(&self) -> &str {
+        &self.source
+    }
+}
+
+impl std::fmt::Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl Comparison {
+    fn as_sql(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Ne => "<>",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+
+    fn is_equality(&self) -> bool {
+        matches!(self, Comparison::Eq | Comparison::Ne)
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_equal = chars.next_if_eq(&'=').is_some();
+                let op = match (c, followed_by_equal) {
+                    ('=', false) => Comparison::Eq,
+                    ('!', true) => Comparison::Ne,
+                    ('<', false) => Comparison::Lt,
+                    ('<', true) => Comparison::Le,
+                    ('>', false) => Comparison::Gt,
+                    ('>', true) => Comparison::Ge,
+                    _ => {
+                        return Err(format!(
+                            "Unknown operator {} in segment.",
+                            c
+                        ))
+                    }
+                };
+                tokens.push(Token::Op(op));
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => break,
+                        },
+                        Some(c) => value.push(c),
+                        None => {
+                            return Err("Unterminated string in segment.".into())
+                        }
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = chars
+                    .next_if(|c| !c.is_whitespace() && !"()=!<>\"".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::LParen => "(".into(),
+        Token::RParen => ")".into(),
+        Token::Op(_) => "operator".into(),
+        Token::Str(s) => format!("\"{}\"", s),
+        Token::Word(w) => w.clone(),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "Unexpected end of segment.".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn next_is_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.position += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // expression := conjunction ("or" conjunction)*
+    fn expression(&mut self) -> Result<Condition, String> {
+        let mut condition = self.conjunction()?;
+        while self.next_is_keyword("or") {
+            let right = self.conjunction()?;
+            condition = Condition::Or(Box::new(condition), Box::new(right));
+        }
+        Ok(condition)
+    }
+
+    // conjunction := unary ("and" unary)*
+    fn conjunction(&mut self) -> Result<Condition, String> {
+        let mut condition = self.unary()?;
+        while self.next_is_keyword("and") {
+            let right = self.unary()?;
+            condition = Condition::And(Box::new(condition), Box::new(right));
+        }
+        Ok(condition)
+    }
+
+    // unary := "not" unary | "(" expression ")" | condition
+    fn unary(&mut self) -> Result<Condition, String> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err("The segment is nested too deeply.".into());
+        }
+        let condition = if self.next_is_keyword("not") {
+            Condition::Not(Box::new(self.unary()?))
+        } else if let Some(Token::LParen) = self.peek() {
+            self.position += 1;
+            let condition = self.expression()?;
+            match self.next()? {
+                Token::RParen => condition,
+                token => {
+                    return Err(format!(
+                        "Expected ) but found {} in segment.",
+                        describe(&token)
+                    ))
+                }
+            }
+        } else {
+            self.condition()?
+        };
+        self.depth -= 1;
+        Ok(condition)
+    }
+
+    fn condition(&mut self) -> Result<Condition, String> {
+        let subject = match self.next()? {
+            Token::Word(w) => w,
+            token => {
+                return Err(format!(
+                    "Expected a condition but found {} in segment.",
+                    describe(&token)
+                ))
+            }
+        };
+        match subject.as_str() {
+            "tag" => {
+                let tag = match self.next()? {
+                    Token::Word(t) | Token::Str(t) => t,
+                    token => {
+                        return Err(format!(
+                            "Expected a tag but found {} in segment.",
+                            describe(&token)
+                        ))
+                    }
+                };
+                if !is_valid_tag(&tag) {
+                    return Err(format!("{} is not a valid tag.", tag));
+                }
+                Ok(Condition::Tag(tag))
+            }
+            "status" => {
+                let op = self.comparison()?;
+                if !op.is_equality() {
+                    return Err(
+                        "Statuses can only be compared with = or !=.".into()
+                    );
+                }
+                let status = match self.next()? {
+                    Token::Word(s) | Token::Str(s) => s,
+                    token => {
+                        return Err(format!(
+                            "Expected a status but found {} in segment.",
+                            describe(&token)
+                        ))
+                    }
+                };
+                if !STATUSES.contains(&status.as_str()) {
+                    return Err(format!(
+                        "{} is not a valid status, expected one of {}.",
+                        status,
+                        STATUSES.join(", ")
+                    ));
+                }
+                Ok(Condition::Status(op, status))
+            }
+            "subscribed_at" => {
+                let op = self.comparison()?;
+                let at = match self.next()? {
+                    Token::Word(s) | Token::Str(s) => parse_timestamp(&s)?,
+                    token => {
+                        return Err(format!(
+                            "Expected a date but found {} in segment.",
+                            describe(&token)
+                        ))
+                    }
+                };
+                Ok(Condition::SubscribedAt(op, at))
+            }
+            field if field.starts_with("field.") => {
+                let name = &field["field.".len()..];
+                if !is_valid_field_name(name) {
+                    return Err(format!("{} is not a valid field name.", name));
+                }
+                let op = self.comparison()?;
+                let value = self.literal()?;
+                if let Literal::Bool(_) = value {
+                    if !op.is_equality() {
+                        return Err("Booleans can only be compared with \
+                            = or !=."
+                            .into());
+                    }
+                }
+                Ok(Condition::Field(name.to_owned(), op, value))
+            }
+            _ => Err(format!(
+                "Unknown condition {}, expected tag, status, \
+                subscribed_at or field.<name>.",
+                subject
+            )),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, String> {
+        match self.next()? {
+            Token::Op(op) => Ok(op),
+            token => Err(format!(
+                "Expected a comparison but found {} in segment.",
+                describe(&token)
+            )),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal, String> {
+        match self.next()? {
+            Token::Str(s) => Ok(Literal::Text(s)),
+            Token::Word(w) if w == "true" => Ok(Literal::Bool(true)),
+            Token::Word(w) if w == "false" => Ok(Literal::Bool(false)),
+            Token::Word(w) => match w.parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(Literal::Number(n)),
+                _ => Err(format!(
+                    "{} is not a valid value, \
+                    strings must be between double quotes.",
+                    w
+                )),
+            },
+            token => Err(format!(
+                "Expected a value but found {} in segment.",
+                describe(&token)
+            )),
+        }
+    }
+}
+
+// Accept both plain dates, at midnight UTC, and RFC 3339 timestamps.
+fn parse_timestamp(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).expect("Midnight is valid");
+        return Ok(Utc.from_utc_datetime(&midnight));
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|_| {
+            format!(
+                "{} is not a valid date, expected YYYY-MM-DD \
+                or an RFC 3339 timestamp.",
+                s
+            )
+        })
+}
+
+struct Compiler {
+    first_placeholder: usize,
+    parameters: Vec<SqlParameter>,
+}
+
+impl Compiler {
+    fn bind(&mut self, parameter: SqlParameter) -> String {
+        self.parameters.push(parameter);
+        format!("${}", self.first_placeholder + self.parameters.len() - 1)
+    }
+
+    fn condition(&mut self, condition: &Condition) -> String {
+        match condition {
+            Condition::Tag(tag) => {
+                format!(
+                    "{} = ANY(s.tags)",
+                    self.bind(SqlParameter::Text(tag.clone()))
+                )
+            }
+            Condition::Status(op, status) => format!(
+                "s.status {} {}",
+                op.as_sql(),
+                self.bind(SqlParameter::Text(status.clone()))
+            ),
+            Condition::SubscribedAt(op, at) => format!(
+                "s.subscribed_at {} {}",
+                op.as_sql(),
+                self.bind(SqlParameter::Timestamp(*at))
+            ),
+            Condition::Field(name, op, value) => {
+                let name = self.bind(SqlParameter::Text(name.clone()));
+                self.field(&name, *op, value)
+            }
+            Condition::Not(c) => format!("NOT ({})", self.condition(c)),
+            Condition::And(l, r) => {
+                format!("({}) AND ({})", self.condition(l), self.condition(r))
+            }
+            Condition::Or(l, r) => {
+                format!("({}) OR ({})", self.condition(l), self.condition(r))
+            }
+        }
+    }
+
+    // Subscribers without the field, or with a value of another type,
+    // never match an ordering comparison and always match `!=`.
+    fn field(&mut self, name: &str, op: Comparison, value: &Literal) -> String {
+        if op.is_equality() {
+            let json = match value {
+                Literal::Text(s) => serde_json::Value::from(s.as_str()),
+                Literal::Number(n) => serde_json::Value::from(*n),
+                Literal::Bool(b) => serde_json::Value::from(*b),
+            };
+            let json = self.bind(SqlParameter::Text(json.to_string()));
+            let is = match op {
+                Comparison::Eq => "IS NOT DISTINCT FROM",
+                _ => "IS DISTINCT FROM",
+            };
+            return format!(
+                "(s.custom_fields -> {}) {} {}::jsonb",
+                name, is, json
+            );
+        }
+        let (json_type, cast, value) = match value {
+            Literal::Text(s) => ("string", "", SqlParameter::Text(s.clone())),
+            Literal::Number(n) => {
+                ("number", "::float8", SqlParameter::Float(*n))
+            }
+            // Rejected by the parser
+            Literal::Bool(_) => return "false".into(),
+        };
+        format!(
+            "CASE WHEN jsonb_typeof(s.custom_fields -> {name}) = '{json_type}' \
+            THEN (s.custom_fields ->> {name}){cast} {op} {value} \
+            ELSE false END",
+            name = name,
+            json_type = json_type,
+            cast = cast,
+            op = op.as_sql(),
+            value = self.bind(value),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Segment, SqlParameter};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn a_tag_condition_is_compiled_with_a_placeholder() {
+        let segment = Segment::parse("tag beta").unwrap();
+        let (sql, parameters) = segment.to_sql(3);
+        assert_eq!(sql, "$3 = ANY(s.tags)");
+        assert_eq!(parameters, vec![SqlParameter::Text("beta".into())]);
+    }
+
+    #[test]
+    fn dates_are_parsed_as_midnight_utc() {
+        let segment = Segment::parse("subscribed_at >= 2022-03-01").unwrap();
+        let (sql, parameters) = segment.to_sql(1);
+        assert_eq!(sql, "s.subscribed_at >= $1");
+        assert_eq!(
+            parameters,
+            vec![SqlParameter::Timestamp(
+                "2022-03-01T00:00:00Z".parse().unwrap()
+            )]
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let segment =
+            Segment::parse("tag a or tag b and status = confirmed").unwrap();
+        let (sql, _) = segment.to_sql(1);
+        assert_eq!(
+            sql,
+            "($1 = ANY(s.tags)) OR (($2 = ANY(s.tags)) AND (s.status = $3))"
+        );
+    }
+
+    #[test]
+    fn parentheses_and_not_are_supported() {
+        let segment = Segment::parse("not (tag a or tag b) and tag c").unwrap();
+        let (sql, _) = segment.to_sql(1);
+        assert_eq!(
+            sql,
+            "(NOT (($1 = ANY(s.tags)) OR ($2 = ANY(s.tags)))) \
+            AND ($3 = ANY(s.tags))"
+        );
+    }
+
+    #[test]
+    fn field_equality_compares_json_values() {
+        let segment = Segment::parse(r#"field.plan = "pro""#).unwrap();
+        let (sql, parameters) = segment.to_sql(1);
+        assert_eq!(
+            sql,
+            "(s.custom_fields -> $1) IS NOT DISTINCT FROM $2::jsonb"
+        );
+        assert_eq!(
+            parameters,
+            vec![
+                SqlParameter::Text("plan".into()),
+                SqlParameter::Text(r#""pro""#.into())
+            ]
+        );
+    }
+
+    #[test]
+    fn numeric_field_comparisons_check_the_value_type() {
+        let segment = Segment::parse("field.seats > 10").unwrap();
+        let (sql, parameters) = segment.to_sql(1);
+        assert_eq!(
+            sql,
+            "CASE WHEN jsonb_typeof(s.custom_fields -> $1) = 'number' \
+            THEN (s.custom_fields ->> $1)::float8 > $2 ELSE false END"
+        );
+        assert_eq!(
+            parameters,
+            vec![
+                SqlParameter::Text("seats".into()),
+                SqlParameter::Float(10.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn values_are_never_inlined() {
+        let segment = Segment::parse(
+            r#"field.name = "x'; DROP TABLE subscriptions; --""#,
+        )
+        .unwrap();
+        let (sql, _) = segment.to_sql(1);
+        assert!(!sql.contains("DROP"));
+    }
+
+    #[test]
+    fn keywords_are_case_insensitive() {
+        assert_ok!(Segment::parse("tag a AND NOT tag b OR tag c"));
+    }
+
+    #[test]
+    fn invalid_segments_are_rejected() {
+        for segment in [
+            "",
+            "tag",
+            "tag a and",
+            "(tag a",
+            "tag a)",
+            "colour = red",
+            "status = gone",
+            "status > confirmed",
+            "subscribed_at > yesterday",
+            "field.plan = pro",
+            "field.beta > true",
+            "field.bad-name = 1",
+            r#"tag "has space""#,
+            r#"field.plan = "unterminated"#,
+        ] {
+            assert_err!(Segment::parse(segment), "{} was accepted", segment);
+        }
+    }
+
+    #[test]
+    fn deeply_nested_segments_are_rejected() {
+        let segment = format!("{}tag a{}", "(".repeat(100), ")".repeat(100));
+        assert_err!(Segment::parse(&segment));
+    }
+}
@@ -0,0 +1,149 @@
+use serde_json::{Map, Value};
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Tags are short lowercase labels: letters, digits, `-` and `_`.
+pub fn is_valid_tag(s: &str) -> bool {
+    !s.is_empty()
+        && s.len() <= MAX_NAME_LENGTH
+        && s.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_'
+        })
+}
+
+/// Custom field names must be usable as identifiers, in segments and in
+/// templates alike.
+pub fn is_valid_field_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic())
+        && s.len() <= MAX_NAME_LENGTH
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SubscriberTags(Vec<String>);
+
+impl SubscriberTags {
+    /// Parse a comma-separated list of tags.
+    /// Tags are lowercased and duplicates are dropped.
+    pub fn parse(s: &str) -> Result<SubscriberTags, String> {
+        let mut tags = Vec::new();
+        for tag in s.split(',').map(|t| t.trim().to_lowercase()) {
+            if tag.is_empty() {
+                continue;
+            }
+            if !is_valid_tag(&tag) {
+                return Err(format!(
+                    "{} is not a valid tag: use letters, digits, - and _.",
+                    tag
+                ));
+            }
+            tags.push(tag);
+        }
+        tags.sort();
+        tags.dedup();
+        Ok(Self(tags))
+    }
+}
+
+impl AsRef<[String]> for SubscriberTags {
+    fn as_ref(&self) -> &[String] {
+        &self.0
+    }
+}
+
+/// Arbitrary attributes of a subscriber, stored as a flat JSON object.
+#[derive(Debug, PartialEq)]
+pub struct CustomFields(Map<String, Value>);
+
+impl CustomFields {
+    /// Parse a JSON object whose values are strings, numbers or booleans.
+    /// An empty input means no custom fields.
+    pub fn parse(s: &str) -> Result<CustomFields, String> {
+        if s.trim().is_empty() {
+            return Ok(Self(Map::new()));
+        }
+        let fields = match serde_json::from_str(s) {
+            Ok(Value::Object(fields)) => fields,
+            _ => return Err("Custom fields must be a JSON object.".into()),
+        };
+        for (name, value) in &fields {
+            if !is_valid_field_name(name) {
+                return Err(format!(
+                    "{} is not a valid field name: start with a letter, \
+                    then use letters, digits and _.",
+                    name
+                ));
+            }
+            if !(value.is_string() || value.is_number() || value.is_boolean()) {
+                return Err(format!(
+                    "The value of {} must be a string, a number or a boolean.",
+                    name
+                ));
+            }
+        }
+        Ok(Self(fields))
+    }
+
+    pub fn into_inner(self) -> Value {
+        Value::Object(self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CustomFields, SubscriberTags};
+    use claim::{assert_err, assert_ok};
+
+    #[test]
+    fn tags_are_normalised() {
+        let tags = SubscriberTags::parse(" Beta, vip ,,beta").unwrap();
+        assert_eq!(tags.as_ref(), ["beta", "vip"]);
+    }
+
+    #[test]
+    fn an_empty_string_has_no_tags() {
+        assert!(SubscriberTags::parse("").unwrap().as_ref().is_empty());
+    }
+
+    #[test]
+    fn tags_with_spaces_or_symbols_are_rejected() {
+        assert_err!(SubscriberTags::parse("early adopter"));
+        assert_err!(SubscriberTags::parse("<b>"));
+        assert_err!(SubscriberTags::parse(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn a_flat_json_object_is_valid() {
+        assert_ok!(CustomFields::parse(
+            r#"{"plan": "pro", "seats": 12, "beta": true}"#
+        ));
+    }
+
+    #[test]
+    fn empty_custom_fields_are_valid() {
+        assert_eq!(
+            CustomFields::parse(" ").unwrap().into_inner(),
+            serde_json::json!({})
+        );
+    }
+
+    #[test]
+    fn non_objects_are_rejected() {
+        assert_err!(CustomFields::parse("[1, 2]"));
+        assert_err!(CustomFields::parse("plan=pro"));
+    }
+
+    #[test]
+    fn nested_values_are_rejected() {
+        assert_err!(CustomFields::parse(r#"{"address": {"city": "Rome"}}"#));
+        assert_err!(CustomFields::parse(r#"{"tags": ["a"]}"#));
+        assert_err!(CustomFields::parse(r#"{"nothing": null}"#));
+    }
+
+    #[test]
+    fn field_names_must_be_identifiers() {
+        assert_err!(CustomFields::parse(r#"{"1st": 1}"#));
+        assert_err!(CustomFields::parse(r#"{"first name": "a"}"#));
+    }
+}
@@ -12,6 +12,13 @@ impl SubscriberEmail {
             Err(format!("{} is not a valid subscriber email address.", s))
         }
     }
+
+    /// The part after the `@`, in lowercase.
+    pub fn domain(&self) -> String {
+        // A valid address always has an `@`
+        let (_, domain) = self.0.rsplit_once('@').unwrap_or_default();
+        domain.to_lowercase()
+    }
 }
 
 impl std::fmt::Display for SubscriberEmail {
@@ -54,6 +61,12 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn the_domain_is_lowercased() {
+        let email = SubscriberEmail::parse("ursula@GMail.com".into()).unwrap();
+        assert_eq!(email.domain(), "gmail.com");
+    }
+
     // // We have removed the `assert_ok` import.
 
     // Both `Clone` and `Debug` are required by `quickcheck`
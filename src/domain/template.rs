@@ -0,0 +1,276 @@
+use serde_json::Value;
+
+use crate::domain::is_valid_field_name;
+
+/// Newsletter content with merge tags, personalised for each recipient.
+///
+/// A merge tag is written `{{ variable }}` or
+/// `{{ variable | default: "fallback" }}`, the fallback being used when the
+/// recipient has no value for it. The variables are `subscriber.name`,
+/// `subscriber.email`, `unsubscribe_url` and `subscriber.fields.<name>` for
+/// custom fields.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+/// What a recipient brings to the rendering of a template.
+pub struct Recipient<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub custom_fields: &'a Value,
+}
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    Tag {
+        variable: Variable,
+        default: Option<String>,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum Variable {
+    SubscriberName,
+    SubscriberEmail,
+    UnsubscribeUrl,
+    CustomField(String),
+}
+
+impl Template {
+    pub fn parse(s: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let end = rest[start..].find("}}").ok_or_else(|| {
+                let unclosed: String = rest[start..].chars().take(40).collect();
+                format!("The merge tag {} is not closed.", unclosed)
+            })?;
+            let tag = &rest[start..start + end + 2];
+            parts
+                .push(parse_tag(&tag[2..tag.len() - 2]).map_err(|e| {
+                    format!("Invalid merge tag {}: {}", tag, e)
+                })?);
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Template { parts })
+    }
+
+    /// Fill in the merge tags.
+    /// Values are HTML-escaped when rendering the HTML part of an issue.
+    pub fn render(&self, recipient: &Recipient, escape_html: bool) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Tag { variable, default } => {
+                    let value = variable
+                        .value(recipient)
+                        .filter(|v| !v.is_empty())
+                        .or_else(|| default.clone())
+                        .unwrap_or_default();
+                    if escape_html {
+                        rendered.push_str(&htmlescape::encode_minimal(&value));
+                    } else {
+                        rendered.push_str(&value);
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl Variable {
+    fn value(&self, recipient: &Recipient) -> Option<String> {
+        match self {
+            Variable::SubscriberName => Some(recipient.name.to_owned()),
+            Variable::SubscriberEmail => Some(recipient.email.to_owned()),
+            Variable::UnsubscribeUrl => {
+                Some(recipient.unsubscribe_url.to_owned())
+            }
+            Variable::CustomField(name) => {
+                match recipient.custom_fields.get(name)? {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Number(n) => Some(n.to_string()),
+                    Value::Bool(b) => Some(b.to_string()),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+fn parse_tag(tag: &str) -> Result<Part, String> {
+    let (variable, filter) = match tag.split_once('|') {
+        Some((variable, filter)) => (variable.trim(), Some(filter.trim())),
+        None => (tag.trim(), None),
+    };
+    let variable = match variable {
+        "subscriber.name" => Variable::SubscriberName,
+        "subscriber.email" => Variable::SubscriberEmail,
+        "unsubscribe_url" => Variable::UnsubscribeUrl,
+        v => match v.strip_prefix("subscriber.fields.") {
+            Some(name) if is_valid_field_name(name) => {
+                Variable::CustomField(name.to_owned())
+            }
+            _ => {
+                return Err(format!(
+                    "unknown variable {}, expected subscriber.name, \
+                    subscriber.email, unsubscribe_url \
+                    or subscriber.fields.<name>.",
+                    v
+                ))
+            }
+        },
+    };
+    let default = match filter {
+        None => None,
+        Some(filter) => {
+            let literal = filter
+                .strip_prefix("default:")
+                .ok_or("the only supported filter is default.")?;
+            Some(parse_string_literal(literal.trim())?)
+        }
+    };
+    Ok(Part::Tag { variable, default })
+}
+
+fn parse_string_literal(s: &str) -> Result<String, String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or("default values must be between double quotes.")?;
+    let mut value = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(escaped) => value.push(escaped),
+                None => return Err("unterminated escape sequence.".into()),
+            },
+            '"' => {
+                return Err("unescaped double quote in default value.".into())
+            }
+            c => value.push(c),
+        }
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Recipient, Template};
+    use claim::{assert_err, assert_ok};
+    use serde_json::json;
+
+    fn render(template: &str, escape_html: bool) -> String {
+        let custom_fields = json!({"plan": "pro", "seats": 12, "beta": true});
+        let recipient = Recipient {
+            name: "Ursula <Le Guin>",
+            email: "ursula@example.com",
+            unsubscribe_url: "https://example.com/preferences?token=a&b",
+            custom_fields: &custom_fields,
+        };
+        Template::parse(template)
+            .unwrap()
+            .render(&recipient, escape_html)
+    }
+
+    #[test]
+    fn text_without_merge_tags_is_left_untouched() {
+        assert_eq!(
+            render("Hello, world! {not a tag}", false),
+            "Hello, world! {not a tag}"
+        );
+    }
+
+    #[test]
+    fn subscriber_details_are_filled_in() {
+        assert_eq!(
+            render("Hi {{ subscriber.name }} ({{subscriber.email}})", false),
+            "Hi Ursula <Le Guin> (ursula@example.com)"
+        );
+    }
+
+    #[test]
+    fn values_are_html_escaped_in_html() {
+        assert_eq!(
+            render(
+                r#"<p>Hi {{ subscriber.name }}</p><a href="{{ unsubscribe_url }}">"#,
+                true
+            ),
+            r#"<p>Hi Ursula &lt;Le Guin&gt;</p><a href="https://example.com/preferences?token=a&amp;b">"#
+        );
+    }
+
+    #[test]
+    fn custom_fields_are_rendered() {
+        assert_eq!(
+            render(
+                "{{ subscriber.fields.plan }} {{ subscriber.fields.seats }} \
+                {{ subscriber.fields.beta }}",
+                false
+            ),
+            "pro 12 true"
+        );
+    }
+
+    #[test]
+    fn defaults_are_used_for_missing_values() {
+        assert_eq!(
+            render(
+                r#"{{ subscriber.fields.company | default: "your \"team\"" }}"#,
+                false
+            ),
+            r#"your "team""#
+        );
+    }
+
+    #[test]
+    fn defaults_are_ignored_when_there_is_a_value() {
+        assert_eq!(
+            render(r#"{{ subscriber.fields.plan | default: "free" }}"#, false),
+            "pro"
+        );
+    }
+
+    #[test]
+    fn missing_values_without_defaults_are_left_empty() {
+        assert_eq!(render("[{{ subscriber.fields.company }}]", false), "[]");
+    }
+
+    #[test]
+    fn unknown_variables_are_rejected() {
+        assert_err!(Template::parse("Hi {{ subscriber.nmae }}"));
+        assert_err!(Template::parse("Hi {{ name }}"));
+        assert_err!(Template::parse("Hi {{ subscriber.fields.bad-name }}"));
+    }
+
+    #[test]
+    fn invalid_tags_are_rejected() {
+        assert_err!(Template::parse("Hi {{ subscriber.name"));
+        assert_err!(Template::parse("Hi {{ subscriber.name | upper }}"));
+        assert_err!(Template::parse(
+            "Hi {{ subscriber.name | default: friend }}"
+        ));
+        assert_err!(Template::parse(
+            r#"Hi {{ subscriber.name | default: "a"b" }}"#
+        ));
+    }
+
+    #[test]
+    fn tags_can_be_anywhere() {
+        assert_ok!(Template::parse("{{ subscriber.name }}"));
+        assert_ok!(Template::parse("{{subscriber.name}}{{subscriber.email}}"));
+        assert_ok!(Template::parse(""));
+    }
+}
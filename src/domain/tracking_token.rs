@@ -0,0 +1,138 @@
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use uuid::Uuid;
+
+/// A signed token identifying the delivery of an issue to a subscriber,
+/// carried by the tracking pixel and the rewritten links of the issue.
+///
+/// The string form is `{issue_id}.{subscriber_id}.{link}.{signature}`,
+/// where `link` is the URL-safe base64 encoding of the destination of a
+/// link, empty for the tracking pixel. Tracking tokens do not expire:
+/// links in old issues must keep working.
+#[derive(Debug, PartialEq)]
+pub struct TrackingToken {
+    pub newsletter_issue_id: Uuid,
+    pub subscriber_id: Uuid,
+    pub link: Option<String>,
+}
+
+impl TrackingToken {
+    pub fn sign(&self, secret: &Secret<String>) -> String {
+        let payload = self.payload();
+        let signature =
+            hex::encode(mac(&payload, secret).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Returns the token if `s` carries a valid signature.
+    pub fn parse(
+        s: &str,
+        secret: &Secret<String>,
+    ) -> Result<TrackingToken, String> {
+        let invalid = || "The tracking token is not valid.".to_string();
+        let mut parts = s.splitn(4, '.');
+        let (issue_id, subscriber_id, link, signature) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+                _ => return Err(invalid()),
+            };
+        let link = match link {
+            "" => None,
+            link => Some(
+                base64::decode_config(link, base64::URL_SAFE_NO_PAD)
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                    .ok_or_else(invalid)?,
+            ),
+        };
+        let token = Self {
+            newsletter_issue_id: issue_id.parse().map_err(|_| invalid())?,
+            subscriber_id: subscriber_id.parse().map_err(|_| invalid())?,
+            link,
+        };
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        mac(&token.payload(), secret)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        Ok(token)
+    }
+
+    fn payload(&self) -> String {
+        let link = match &self.link {
+            Some(link) => base64::encode_config(link, base64::URL_SAFE_NO_PAD),
+            None => String::new(),
+        };
+        format!(
+            "{}.{}.{}",
+            self.newsletter_issue_id, self.subscriber_id, link
+        )
+    }
+}
+
+fn mac(payload: &str, secret: &Secret<String>) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+            .expect("HMAC can take a key of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok_eq};
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::TrackingToken;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    fn token(link: Option<&str>) -> TrackingToken {
+        TrackingToken {
+            newsletter_issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            link: link.map(String::from),
+        }
+    }
+
+    #[test]
+    fn signed_tokens_are_parsed_successfully() {
+        for token in [token(None), token(Some("https://example.com/?a=1&b"))] {
+            let signed = token.sign(&secret());
+            assert_ok_eq!(TrackingToken::parse(&signed, &secret()), token);
+        }
+    }
+
+    #[test]
+    fn a_token_signed_with_another_secret_is_rejected() {
+        let other_secret = Secret::new("another-key".to_string());
+        let signed = token(None).sign(&other_secret);
+        assert_err!(TrackingToken::parse(&signed, &secret()));
+    }
+
+    #[test]
+    fn a_token_with_a_tampered_link_is_rejected() {
+        let signed = token(Some("https://example.com")).sign(&secret());
+        let parts: Vec<&str> = signed.split('.').collect();
+        let tampered = format!(
+            "{}.{}.{}.{}",
+            parts[0],
+            parts[1],
+            base64::encode_config(
+                "https://evil.example.com",
+                base64::URL_SAFE_NO_PAD
+            ),
+            parts[3]
+        );
+        assert_err!(TrackingToken::parse(&tampered, &secret()));
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert_err!(TrackingToken::parse("", &secret()));
+        assert_err!(TrackingToken::parse("a.b.c.d", &secret()));
+    }
+}
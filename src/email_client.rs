@@ -1,9 +1,36 @@
-use reqwest::Client;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::{header, Client, StatusCode};
 use secrecy::{ExposeSecret, Secret};
 use serde::{Deserialize, Serialize};
 
 use crate::domain::SubscriberEmail;
 
+#[derive(thiserror::Error, Debug)]
+pub enum SendEmailError {
+    #[error("The email provider is rate limiting our sends")]
+    RateLimited {
+        // How long the provider asked us to wait, if it did
+        retry_after: Option<Duration>,
+    },
+    #[error(transparent)]
+    Request(#[from] reqwest::Error),
+}
+
+impl SendEmailError {
+    /// Whether the provider itself failed, e.g. it timed out or answered
+    /// with a 5xx, rather than refused this email or asked us to slow down.
+    pub fn is_provider_failure(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } => false,
+            Self::Request(e) => {
+                e.status().map_or(true, |status| status.is_server_error())
+            }
+        }
+    }
+}
+
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
@@ -34,7 +61,7 @@ impl EmailClient {
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<(), SendEmailError> {
         // You can do better using `reqwest::Url::join` if you change
         // `base_url`'s type from `String` to `reqwest::Url`.
         // I'll leave it as an exercise for the reader!
@@ -47,7 +74,8 @@ impl EmailClient {
             text_body: text_content,
         };
 
-        self.http_client
+        let response = self
+            .http_client
             .post(&url)
             .header(
                 "X-Postmark-Server-Token",
@@ -55,12 +83,35 @@ impl EmailClient {
             )
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(SendEmailError::RateLimited {
+                retry_after: response
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|h| h.to_str().ok())
+                    .and_then(parse_retry_after),
+            });
+        }
+        response.error_for_status()?;
         Ok(())
     }
 }
 
+// `Retry-After` is either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(seconds) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // A date in the past means we can send right away
+    Some(
+        (date.with_timezone(&Utc) - Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "PascalCase")]
 struct SendEmailRequest<'a> {
@@ -85,7 +136,7 @@ mod tests {
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, SendEmailError};
 
     struct SendEmailBodyMatcher;
 
@@ -223,4 +274,32 @@ mod tests {
         // Assert
         assert_err!(result);
     }
+
+    #[tokio::test]
+    async fn send_email_reports_when_the_server_rate_limits_us() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(
+                ResponseTemplate::new(429).insert_header("Retry-After", "7"),
+            )
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        match result {
+            Err(SendEmailError::RateLimited { retry_after }) => {
+                assert_eq!(retry_after, Some(std::time::Duration::from_secs(7)))
+            }
+            other => panic!("Expected to be rate limited, got {:?}", other),
+        }
+    }
 }
@@ -0,0 +1,273 @@
+use std::collections::BTreeSet;
+
+use anyhow::Context;
+use lol_html::html_content::ContentType;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use secrecy::Secret;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::TrackingToken;
+
+pub enum EmailEvent {
+    Open,
+    Click,
+}
+
+impl EmailEvent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EmailEvent::Open => "open",
+            EmailEvent::Click => "click",
+        }
+    }
+}
+
+/// The destinations of the links of an issue that can be tracked:
+/// absolute `http(s)` URLs without merge tags, so that the links to the
+/// preferences page of each subscriber are left alone.
+pub fn tracked_links(html: &str) -> Vec<String> {
+    let mut links = BTreeSet::new();
+    let result = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("a[href]", |el| {
+                if let Some(link) = el.get_attribute("href").and_then(decode) {
+                    links.insert(link);
+                }
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    );
+    if let Err(e) = result {
+        tracing::warn!(
+            error.message = %e,
+            "Failed to look for links in the HTML content"
+        );
+    }
+    links.into_iter().collect()
+}
+
+// The URL of a link, if it can be tracked
+fn decode(href: String) -> Option<String> {
+    let url = htmlescape::decode_html(href.trim()).ok()?;
+    let lowercase = url.to_lowercase();
+    let is_absolute =
+        lowercase.starts_with("https://") || lowercase.starts_with("http://");
+    if is_absolute && !url.contains("{{") {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Point the links of an issue sent to a subscriber to the click
+/// redirect, and add a tracking pixel at the end of the body.
+/// Only the links in `links`, stored when the issue was published, are
+/// rewritten.
+pub fn add_tracking(
+    html: &str,
+    links: &[String],
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    newsletter_issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<String, String> {
+    let url = |action: &str, link: Option<String>| {
+        let token = TrackingToken {
+            newsletter_issue_id,
+            subscriber_id,
+            link,
+        };
+        format!("{}/t/{}/{}", base_url, action, token.sign(hmac_secret))
+    };
+    let pixel = format!(
+        r#"<img src="{}" width="1" height="1" alt="" style="display: block; border: 0">"#,
+        url("o", None)
+    );
+    let mut has_body = false;
+    let tracked = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![
+                element!("a[href]", |el| {
+                    let link = el.get_attribute("href").and_then(decode);
+                    if let Some(link) = link.filter(|l| links.contains(l)) {
+                        el.set_attribute("href", &url("c", Some(link)))?;
+                    }
+                    Ok(())
+                }),
+                element!("body", |el| {
+                    has_body = true;
+                    el.append(&pixel, ContentType::Html);
+                    Ok(())
+                }),
+            ],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    if has_body {
+        Ok(tracked)
+    } else {
+        Ok(format!("{}{}", tracked, pixel))
+    }
+}
+
+#[tracing::instrument(skip(transaction, links))]
+pub async fn store_links(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    links: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_links (newsletter_issue_id, url)
+        SELECT $1, url FROM UNNEST($2::text[]) AS url
+        "#,
+        newsletter_issue_id,
+        links
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_links(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<String>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT url FROM newsletter_issue_links
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.url).collect())
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn is_issue_link(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    url: &str,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM newsletter_issue_links
+            WHERE newsletter_issue_id = $1 AND url = $2
+        ) AS "exists!"
+        "#,
+        newsletter_issue_id,
+        url
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.exists)
+}
+
+#[tracing::instrument(skip_all, fields(kind = event.as_str()))]
+pub async fn record_event(
+    pool: &PgPool,
+    event: EmailEvent,
+    token: &TrackingToken,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_events (
+            event_id, newsletter_issue_id, subscriber_id, kind, url
+        )
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+        Uuid::new_v4(),
+        token.newsletter_issue_id,
+        token.subscriber_id,
+        event.as_str(),
+        token.link
+    )
+    .execute(pool)
+    .await
+    .context("Failed to record an email event")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    use super::{add_tracking, tracked_links};
+    use crate::domain::TrackingToken;
+
+    #[test]
+    fn only_absolute_links_without_merge_tags_are_tracked() {
+        let html = r#"<a href="https://example.com/?a=1&amp;b=2">A</a>
+            <a href="http://example.com">B</a><a href="mailto:a@example.com">C</a>
+            <a href="{{ unsubscribe_url }}">D</a><a href="/relative">E</a>
+            <a href="https://example.com/?a=1&b=2">A again</a>"#;
+        assert_eq!(
+            tracked_links(html),
+            vec!["http://example.com", "https://example.com/?a=1&b=2"]
+        );
+    }
+
+    #[test]
+    fn links_are_rewritten_and_a_pixel_is_added() {
+        let secret = Secret::new("a-very-secret-key".to_string());
+        let (issue_id, subscriber_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let html = r#"<body><a href="https://example.com/?a=1&amp;b=2">A</a><a href="https://example.com/preferences">B</a></body>"#;
+        let links = vec!["https://example.com/?a=1&b=2".to_string()];
+
+        let tracked = add_tracking(
+            html,
+            &links,
+            "http://localhost",
+            &secret,
+            issue_id,
+            subscriber_id,
+        )
+        .unwrap();
+
+        let click_token = TrackingToken {
+            newsletter_issue_id: issue_id,
+            subscriber_id,
+            link: Some(links[0].clone()),
+        };
+        let open_token = TrackingToken {
+            newsletter_issue_id: issue_id,
+            subscriber_id,
+            link: None,
+        };
+        assert_eq!(
+            tracked,
+            format!(
+                r#"<body><a href="http://localhost/t/c/{}">A</a><a href="https://example.com/preferences">B</a><img src="http://localhost/t/o/{}" width="1" height="1" alt="" style="display: block; border: 0"></body>"#,
+                click_token.sign(&secret),
+                open_token.sign(&secret)
+            )
+        );
+    }
+
+    #[test]
+    fn the_pixel_is_appended_to_fragments() {
+        let secret = Secret::new("a-very-secret-key".to_string());
+        let tracked = add_tracking(
+            "<p>Hi</p>",
+            &[],
+            "http://localhost",
+            &secret,
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        )
+        .unwrap();
+        assert!(
+            tracked.starts_with(r#"<p>Hi</p><img src="http://localhost/t/o/"#)
+        );
+    }
+}
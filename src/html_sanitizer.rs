@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+
+// The content of these elements is dropped along with them
+const CLEAN_CONTENT_TAGS: [&str; 2] = ["script", "style"];
+const URL_ATTRIBUTES: [&str; 2] = ["href", "src"];
+
+/// Strip newsletter content down to an allowlist of tags, attributes and
+/// URL schemes, so that scripts, forms or iframes never reach subscribers.
+pub struct HtmlSanitizer {
+    tags: HashSet<String>,
+    attributes: HashSet<String>,
+    url_schemes: HashSet<String>,
+    upgrade_insecure_urls: bool,
+}
+
+/// What sanitizing some HTML changed, for the editor to review.
+#[derive(Debug, Default, PartialEq)]
+pub struct SanitizationReport {
+    pub removed_tags: BTreeMap<String, usize>,
+    pub removed_attributes: BTreeMap<String, usize>,
+    // Links and images whose URL scheme is not allowed, like `javascript:`
+    pub removed_urls: usize,
+    // `http` URLs rewritten to `https`
+    pub upgraded_urls: usize,
+}
+
+impl HtmlSanitizer {
+    pub fn new(
+        tags: Vec<String>,
+        attributes: Vec<String>,
+        url_schemes: Vec<String>,
+        upgrade_insecure_urls: bool,
+    ) -> Self {
+        let lowercase = |v: Vec<String>| -> HashSet<String> {
+            v.into_iter().map(|s| s.to_lowercase()).collect()
+        };
+        Self {
+            tags: lowercase(tags),
+            attributes: lowercase(attributes),
+            url_schemes: lowercase(url_schemes),
+            upgrade_insecure_urls,
+        }
+    }
+
+    pub fn sanitize(&self, html: &str) -> (String, SanitizationReport) {
+        let report = self.review(html);
+        let clean_content_tags = CLEAN_CONTENT_TAGS
+            .into_iter()
+            .filter(|t| !self.tags.contains(*t))
+            .collect();
+        let upgrade_insecure_urls = self.upgrade_insecure_urls;
+        let sanitized = ammonia::Builder::default()
+            .tags(self.tags.iter().map(String::as_str).collect())
+            .clean_content_tags(clean_content_tags)
+            .generic_attributes(
+                self.attributes.iter().map(String::as_str).collect(),
+            )
+            .tag_attributes(HashMap::new())
+            .url_schemes(self.url_schemes.iter().map(String::as_str).collect())
+            .link_rel(None)
+            .attribute_filter(move |_, attribute, value| {
+                if upgrade_insecure_urls && URL_ATTRIBUTES.contains(&attribute)
+                {
+                    if let Some(rest) = strip_http(value) {
+                        return Some(Cow::Owned(format!("https://{}", rest)));
+                    }
+                }
+                Some(Cow::Borrowed(value))
+            })
+            .clean(html)
+            .to_string();
+        (sanitized, report)
+    }
+
+    // Find what `sanitize` is going to remove or rewrite
+    fn review(&self, html: &str) -> SanitizationReport {
+        let mut report = SanitizationReport::default();
+        let result = rewrite_str(
+            html,
+            RewriteStrSettings {
+                element_content_handlers: vec![element!("*", |el| {
+                    let tag = el.tag_name();
+                    if !self.tags.contains(&tag) {
+                        *report.removed_tags.entry(tag).or_default() += 1;
+                        return Ok(());
+                    }
+                    for attribute in el.attributes() {
+                        let name = attribute.name();
+                        if !self.attributes.contains(&name) {
+                            *report
+                                .removed_attributes
+                                .entry(name)
+                                .or_default() += 1;
+                        } else if URL_ATTRIBUTES.contains(&name.as_str()) {
+                            let value = attribute.value();
+                            match url_scheme(&value) {
+                                Some(s) if !self.url_schemes.contains(&s) => {
+                                    report.removed_urls += 1;
+                                }
+                                _ if self.upgrade_insecure_urls
+                                    && strip_http(&value).is_some() =>
+                                {
+                                    report.upgraded_urls += 1;
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            },
+        );
+        if let Err(e) = result {
+            tracing::warn!(
+                error.message = %e,
+                "Failed to review the HTML content before sanitizing it"
+            );
+        }
+        report
+    }
+}
+
+// The scheme of an absolute URL, lowercased, `None` for relative URLs
+fn url_scheme(url: &str) -> Option<String> {
+    let url = url.trim();
+    let end = url.find([':', '/', '?', '#'])?;
+    if url[end..].starts_with(':') {
+        Some(url[..end].to_lowercase())
+    } else {
+        None
+    }
+}
+
+fn strip_http(url: &str) -> Option<&str> {
+    let url = url.trim();
+    match url.get(..7) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("http://") => {
+            Some(&url[7..])
+        }
+        _ => None,
+    }
+}
+
+impl SanitizationReport {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl std::fmt::Display for SanitizationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut removed = Vec::new();
+        for (tag, count) in &self.removed_tags {
+            removed.push(format!(
+                "{} <{}> {}",
+                count,
+                tag,
+                plural(*count, "tag")
+            ));
+        }
+        for (attribute, count) in &self.removed_attributes {
+            removed.push(format!(
+                "{} {} {}",
+                count,
+                attribute,
+                plural(*count, "attribute")
+            ));
+        }
+        if self.removed_urls > 0 {
+            removed.push(format!(
+                "{} {} with a forbidden URL scheme",
+                self.removed_urls,
+                plural(self.removed_urls, "URL")
+            ));
+        }
+        let mut report = String::new();
+        if !removed.is_empty() {
+            write!(report, "Removed {}.", removed.join(", "))?;
+        }
+        if self.upgraded_urls > 0 {
+            if !report.is_empty() {
+                report.push(' ');
+            }
+            write!(
+                report,
+                "Rewrote {} {} to https.",
+                self.upgraded_urls,
+                plural(self.upgraded_urls, "URL")
+            )?;
+        }
+        f.write_str(&report)
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        word.to_owned()
+    } else {
+        format!("{}s", word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HtmlSanitizer;
+
+    fn sanitizer() -> HtmlSanitizer {
+        let strings =
+            |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        HtmlSanitizer::new(
+            strings(&["p", "a", "img", "strong"]),
+            strings(&["href", "src", "alt", "style"]),
+            strings(&["https", "http", "mailto"]),
+            true,
+        )
+    }
+
+    #[test]
+    fn allowed_html_is_left_untouched() {
+        let html = r#"<p style="color: red">Hi <strong>you</strong>, <a href="https://example.com">read</a></p>"#;
+        let (sanitized, report) = sanitizer().sanitize(html);
+        assert_eq!(sanitized, html);
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn scripts_forms_and_iframes_are_removed() {
+        let (sanitized, report) = sanitizer().sanitize(
+            r#"<p>Hi</p><script>alert(1)</script><form action="/x"><p>In a form</p></form><iframe src="https://tracker.example.com"></iframe>"#,
+        );
+        assert_eq!(sanitized, "<p>Hi</p><p>In a form</p>");
+        assert_eq!(
+            report.to_string(),
+            "Removed 1 <form> tag, 1 <iframe> tag, 1 <script> tag."
+        );
+    }
+
+    #[test]
+    fn attributes_outside_the_allowlist_are_removed() {
+        let (sanitized, report) = sanitizer()
+            .sanitize(r#"<p onclick="steal()" class="a">Hi</p><img src="https://example.com/a.png" onerror="steal()">"#);
+        assert_eq!(
+            sanitized,
+            r#"<p>Hi</p><img src="https://example.com/a.png">"#
+        );
+        assert_eq!(
+            report.to_string(),
+            "Removed 1 class attribute, 1 onclick attribute, 1 onerror attribute."
+        );
+    }
+
+    #[test]
+    fn urls_are_upgraded_to_https() {
+        let (sanitized, report) = sanitizer()
+            .sanitize(r#"<a href="http://example.com/post">Read</a>"#);
+        assert_eq!(sanitized, r#"<a href="https://example.com/post">Read</a>"#);
+        assert_eq!(report.to_string(), "Rewrote 1 URL to https.");
+    }
+
+    #[test]
+    fn forbidden_url_schemes_are_removed() {
+        let (sanitized, report) =
+            sanitizer().sanitize(r#"<a href="javascript:alert(1)">Click</a>"#);
+        assert_eq!(sanitized, "<a>Click</a>");
+        assert_eq!(
+            report.to_string(),
+            "Removed 1 URL with a forbidden URL scheme."
+        );
+    }
+
+    #[test]
+    fn merge_tags_in_urls_are_kept() {
+        let html = r#"<a href="{{ unsubscribe_url }}">Unsubscribe</a>"#;
+        let (sanitized, report) = sanitizer().sanitize(html);
+        assert_eq!(sanitized, html);
+        assert!(report.is_empty());
+    }
+}
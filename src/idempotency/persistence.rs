@@ -5,6 +5,7 @@ use sqlx::{Postgres, Transaction};
 use uuid::Uuid;
 
 use super::IdempotencyKey;
+use crate::metrics::IDEMPOTENCY_REPLAYS;
 
 pub enum NextAction {
     StartProcessing(Box<Transaction<'static, Postgres>>),
@@ -42,6 +43,7 @@ pub async fn try_processing(
             .ok_or_else(|| {
                 anyhow::anyhow!("We expected a saved response, we didn't it")
             })?;
+        IDEMPOTENCY_REPLAYS.inc();
         Ok(NextAction::ReturnSavedResponse(saved_response))
     }
 }
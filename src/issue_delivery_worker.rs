@@ -1,36 +1,145 @@
 use std::time::Duration;
 
+use secrecy::Secret;
 use sqlx::{PgPool, Postgres, Transaction};
-use tracing::{field::display, Span};
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
-    configuration::Settings, domain::SubscriberEmail,
-    email_client::EmailClient, startup::get_connection_pool,
+    circuit_breaker::{CircuitBreaker, SendPermit},
+    configuration::Settings,
+    confirmation_emails::try_send_confirmation_email,
+    domain::{Recipient, SubscriberEmail, Template},
+    email_client::{EmailClient, SendEmailError},
+    email_tracking::{add_tracking, get_links},
+    metrics::{EMAILS, WORKER_ITERATIONS},
+    newsletter_issues::{archive_url, prepend_to_body},
+    outgoing_webhooks::{
+        enqueue_event, try_deliver_webhook, webhook_client, WebhookEvent,
+    },
+    rate_limiter::SendRateLimiter,
+    routes::preferences::unsubscribe_url,
+    startup::get_worker_connection_pool,
+    suppressions::{find_suppression, log_suppressed_send},
+    telemetry::set_parent_from,
+    workers::heartbeat,
 };
 
+// How often the worker tells it is alive
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
 pub async fn run_worker_until_stopped(
     configuration: Settings,
 ) -> Result<(), anyhow::Error> {
-    let connection_pool = get_connection_pool(&configuration.database);
+    let concurrency = configuration.worker.concurrency.max(1);
+    let connection_pool =
+        get_worker_connection_pool(&configuration.database, concurrency);
+    let rate_limiter =
+        SendRateLimiter::new(&configuration.email_client.rate_limit);
+    let circuit_breaker =
+        CircuitBreaker::new(&configuration.email_client.circuit_breaker);
     // use the helper function
     let email_client = configuration.email_client.client();
-    worker_loop(connection_pool, email_client).await
+    let webhook_client = webhook_client();
+    let base_url = configuration.application.base_url;
+    let hmac_secret = configuration.application.hmac_secret;
+    let worker_id = Uuid::new_v4();
+    tracing::info!(%worker_id, concurrency, "Starting the delivery worker.");
+
+    // The consumers take turns on this task, waiting on the network and
+    // the database most of the time. Tasks are locked while they are
+    // worked on, so each is only picked up by one of them.
+    let consumers = (0..concurrency).map(|_| {
+        worker_loop(
+            &connection_pool,
+            &email_client,
+            &rate_limiter,
+            &circuit_breaker,
+            &webhook_client,
+            &base_url,
+            &hmac_secret,
+            worker_id,
+        )
+    });
+    futures::try_join!(
+        heartbeat_loop(&connection_pool, &rate_limiter, worker_id, concurrency),
+        futures::future::try_join_all(consumers),
+    )?;
+    Ok(())
+}
+
+// Housekeeping shared by the consumers is done along with the heartbeat
+async fn heartbeat_loop(
+    pool: &PgPool,
+    rate_limiter: &SendRateLimiter,
+    worker_id: Uuid,
+    concurrency: u16,
+) -> Result<(), anyhow::Error> {
+    loop {
+        if let Err(e) = heartbeat(pool, worker_id, concurrency).await {
+            tracing::warn!(
+                error.message = %e,
+                "Failed to record the worker heartbeat."
+            );
+        }
+        if let Err(e) = rate_limiter.forget_idle_buckets(pool).await {
+            tracing::warn!(
+                error.message = %e,
+                "Failed to forget the idle send rate buckets."
+            );
+        }
+        tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+    }
 }
 
+// Issues, confirmation emails and webhook events are delivered in turns
+#[allow(clippy::too_many_arguments)]
 async fn worker_loop(
-    pool: PgPool,
-    email_client: EmailClient,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    circuit_breaker: &CircuitBreaker,
+    webhook_client: &reqwest::Client,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    worker_id: Uuid,
 ) -> Result<(), anyhow::Error> {
     loop {
-        match try_execute_task(&pool, &email_client).await {
-            Ok(ExecutionOutcome::EmptyQueue) => {
+        let issues = try_execute_task(
+            pool,
+            email_client,
+            rate_limiter,
+            circuit_breaker,
+            base_url,
+            hmac_secret,
+            worker_id,
+        )
+        .await;
+        let confirmations = try_send_confirmation_email(
+            pool,
+            email_client,
+            rate_limiter,
+            circuit_breaker,
+            base_url,
+        )
+        .await;
+        let webhooks = try_deliver_webhook(pool, webhook_client).await;
+        match (issues, confirmations, webhooks) {
+            (Err(_), _, _) | (_, Err(_), _) | (_, _, Err(_)) => {
+                WORKER_ITERATIONS.with_label_values(&["error"]).inc();
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            (
+                Ok(ExecutionOutcome::EmptyQueue | ExecutionOutcome::Paused),
+                Ok(ExecutionOutcome::EmptyQueue | ExecutionOutcome::Paused),
+                Ok(ExecutionOutcome::EmptyQueue),
+            ) => {
+                WORKER_ITERATIONS.with_label_values(&["idle"]).inc();
                 tokio::time::sleep(Duration::from_secs(10)).await;
             }
-            Err(_) => {
-                tokio::time::sleep(Duration::from_secs(1)).await;
+            _ => {
+                WORKER_ITERATIONS.with_label_values(&["busy"]).inc();
             }
-            Ok(ExecutionOutcome::TaskCompleted) => {}
         }
     }
 }
@@ -38,97 +147,447 @@ async fn worker_loop(
 pub enum ExecutionOutcome {
     TaskCompleted,
     EmptyQueue,
+    // The circuit breaker around the email provider is open
+    Paused,
 }
 
-#[tracing::instrument(
-    skip_all,
-    fields(
-        newsletter_issue_id=tracing::field::Empty,
-        subscriber_email=tracing::field::Empty
-    )
-)]
+// How many times the delivery of an issue to a subscriber is attempted
+const MAX_DELIVERY_ATTEMPTS: i16 = 5;
+// Delay before the first retry, doubled after every failed attempt
+const RETRY_BACKOFF_SECONDS: f64 = 30.0;
+// How long to hold back when the provider rate limits us without telling
+// for how long
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(30);
+// A task set aside while waiting for our turn to send is left alone by the
+// other consumers for this long after it
+const SET_ASIDE_MARGIN: Duration = Duration::from_secs(60);
+
+/// Deliver the next task in the queue, on behalf of the worker.
 pub async fn try_execute_task(
     pool: &PgPool,
     email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    circuit_breaker: &CircuitBreaker,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    worker_id: Uuid,
 ) -> Result<ExecutionOutcome, anyhow::Error> {
-    let task = dequeue_task(pool).await?;
-    if task.is_none() {
-        return Ok(ExecutionOutcome::EmptyQueue);
+    if !circuit_breaker.allow(pool).await? {
+        return Ok(ExecutionOutcome::Paused);
     }
-    let (transaction, issue_id, email) = task.unwrap();
-
-    Span::current()
-        .record("newsletter_issue_id", &display(issue_id))
-        .record("subscriber_email", &display(&email));
-
-    // send email
-    match SubscriberEmail::parse(email.clone()) {
-        Ok(email) => {
-            let issue = get_issue(pool, issue_id).await?;
-            if let Err(e) = email_client
-                .send_email(
-                    &email,
-                    &issue.title,
-                    &issue.html_content,
-                    &issue.text_content,
-                )
+    let (transaction, task) = match dequeue_task(pool).await? {
+        Some(next) => next,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    // Continue the trace of the request that published the issue
+    let span = tracing::info_span!(
+        "execute_task",
+        newsletter_issue_id = %task.newsletter_issue_id,
+        subscriber_email = %task.subscriber_email,
+        %worker_id
+    );
+    set_parent_from(&span, task.trace_context.clone());
+    execute_task(
+        pool,
+        email_client,
+        rate_limiter,
+        circuit_breaker,
+        base_url,
+        hmac_secret,
+        transaction,
+        task,
+        worker_id,
+    )
+    .instrument(span)
+    .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    circuit_breaker: &CircuitBreaker,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    transaction: PgTransaction,
+    task: Task,
+    worker_id: Uuid,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let email = match compose(pool, base_url, hmac_secret, &task).await? {
+        Ok(email) => email,
+        Err(outcome) => {
+            return complete_task(pool, transaction, task, worker_id, outcome)
                 .await
-            {
-                tracing:: error!(
-                    error.cause_chain = ?e,
-                    error.message = %e,
-                    "Failed to deliver issue to a confirmed subscriber. \
-                    Skipping.",
-                );
-            }
         }
+    };
+    let permit = match circuit_breaker.permit_send(pool).await? {
+        Some(permit) => permit,
+        // Opened, or another consumer is probing, since the task was taken:
+        // it is left in the queue as it was
+        None => return Ok(ExecutionOutcome::Paused),
+    };
+    // The send is booked with the rate limiter only once it is certain
+    let (transaction, task) = match wait_for_turn(
+        pool,
+        rate_limiter,
+        transaction,
+        task,
+        &email.recipient,
+    )
+    .await?
+    {
+        Some(next) => next,
+        // Cancelled or paused while we waited
+        None => {
+            circuit_breaker.release(pool, permit).await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+    };
+    let outcome = send(
+        pool,
+        email_client,
+        rate_limiter,
+        circuit_breaker,
+        permit,
+        &email,
+    )
+    .await?;
+    complete_task(pool, transaction, task, worker_id, outcome).await
+}
+
+// Record the outcome of a delivery and remove the task, unless it is to
+// be attempted again.
+async fn complete_task(
+    pool: &PgPool,
+    mut transaction: PgTransaction,
+    task: Task,
+    worker_id: Uuid,
+    outcome: DeliveryOutcome,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let n_attempts = task.n_retries + 1;
+    match outcome {
+        DeliveryOutcome::Sent => {
+            EMAILS.with_label_values(&["sent"]).inc();
+            log_delivery(&mut transaction, &task, worker_id, "sent", None)
+                .await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+        DeliveryOutcome::RateLimited(delay) => {
+            EMAILS.with_label_values(&["rate_limited"]).inc();
+            postpone_task(&mut transaction, &task, delay).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        DeliveryOutcome::Retry(_) if n_attempts < MAX_DELIVERY_ATTEMPTS => {
+            EMAILS.with_label_values(&["retried"]).inc();
+            retry_task(&mut transaction, &task).await?;
+            transaction.commit().await?;
+            return Ok(ExecutionOutcome::TaskCompleted);
+        }
+        DeliveryOutcome::Retry(e) | DeliveryOutcome::Failed(e) => {
+            EMAILS.with_label_values(&["failed"]).inc();
+            log_delivery(
+                &mut transaction,
+                &task,
+                worker_id,
+                "failed",
+                Some(&e),
+            )
+            .await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+        DeliveryOutcome::Suppressed(reason) => {
+            EMAILS.with_label_values(&["suppressed"]).inc();
+            log_suppressed_send(
+                &mut transaction,
+                &task.subscriber_email,
+                "delivery",
+                Some(task.newsletter_issue_id),
+                &reason,
+            )
+            .await?;
+            delete_task(&mut transaction, &task).await?;
+        }
+    }
+    transaction.commit().await?;
+    // Once the task is committed and its row unlocked: cancelling an issue
+    // locks it before waiting for the tasks being executed.
+    if is_queue_empty(pool, task.newsletter_issue_id).await? {
+        let mut transaction = pool.begin().await?;
+        finish_delivery_if_done(&mut transaction, task.newsletter_issue_id)
+            .await?;
+        transaction.commit().await?;
+    }
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+enum DeliveryOutcome {
+    Sent,
+    // Trying again would not help, e.g. the address is invalid
+    Failed(String),
+    // Sending failed, it can be attempted again later
+    Retry(String),
+    // The address was suppressed after the issue was published
+    Suppressed(String),
+    // The provider refused the send, to be attempted again after the delay
+    // without counting as an attempt
+    RateLimited(Duration),
+}
+
+// An issue personalised for one of its recipients
+struct Email {
+    recipient: SubscriberEmail,
+    issue: NewsletterIssue,
+}
+
+// Personalise the issue for the subscriber of the task, unless there is
+// nothing to send them.
+async fn compose(
+    pool: &PgPool,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    task: &Task,
+) -> Result<Result<Email, DeliveryOutcome>, anyhow::Error> {
+    let email = &task.subscriber_email;
+    let subscriber_email = match SubscriberEmail::parse(email.clone()) {
+        Ok(subscriber_email) => subscriber_email,
         Err(e) => {
-            tracing:: error!(
+            tracing::error!(
                 error.cause_chain = ?e,
                 error.message = %e,
                 "Skipping a confirmed subscriber. \
                 Their stored contact details are invalid",
             );
+            return Ok(Err(DeliveryOutcome::Failed(e)));
+        }
+    };
+    if let Some(suppression) = find_suppression(pool, email).await? {
+        return Ok(Err(DeliveryOutcome::Suppressed(suppression.describe())));
+    }
+    let issue = get_issue(pool, task.newsletter_issue_id).await?;
+    let subscriber = match get_subscriber(pool, email).await? {
+        Some(subscriber) => subscriber,
+        None => {
+            tracing::warn!(
+                "Skipping a subscriber who changed their email address \
+                or was deleted since the issue was published.",
+            );
+            return Ok(Err(DeliveryOutcome::Failed(
+                "The subscriber changed their email address or was deleted."
+                    .into(),
+            )));
+        }
+    };
+    let unsubscribe_url = unsubscribe_url(base_url, hmac_secret, subscriber.id);
+    let recipient = Recipient {
+        name: &subscriber.name,
+        email,
+        unsubscribe_url: &unsubscribe_url,
+        custom_fields: &subscriber.custom_fields,
+    };
+    let mut issue = match issue.personalise(&recipient) {
+        Ok(issue) => issue,
+        Err(e) => {
+            tracing::error!(
+                error.message = %e,
+                "Skipping an issue whose merge tags are invalid.",
+            );
+            return Ok(Err(DeliveryOutcome::Failed(e)));
+        }
+    };
+    if issue.show_in_archive {
+        issue.add_view_in_browser_link(base_url);
+    }
+    if issue.tracking_enabled {
+        issue.html_content = track(
+            pool,
+            &issue.html_content,
+            base_url,
+            hmac_secret,
+            task.newsletter_issue_id,
+            subscriber.id,
+        )
+        .await?;
+    }
+    Ok(Ok(Email {
+        recipient: subscriber_email,
+        issue,
+    }))
+}
+
+async fn send(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    rate_limiter: &SendRateLimiter,
+    circuit_breaker: &CircuitBreaker,
+    permit: SendPermit,
+    email: &Email,
+) -> Result<DeliveryOutcome, anyhow::Error> {
+    match email_client
+        .send_email(
+            &email.recipient,
+            &email.issue.title,
+            &email.issue.html_content,
+            &email.issue.text_content,
+        )
+        .await
+    {
+        Ok(()) => {
+            circuit_breaker.record_success(pool).await?;
+            Ok(DeliveryOutcome::Sent)
+        }
+        Err(SendEmailError::RateLimited { retry_after }) => {
+            let delay = retry_after.unwrap_or(DEFAULT_RETRY_AFTER);
+            tracing::warn!(
+                retry_after_ms = delay.as_millis(),
+                "The email provider is rate limiting our sends, backing off.",
+            );
+            circuit_breaker.release(pool, permit).await?;
+            rate_limiter.back_off(pool, delay).await?;
+            Ok(DeliveryOutcome::RateLimited(delay))
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "Failed to deliver issue to a confirmed subscriber.",
+            );
+            if e.is_provider_failure() {
+                circuit_breaker.record_failure(pool).await?;
+            } else {
+                circuit_breaker.release(pool, permit).await?;
+            }
+            Ok(DeliveryOutcome::Retry(e.to_string()))
         }
     }
-    delete_task(transaction, issue_id, &email).await?;
-    Ok(ExecutionOutcome::TaskCompleted)
 }
 
 type PgTransaction = Transaction<'static, Postgres>;
 
+struct Task {
+    newsletter_issue_id: Uuid,
+    subscriber_email: String,
+    n_retries: i16,
+    trace_context: Option<serde_json::Value>,
+}
+
 #[tracing::instrument(skip_all)]
 async fn dequeue_task(
     pool: &PgPool,
-) -> Result<Option<(PgTransaction, Uuid, String)>, anyhow::Error> {
+) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
     let mut transaction = pool.begin().await?;
-    let r = sqlx::query!(
+    let task = sqlx::query_as!(
+        Task,
         r#"
-        SELECT newsletter_issue_id, subscriber_email
-        FROM issue_delivery_queue
-        FOR UPDATE
+        SELECT
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            q.trace_context
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i
+            ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE
+            q.execute_after <= now() AND
+            -- The tasks of paused issues stay in the queue until resumed
+            i.delivery_status = 'active'
+        FOR UPDATE OF q
         SKIP LOCKED
         LIMIT 1
         "#,
     )
     .fetch_optional(&mut transaction)
     .await?;
-    if let Some(r) = r {
-        Ok(Some((
-            transaction,
-            r.newsletter_issue_id,
-            r.subscriber_email,
-        )))
-    } else {
-        Ok(None)
+    Ok(task.map(|task| (transaction, task)))
+}
+
+// Book our send with the rate limiter. If it is not our turn yet, the task
+// is set aside and unlocked while we wait, then taken again: holding its
+// lock would block cancelling the issue for as long.
+#[tracing::instrument(skip_all)]
+async fn wait_for_turn(
+    pool: &PgPool,
+    rate_limiter: &SendRateLimiter,
+    mut transaction: PgTransaction,
+    task: Task,
+    recipient: &SubscriberEmail,
+) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let wait = rate_limiter.reserve(pool, recipient).await?;
+    if wait.is_zero() {
+        return Ok(Some((transaction, task)));
     }
+    tracing::debug!(wait_ms = wait.as_millis(), "Waiting to send.");
+    postpone_task(&mut transaction, &task, wait + SET_ASIDE_MARGIN).await?;
+    transaction.commit().await?;
+    tokio::time::sleep(wait).await;
+    let next = take_task(pool, &task).await?;
+    if next.is_none() {
+        hand_back_task(pool, &task).await?;
+    }
+    Ok(next)
+}
+
+// Lock a task again, unless it was removed or its issue paused since
+#[tracing::instrument(skip_all)]
+async fn take_task(
+    pool: &PgPool,
+    task: &Task,
+) -> Result<Option<(PgTransaction, Task)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let task = sqlx::query_as!(
+        Task,
+        r#"
+        SELECT
+            q.newsletter_issue_id,
+            q.subscriber_email,
+            q.n_retries,
+            q.trace_context
+        FROM issue_delivery_queue q
+        JOIN newsletter_issues i
+            ON i.newsletter_issue_id = q.newsletter_issue_id
+        WHERE
+            q.newsletter_issue_id = $1 AND
+            q.subscriber_email = $2 AND
+            i.delivery_status = 'active'
+        FOR UPDATE OF q
+        SKIP LOCKED
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(task.map(|task| (transaction, task)))
+}
+
+// Undo setting a task aside, so that it is due as soon as its issue is
+// resumed. Nothing changes if it was removed in the meantime.
+#[tracing::instrument(skip_all)]
+async fn hand_back_task(
+    pool: &PgPool,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
 }
 
 #[tracing::instrument(skip_all)]
 async fn delete_task(
-    mut transaction: PgTransaction,
-    issue_id: Uuid,
-    email: &str,
+    transaction: &mut PgTransaction,
+    task: &Task,
 ) -> Result<(), anyhow::Error> {
     sqlx::query!(
         r#"
@@ -137,12 +596,162 @@ async fn delete_task(
             newsletter_issue_id = $1 AND
             subscriber_email = $2
         "#,
-        issue_id,
-        email
+        task.newsletter_issue_id,
+        task.subscriber_email
     )
-    .execute(&mut transaction)
+    .execute(transaction)
     .await?;
-    transaction.commit().await?;
+    Ok(())
+}
+
+// Leave the task in the queue for another attempt, after a backoff
+#[tracing::instrument(skip_all)]
+async fn retry_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+) -> Result<(), anyhow::Error> {
+    let backoff = RETRY_BACKOFF_SECONDS * 2f64.powi(task.n_retries.into());
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        backoff
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn postpone_task(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    delay: Duration,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE issue_delivery_queue
+        SET execute_after = now() + make_interval(secs => $3)
+        WHERE
+            newsletter_issue_id = $1 AND
+            subscriber_email = $2
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        delay.as_secs_f64()
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, task))]
+async fn log_delivery(
+    transaction: &mut PgTransaction,
+    task: &Task,
+    worker_id: Uuid,
+    outcome: &str,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_log (
+            newsletter_issue_id,
+            subscriber_email,
+            outcome,
+            n_attempts,
+            error,
+            worker_id
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        task.newsletter_issue_id,
+        task.subscriber_email,
+        outcome,
+        task.n_retries + 1,
+        error,
+        worker_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn is_queue_empty(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<bool, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT NOT EXISTS (
+            SELECT 1 FROM issue_delivery_queue
+            WHERE newsletter_issue_id = $1
+        ) AS "is_empty!"
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.is_empty)
+}
+
+/// Mark the delivery of an issue as finished once its last recipient is
+/// done with, and let the endpoints know. Call it once the recipients are
+/// committed: workers finishing the last ones at the same time then all
+/// see the queue empty, and the update of the issue lets a single one
+/// through.
+#[tracing::instrument(skip(transaction))]
+pub async fn finish_delivery_if_done(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let issue = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET delivery_finished_at = now()
+        WHERE
+            newsletter_issue_id = $1 AND
+            delivery_finished_at IS NULL AND
+            NOT EXISTS (
+                SELECT 1 FROM issue_delivery_queue
+                WHERE newsletter_issue_id = $1
+            )
+        RETURNING
+            title,
+            (
+                SELECT COUNT(*) FROM issue_delivery_log
+                WHERE newsletter_issue_id = $1 AND outcome = 'sent'
+            ) AS "sent!",
+            (
+                SELECT COUNT(*) FROM issue_delivery_log
+                WHERE newsletter_issue_id = $1 AND outcome = 'failed'
+            ) AS "failed!"
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await?;
+    if let Some(issue) = issue {
+        enqueue_event(
+            transaction,
+            WebhookEvent::DeliveryFinished,
+            serde_json::json!({
+                "newsletter_issue_id": newsletter_issue_id,
+                "title": issue.title,
+                "sent": issue.sent,
+                "failed": issue.failed,
+            }),
+        )
+        .await?;
+    }
     Ok(())
 }
 
@@ -150,6 +759,102 @@ struct NewsletterIssue {
     title: String,
     text_content: String,
     html_content: String,
+    tracking_enabled: bool,
+    show_in_archive: bool,
+    slug: String,
+}
+
+impl NewsletterIssue {
+    // Fill in the merge tags for a recipient.
+    // Templates are validated when the issue is published.
+    fn personalise(
+        &self,
+        recipient: &Recipient,
+    ) -> Result<NewsletterIssue, String> {
+        Ok(NewsletterIssue {
+            title: Template::parse(&self.title)?.render(recipient, false),
+            text_content: Template::parse(&self.text_content)?
+                .render(recipient, false),
+            html_content: Template::parse(&self.html_content)?
+                .render(recipient, true),
+            tracking_enabled: self.tracking_enabled,
+            show_in_archive: self.show_in_archive,
+            slug: self.slug.clone(),
+        })
+    }
+
+    // Point to the copy of the issue in the public archive
+    fn add_view_in_browser_link(&mut self, base_url: &str) {
+        let url = archive_url(base_url, &self.slug);
+        let link = format!(
+            r#"<p style="text-align: center"><a href="{}">View in browser</a></p>"#,
+            htmlescape::encode_minimal(&url)
+        );
+        match prepend_to_body(&self.html_content, &link) {
+            Ok(html_content) => self.html_content = html_content,
+            Err(e) => tracing::warn!(
+                error.message = %e,
+                "Failed to add the view in browser link to an issue.",
+            ),
+        }
+        self.text_content =
+            format!("View in browser: {}\n\n{}", url, self.text_content);
+    }
+}
+
+// Add the tracking pixel and click redirects to the HTML content sent to a
+// subscriber. If that fails, the issue is sent without tracking.
+async fn track(
+    pool: &PgPool,
+    html_content: &str,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<String, anyhow::Error> {
+    let links = get_links(pool, issue_id).await?;
+    match add_tracking(
+        html_content,
+        &links,
+        base_url,
+        hmac_secret,
+        issue_id,
+        subscriber_id,
+    ) {
+        Ok(html_content) => Ok(html_content),
+        Err(e) => {
+            tracing::warn!(
+                error.message = %e,
+                "Failed to add tracking to an issue. Sending it untracked.",
+            );
+            Ok(html_content.to_owned())
+        }
+    }
+}
+
+struct Subscriber {
+    id: Uuid,
+    name: String,
+    custom_fields: serde_json::Value,
+}
+
+#[tracing::instrument(skip_all)]
+async fn get_subscriber(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<Subscriber>, anyhow::Error> {
+    let subscriber = sqlx::query_as!(
+        Subscriber,
+        r#"
+        SELECT id, name, custom_fields
+        FROM subscriptions
+        WHERE email = $1
+        "#,
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(subscriber)
 }
 
 async fn get_issue(
@@ -159,7 +864,13 @@ async fn get_issue(
     let issue = sqlx::query_as!(
         NewsletterIssue,
         r#"
-        SELECT title, text_content, html_content
+        SELECT
+            title,
+            text_content,
+            html_content,
+            tracking_enabled,
+            show_in_archive,
+            slug
         FROM newsletter_issues
         WHERE
             newsletter_issue_id = $1
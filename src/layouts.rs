@@ -0,0 +1,66 @@
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::Layout;
+
+pub struct LayoutSummary {
+    pub layout_id: Uuid,
+    pub name: String,
+    pub is_default: bool,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_layouts(
+    pool: &PgPool,
+) -> Result<Vec<LayoutSummary>, sqlx::Error> {
+    sqlx::query_as!(
+        LayoutSummary,
+        r#"
+        SELECT layout_id, name, is_default
+        FROM layouts
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Returns `None` if there is no layout with this id.
+#[tracing::instrument(skip(pool))]
+pub async fn get_layout(
+    pool: &PgPool,
+    layout_id: Uuid,
+) -> Result<Option<Layout>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT html FROM layouts WHERE layout_id = $1
+        "#,
+        layout_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the layout")?;
+    row.map(|r| Layout::parse(r.html).map_err(anyhow::Error::msg))
+        .transpose()
+        .context("A stored layout is invalid")
+}
+
+/// The layout of the emails sent by the application itself,
+/// if one was chosen.
+#[tracing::instrument(skip(pool))]
+pub async fn get_default_layout(
+    pool: &PgPool,
+) -> Result<Option<Layout>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT html FROM layouts WHERE is_default
+        "#
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the default layout")?;
+    row.map(|r| Layout::parse(r.html).map_err(anyhow::Error::msg))
+        .transpose()
+        .context("The default layout is invalid")
+}
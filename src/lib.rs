@@ -1,13 +1,25 @@
 //! src/lib.rs
 // make public to other binaries (main, test)
 pub mod authentication;
+pub mod circuit_breaker;
 pub mod configuration;
+pub mod confirmation_emails;
 pub mod domain;
 pub mod email_client;
+pub mod email_tracking;
+pub mod html_sanitizer;
 pub mod idempotency;
 pub mod issue_delivery_worker;
+pub mod layouts;
+pub mod mailing_lists;
+pub mod metrics;
+pub mod newsletter_issues;
+pub mod outgoing_webhooks;
+pub mod rate_limiter;
 pub mod routes;
 pub mod session_state;
 pub mod startup;
+pub mod suppressions;
 pub mod telemetry;
 pub mod utils;
+pub mod workers;
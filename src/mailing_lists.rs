@@ -0,0 +1,86 @@
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+pub struct MailingList {
+    pub list_id: Uuid,
+    pub name: String,
+    pub description: String,
+    pub is_default: bool,
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_lists(pool: &PgPool) -> Result<Vec<MailingList>, sqlx::Error> {
+    sqlx::query_as!(
+        MailingList,
+        r#"
+        SELECT list_id, name, description, is_default
+        FROM lists
+        ORDER BY name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// Resolve the lists targeted by a subscription or a newsletter issue.
+///
+/// When nothing was chosen the default lists are used.
+/// Returns `None` if one of the ids does not match any list.
+#[tracing::instrument(skip(pool))]
+pub async fn select_lists(
+    pool: &PgPool,
+    list_ids: &[Uuid],
+) -> Result<Option<Vec<Uuid>>, sqlx::Error> {
+    if list_ids.is_empty() {
+        let rows = sqlx::query!(
+            r#"
+            SELECT list_id FROM lists WHERE is_default
+            "#
+        )
+        .fetch_all(pool)
+        .await?;
+        return Ok(Some(rows.into_iter().map(|r| r.list_id).collect()));
+    }
+
+    let mut requested = list_ids.to_vec();
+    requested.sort();
+    requested.dedup();
+    let rows = sqlx::query!(
+        r#"
+        SELECT list_id FROM lists WHERE list_id = ANY($1)
+        "#,
+        &requested
+    )
+    .fetch_all(pool)
+    .await?;
+    if rows.len() < requested.len() {
+        return Ok(None);
+    }
+    Ok(Some(requested))
+}
+
+/// Add a subscriber to the given lists, skipping the ones they already
+/// belong to. The new memberships are confirmed together with the
+/// subscriber's email address.
+///
+/// Returns the number of lists the subscriber was not a member of yet.
+#[tracing::instrument(skip(transaction))]
+pub async fn add_memberships(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (list_id, subscriber_id, created_at)
+        SELECT list_id, $1, now()
+        FROM UNNEST($2::uuid[]) AS t(list_id)
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_id,
+        list_ids
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected())
+}
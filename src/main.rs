@@ -5,17 +5,25 @@ use tokio::task::JoinError;
 use zero2prod::configuration::get_configuration;
 use zero2prod::issue_delivery_worker::run_worker_until_stopped;
 use zero2prod::startup::Application;
-use zero2prod::telemetry::{get_subscriber, init_subscriber};
+use zero2prod::telemetry::{
+    get_subscriber, get_tracer_provider, init_subscriber, init_tracer,
+};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let subscriber =
-        get_subscriber("zero2prod".into(), "debug".into(), std::io::stdout);
-    init_subscriber(subscriber);
-
     let configuration =
         get_configuration().expect("failed to read configuration ");
 
+    let tracer_provider =
+        get_tracer_provider("zero2prod".into(), &configuration.telemetry)?;
+    let subscriber = get_subscriber(
+        "zero2prod".into(),
+        "debug".into(),
+        std::io::stdout,
+        init_tracer(tracer_provider),
+    );
+    init_subscriber(subscriber);
+
     let application = Application::build(configuration.clone()).await?;
     let application = application.run_until_stopped();
     let worker = run_worker_until_stopped(configuration);
@@ -26,6 +34,8 @@ async fn main() -> anyhow::Result<()> {
         o = application_task => report_exit("API", o),
         o = worker_task => report_exit("Backgground worker", o),
     };
+    // Export the spans still waiting in the batch
+    opentelemetry::global::shutdown_tracer_provider();
 
     Ok(())
 }
@@ -0,0 +1,154 @@
+//! Prometheus metrics, scraped from `/metrics`.
+use std::time::Instant;
+
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+};
+use actix_web_lab::middleware::Next;
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, TextEncoder,
+};
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "http_requests_total",
+        "HTTP requests handled, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+pub static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "http_request_duration_seconds",
+        "Time taken to handle HTTP requests, by route and status",
+        &["method", "route", "status"]
+    )
+    .unwrap()
+});
+
+// Refreshed from the database when the metrics are scraped
+pub static QUEUE_DEPTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "delivery_queue_depth",
+        "Tasks waiting in the delivery queues",
+        &["queue"]
+    )
+    .unwrap()
+});
+
+pub static EMAILS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "emails_total",
+        "Issue deliveries attempted by the worker, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static RATE_LIMIT_WAIT: Lazy<Histogram> = Lazy::new(|| {
+    register_histogram!(
+        "email_rate_limit_wait_seconds",
+        "Time the delivery worker waited for the send rate limits"
+    )
+    .unwrap()
+});
+
+// Refreshed from the database when the metrics are scraped, 1 for the
+// current state and 0 for the others
+pub static CIRCUIT_BREAKER_STATE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "email_circuit_breaker_state",
+        "State of the circuit breaker around the email provider",
+        &["state"]
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_BREAKER_TRANSITIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "email_circuit_breaker_transitions_total",
+        "Changes of state of the circuit breaker around the email provider, \
+        by new state",
+        &["state"]
+    )
+    .unwrap()
+});
+
+pub static WORKER_ITERATIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "worker_loop_iterations_total",
+        "Iterations of the delivery worker loop, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+pub static IDEMPOTENCY_REPLAYS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "idempotency_replays_total",
+        "Requests answered with the saved response of an earlier request"
+    )
+    .unwrap()
+});
+
+pub static LOGINS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "logins_total",
+        "Login attempts, by outcome",
+        &["outcome"]
+    )
+    .unwrap()
+});
+
+// Refreshed when the metrics are scraped
+pub static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "db_pool_connections",
+        "Connections of the API database pool, by state",
+        &["state"]
+    )
+    .unwrap()
+});
+
+pub static DB_POOL_MAX_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "db_pool_max_connections",
+        "Size limit of the API database pool"
+    )
+    .unwrap()
+});
+
+/// Count every request and time it, labelled with the pattern of the
+/// route it matched rather than its path to keep ids out of the labels.
+pub async fn record_http_metrics(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".into());
+    let start = Instant::now();
+    let result = next.call(req).await;
+    let status = match &result {
+        Ok(response) => response.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    HTTP_REQUESTS.with_label_values(&labels).inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+/// All the registered metrics in the Prometheus text format.
+pub fn encode() -> Result<String, anyhow::Error> {
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
@@ -0,0 +1,305 @@
+use chrono::{DateTime, Utc};
+use lol_html::html_content::ContentType;
+use lol_html::{element, rewrite_str, RewriteStrSettings};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{Recipient, Template};
+
+// Issues per page of the archive
+const ARCHIVE_PAGE_SIZE: i64 = 10;
+
+/// Delivery progress and tracking numbers of a newsletter issue.
+pub struct IssueStats {
+    pub newsletter_issue_id: Uuid,
+    pub title: String,
+    pub published_at: String,
+    pub tracking_enabled: bool,
+    // Waiting for a first attempt
+    pub queued: i64,
+    // Waiting for another attempt after a failure
+    pub retrying: i64,
+    pub sent: i64,
+    pub failed: i64,
+    pub started_at: Option<DateTime<Utc>>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    // 'active', 'paused' or 'cancelled'
+    pub delivery_status: String,
+}
+
+impl IssueStats {
+    pub fn is_delivering(&self) -> bool {
+        self.queued + self.retrying > 0
+    }
+
+    /// A summary of where the delivery stands, for the admin pages.
+    pub fn status(&self) -> &'static str {
+        match self.delivery_status.as_str() {
+            "cancelled" => "Cancelled",
+            "paused" => "Paused",
+            _ if self.is_delivering() => "Delivering",
+            _ => "Delivered",
+        }
+    }
+
+    /// Emails sent per minute, once some went out.
+    pub fn throughput(&self) -> Option<f64> {
+        let (started_at, ended_at) = (self.started_at?, self.ended_at?);
+        let end = if self.is_delivering() {
+            Utc::now()
+        } else {
+            ended_at
+        };
+        // At least a second, for issues sent to a handful of subscribers
+        let seconds =
+            (end - started_at).num_milliseconds().max(1000) as f64 / 1000.0;
+        Some(self.sent as f64 * 60.0 / seconds)
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_issue_stats(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<IssueStats>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueStats,
+        r#"
+        SELECT
+            newsletter_issue_id AS "newsletter_issue_id!",
+            title AS "title!",
+            published_at AS "published_at!",
+            tracking_enabled AS "tracking_enabled!",
+            queued AS "queued!",
+            retrying AS "retrying!",
+            sent AS "sent!",
+            failed AS "failed!",
+            started_at,
+            ended_at,
+            unique_opens AS "unique_opens!",
+            unique_clicks AS "unique_clicks!",
+            delivery_status AS "delivery_status!"
+        FROM newsletter_issue_stats
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// All the issues, latest first.
+#[tracing::instrument(skip(pool))]
+pub async fn get_all_issue_stats(
+    pool: &PgPool,
+) -> Result<Vec<IssueStats>, sqlx::Error> {
+    sqlx::query_as!(
+        IssueStats,
+        r#"
+        SELECT
+            newsletter_issue_id AS "newsletter_issue_id!",
+            title AS "title!",
+            published_at AS "published_at!",
+            tracking_enabled AS "tracking_enabled!",
+            queued AS "queued!",
+            retrying AS "retrying!",
+            sent AS "sent!",
+            failed AS "failed!",
+            started_at,
+            ended_at,
+            unique_opens AS "unique_opens!",
+            unique_clicks AS "unique_clicks!",
+            delivery_status AS "delivery_status!"
+        FROM newsletter_issue_stats
+        ORDER BY published_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
+
+/// The path of an issue in the public archive: the title, without merge
+/// tags, followed by the start of the issue id to keep it unique.
+pub fn slug(title: &str, newsletter_issue_id: Uuid) -> String {
+    let mut words = String::new();
+    let mut rest = title;
+    while let Some(start) = rest.find("{{") {
+        words.push_str(&rest[..start]);
+        rest = match rest[start..].find("}}") {
+            Some(end) => &rest[start + end + 2..],
+            None => "",
+        };
+    }
+    words.push_str(rest);
+    let mut slug = words
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if !slug.is_empty() {
+        slug.push('-');
+    }
+    slug.push_str(&newsletter_issue_id.to_simple().to_string()[..8]);
+    slug
+}
+
+pub fn archive_url(base_url: &str, slug: &str) -> String {
+    format!("{}/archive/{}", base_url, slug)
+}
+
+/// Add a snippet of HTML at the start of the body, or of the whole
+/// document for fragments without one.
+pub fn prepend_to_body(html: &str, snippet: &str) -> Result<String, String> {
+    let mut has_body = false;
+    let prepended = rewrite_str(
+        html,
+        RewriteStrSettings {
+            element_content_handlers: vec![element!("body", |el| {
+                has_body = true;
+                el.prepend(snippet, ContentType::Html);
+                Ok(())
+            })],
+            ..RewriteStrSettings::default()
+        },
+    )
+    .map_err(|e| e.to_string())?;
+    if has_body {
+        Ok(prepended)
+    } else {
+        Ok(format!("{}{}", snippet, html))
+    }
+}
+
+pub struct ArchivedIssue {
+    pub newsletter_issue_id: Uuid,
+    pub slug: String,
+    pub title: String,
+    pub html_content: String,
+    pub published_at: DateTime<Utc>,
+}
+
+// Archived issues are public: merge tags are filled in for nobody in
+// particular, falling back to their default values. Readers who want to
+// unsubscribe are sent to the form asking for their preferences link.
+fn render_for_anyone(content: &str, escape_html: bool) -> String {
+    let recipient = Recipient {
+        name: "",
+        email: "",
+        unsubscribe_url: "/preferences/link",
+        custom_fields: &serde_json::Value::Null,
+    };
+    match Template::parse(content) {
+        Ok(template) => template.render(&recipient, escape_html),
+        Err(_) => content.to_owned(),
+    }
+}
+
+impl ArchivedIssue {
+    pub fn title(&self) -> String {
+        render_for_anyone(&self.title, false)
+    }
+
+    pub fn html_content(&self) -> String {
+        render_for_anyone(&self.html_content, true)
+    }
+}
+
+/// Where a page of the archive starts, `None` if it is too far away to
+/// be counted. Pages start at 1.
+pub fn archive_offset(page: i64) -> Option<i64> {
+    (page - 1).checked_mul(ARCHIVE_PAGE_SIZE)
+}
+
+/// A page of the archive starting at `offset`, latest issues first, and
+/// whether there are older issues.
+#[tracing::instrument(skip(pool))]
+pub async fn get_archive_page(
+    pool: &PgPool,
+    offset: i64,
+) -> Result<(Vec<ArchivedIssue>, bool), sqlx::Error> {
+    let mut issues = sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            slug,
+            title,
+            html_content,
+            published_at::timestamptz AS "published_at!"
+        FROM newsletter_issues
+        WHERE show_in_archive AND delivery_status <> 'cancelled'
+        ORDER BY published_at::timestamptz DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        ARCHIVE_PAGE_SIZE + 1,
+        offset
+    )
+    .fetch_all(pool)
+    .await?;
+    let has_more = issues.len() as i64 > ARCHIVE_PAGE_SIZE;
+    issues.truncate(ARCHIVE_PAGE_SIZE as usize);
+    Ok((issues, has_more))
+}
+
+/// Returns `None` if there is no issue with this slug in the archive.
+#[tracing::instrument(skip(pool))]
+pub async fn get_archived_issue(
+    pool: &PgPool,
+    slug: &str,
+) -> Result<Option<ArchivedIssue>, sqlx::Error> {
+    sqlx::query_as!(
+        ArchivedIssue,
+        r#"
+        SELECT
+            newsletter_issue_id,
+            slug,
+            title,
+            html_content,
+            published_at::timestamptz AS "published_at!"
+        FROM newsletter_issues
+        WHERE
+            slug = $1 AND
+            show_in_archive AND
+            delivery_status <> 'cancelled'
+        "#,
+        slug
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{prepend_to_body, slug};
+
+    #[test]
+    fn slugs_are_made_of_the_title_and_the_issue_id() {
+        let id =
+            Uuid::parse_str("3f2a9c1e-0000-4000-8000-000000000000").unwrap();
+        assert_eq!(slug("Hello, World!", id), "hello-world-3f2a9c1e");
+        assert_eq!(
+            slug("News for {{ subscriber.name }} - May", id),
+            "news-for-may-3f2a9c1e"
+        );
+        assert_eq!(slug("{{ subscriber.name }}", id), "3f2a9c1e");
+        assert_eq!(slug("Café & crème", id), "caf-cr-me-3f2a9c1e");
+    }
+
+    #[test]
+    fn snippets_are_prepended_to_the_body() {
+        assert_eq!(
+            prepend_to_body("<html><body><p>Hi</p></body></html>", "<hr>")
+                .unwrap(),
+            "<html><body><hr><p>Hi</p></body></html>"
+        );
+        assert_eq!(
+            prepend_to_body("<p>Hi</p>", "<hr>").unwrap(),
+            "<hr><p>Hi</p>"
+        );
+    }
+}
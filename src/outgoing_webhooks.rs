@@ -0,0 +1,340 @@
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::issue_delivery_worker::ExecutionOutcome;
+
+// How many times an event is sent to an endpoint before giving up
+const MAX_DELIVERY_ATTEMPTS: i16 = 8;
+// Delay before the first retry, doubled after every failed attempt
+const RETRY_BACKOFF_SECONDS: f64 = 30.0;
+// Endpoints that take longer are considered down
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The subscriber and issue lifecycle events sent to the endpoints.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    Subscribed,
+    Confirmed,
+    Unsubscribed,
+    IssuePublished,
+    DeliveryFinished,
+}
+
+impl WebhookEvent {
+    pub const ALL: [WebhookEvent; 5] = [
+        WebhookEvent::Subscribed,
+        WebhookEvent::Confirmed,
+        WebhookEvent::Unsubscribed,
+        WebhookEvent::IssuePublished,
+        WebhookEvent::DeliveryFinished,
+    ];
+
+    pub fn parse(s: &str) -> Result<WebhookEvent, String> {
+        Self::ALL
+            .into_iter()
+            .find(|event| event.as_str() == s)
+            .ok_or_else(|| format!("{} is not a known event.", s))
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Subscribed => "subscriber.subscribed",
+            WebhookEvent::Confirmed => "subscriber.confirmed",
+            WebhookEvent::Unsubscribed => "subscriber.unsubscribed",
+            WebhookEvent::IssuePublished => "issue.published",
+            WebhookEvent::DeliveryFinished => "issue.delivery_finished",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            WebhookEvent::Subscribed => "Someone subscribed",
+            WebhookEvent::Confirmed => "A subscriber confirmed their address",
+            WebhookEvent::Unsubscribed => "A subscriber unsubscribed",
+            WebhookEvent::IssuePublished => "An issue was published",
+            WebhookEvent::DeliveryFinished => {
+                "An issue was delivered to all its recipients"
+            }
+        }
+    }
+}
+
+/// Queue an event for every endpoint listening to it. It is only sent
+/// once the transaction is committed.
+#[tracing::instrument(skip(transaction, data))]
+pub async fn enqueue_event(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: WebhookEvent,
+    data: serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    let event_id = Uuid::new_v4();
+    let payload = serde_json::json!({
+        "id": event_id,
+        "type": event.as_str(),
+        "created_at": Utc::now(),
+        "data": data,
+    });
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_delivery_queue (
+            event_id,
+            endpoint_id,
+            event,
+            payload
+        )
+        SELECT $1, endpoint_id, $2, $3
+        FROM webhook_endpoints
+        WHERE $2 = ANY(events)
+        "#,
+        event_id,
+        event.as_str(),
+        payload
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// The value of the `X-Webhook-Signature` header: the time of the request
+/// and an HMAC-SHA256 of `{timestamp}.{body}`, hex-encoded. Receivers can
+/// reject stale timestamps to prevent replays.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(format!("{}.{}", timestamp, body).as_bytes());
+    format!(
+        "t={},v1={}",
+        timestamp,
+        hex::encode(mac.finalize().into_bytes())
+    )
+}
+
+pub fn webhook_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .unwrap()
+}
+
+type PgTransaction = Transaction<'static, Postgres>;
+
+struct Delivery {
+    event_id: Uuid,
+    endpoint_id: Uuid,
+    event: String,
+    payload: serde_json::Value,
+    n_retries: i16,
+    url: String,
+    secret: String,
+}
+
+/// Send the next event in the queue to its endpoint.
+#[tracing::instrument(
+    skip_all,
+    fields(
+        event_id=tracing::field::Empty,
+        endpoint_id=tracing::field::Empty
+    )
+)]
+pub async fn try_deliver_webhook(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+) -> Result<ExecutionOutcome, anyhow::Error> {
+    let (mut transaction, delivery) = match dequeue_delivery(pool).await? {
+        Some(next) => next,
+        None => return Ok(ExecutionOutcome::EmptyQueue),
+    };
+    Span::current()
+        .record("event_id", &display(delivery.event_id))
+        .record("endpoint_id", &display(delivery.endpoint_id));
+
+    let attempt = delivery.n_retries + 1;
+    let (status_code, error) = match send(http_client, &delivery).await {
+        Ok(status) if status.is_success() => (Some(status), None),
+        Ok(status) => (Some(status), Some(format!("Responded {}", status))),
+        Err(e) => (e.status(), Some(e.to_string())),
+    };
+    let outcome = match &error {
+        None => "delivered",
+        Some(_) if attempt < MAX_DELIVERY_ATTEMPTS => "retrying",
+        Some(_) => "failed",
+    };
+    if let Some(e) = &error {
+        tracing::warn!(
+            error.message = %e,
+            attempt,
+            "Failed to deliver a webhook event.",
+        );
+    }
+    log_attempt(
+        &mut transaction,
+        &delivery,
+        outcome,
+        status_code.map(|s| s.as_u16() as i16),
+        error.as_deref(),
+    )
+    .await?;
+    if outcome == "retrying" {
+        retry_delivery(&mut transaction, &delivery).await?;
+    } else {
+        delete_delivery(&mut transaction, &delivery).await?;
+    }
+    transaction.commit().await?;
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+async fn send(
+    http_client: &reqwest::Client,
+    delivery: &Delivery,
+) -> Result<reqwest::StatusCode, reqwest::Error> {
+    let body = delivery.payload.to_string();
+    let timestamp = Utc::now().timestamp();
+    let response = http_client
+        .post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Webhook-Id", delivery.event_id.to_string())
+        .header("X-Webhook-Event", &delivery.event)
+        .header(
+            "X-Webhook-Signature",
+            signature(&delivery.secret, timestamp, &body),
+        )
+        .body(body)
+        .send()
+        .await?;
+    Ok(response.status())
+}
+
+#[tracing::instrument(skip_all)]
+async fn dequeue_delivery(
+    pool: &PgPool,
+) -> Result<Option<(PgTransaction, Delivery)>, anyhow::Error> {
+    let mut transaction = pool.begin().await?;
+    let delivery = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT
+            q.event_id,
+            q.endpoint_id,
+            q.event,
+            q.payload,
+            q.n_retries,
+            e.url,
+            e.secret
+        FROM webhook_delivery_queue q
+        JOIN webhook_endpoints e ON e.endpoint_id = q.endpoint_id
+        WHERE q.execute_after <= now()
+        ORDER BY q.execute_after
+        FOR UPDATE OF q
+        SKIP LOCKED
+        LIMIT 1
+        "#,
+    )
+    .fetch_optional(&mut transaction)
+    .await?;
+    Ok(delivery.map(|delivery| (transaction, delivery)))
+}
+
+#[tracing::instrument(skip_all)]
+async fn delete_delivery(
+    transaction: &mut PgTransaction,
+    delivery: &Delivery,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM webhook_delivery_queue
+        WHERE event_id = $1 AND endpoint_id = $2
+        "#,
+        delivery.event_id,
+        delivery.endpoint_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+// Leave the event in the queue for another attempt, after a backoff
+#[tracing::instrument(skip_all)]
+async fn retry_delivery(
+    transaction: &mut PgTransaction,
+    delivery: &Delivery,
+) -> Result<(), anyhow::Error> {
+    let backoff = RETRY_BACKOFF_SECONDS * 2f64.powi(delivery.n_retries.into());
+    sqlx::query!(
+        r#"
+        UPDATE webhook_delivery_queue
+        SET
+            n_retries = n_retries + 1,
+            execute_after = now() + make_interval(secs => $3)
+        WHERE event_id = $1 AND endpoint_id = $2
+        "#,
+        delivery.event_id,
+        delivery.endpoint_id,
+        backoff
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(transaction, delivery))]
+async fn log_attempt(
+    transaction: &mut PgTransaction,
+    delivery: &Delivery,
+    outcome: &str,
+    status_code: Option<i16>,
+    error: Option<&str>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_delivery_log (
+            event_id,
+            endpoint_id,
+            event,
+            attempt,
+            outcome,
+            status_code,
+            error
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+        delivery.event_id,
+        delivery.endpoint_id,
+        delivery.event,
+        delivery.n_retries + 1,
+        outcome,
+        status_code,
+        error
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{signature, WebhookEvent};
+
+    #[test]
+    fn events_round_trip() {
+        for event in WebhookEvent::ALL {
+            assert_eq!(WebhookEvent::parse(event.as_str()), Ok(event));
+        }
+        assert!(WebhookEvent::parse("subscriber.deleted").is_err());
+    }
+
+    #[test]
+    fn the_signature_covers_the_timestamp_and_the_body() {
+        let expected = signature("secret", 1653900941, r#"{"id":1}"#);
+        assert!(expected.starts_with("t=1653900941,v1="));
+        assert_eq!(expected.len(), "t=1653900941,v1=".len() + 64);
+        assert_ne!(expected, signature("secret", 1653900942, r#"{"id":1}"#));
+        assert_ne!(expected, signature("secret", 1653900941, r#"{"id":2}"#));
+        assert_ne!(expected, signature("other", 1653900941, r#"{"id":1}"#));
+    }
+}
@@ -0,0 +1,149 @@
+//! Token buckets throttling the sends of the delivery workers. They are
+//! stored in Postgres so every consumer of every worker process draws
+//! from the same ones.
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::configuration::RateLimitSettings;
+use crate::domain::SubscriberEmail;
+use crate::metrics::RATE_LIMIT_WAIT;
+
+const PROVIDER_BUCKET: &str = "provider";
+
+pub struct SendRateLimiter {
+    messages_per_second: f64,
+    messages_per_second_per_domain: f64,
+}
+
+impl SendRateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            messages_per_second: settings.messages_per_second,
+            messages_per_second_per_domain: settings
+                .messages_per_second_per_domain,
+        }
+    }
+
+    /// Book a send to the recipient, within the limits of the provider and
+    /// of the recipient domain, and return how long to wait for our turn.
+    /// Callers wait without holding locks: other consumers keep booking
+    /// the sends after ours in the meantime.
+    #[tracing::instrument(skip(self, pool))]
+    pub async fn reserve(
+        &self,
+        pool: &PgPool,
+        recipient: &SubscriberEmail,
+    ) -> Result<Duration, sqlx::Error> {
+        let provider_wait =
+            take_token(pool, PROVIDER_BUCKET, self.messages_per_second).await?;
+        let domain_wait = take_token(
+            pool,
+            &format!("domain:{}", recipient.domain()),
+            self.messages_per_second_per_domain,
+        )
+        .await?;
+        let wait = provider_wait.max(domain_wait);
+        RATE_LIMIT_WAIT.observe(wait.as_secs_f64());
+        Ok(wait)
+    }
+
+    /// Forget the domain buckets that refilled since their last send, the
+    /// next one creates them again as full. Without this a bucket would be
+    /// kept for every domain ever mailed.
+    #[tracing::instrument(skip(self, pool))]
+    pub async fn forget_idle_buckets(
+        &self,
+        pool: &PgPool,
+    ) -> Result<u64, sqlx::Error> {
+        let per_second = self.messages_per_second_per_domain;
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM send_rate_buckets
+            WHERE
+                bucket LIKE 'domain:%' AND
+                tokens + $1 * extract(
+                    epoch FROM clock_timestamp() - refilled_at
+                )::float8 >= $2
+            "#,
+            per_second,
+            capacity(per_second)
+        )
+        .execute(pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Hold back every send until the provider accepts them again.
+    #[tracing::instrument(skip(self, pool))]
+    pub async fn back_off(
+        &self,
+        pool: &PgPool,
+        retry_after: Duration,
+    ) -> Result<(), sqlx::Error> {
+        // The bucket is left in debt so that the next token is available
+        // once the delay is over, unless sends are already booked past it
+        let debt = -retry_after.as_secs_f64() * self.messages_per_second;
+        sqlx::query!(
+            r#"
+            INSERT INTO send_rate_buckets (bucket, tokens, refilled_at)
+            VALUES ($1, $2, clock_timestamp())
+            ON CONFLICT (bucket) DO UPDATE SET
+                tokens = LEAST(
+                    $2,
+                    send_rate_buckets.tokens + $3 * extract(
+                        epoch FROM clock_timestamp() - send_rate_buckets.refilled_at
+                    )::float8
+                ),
+                refilled_at = clock_timestamp()
+            "#,
+            PROVIDER_BUCKET,
+            debt,
+            self.messages_per_second
+        )
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+// A bucket holds at most a second of sends, at least one
+fn capacity(per_second: f64) -> f64 {
+    per_second.max(1.0)
+}
+
+// Book a token from the bucket, even if it is empty, and return how long
+// to wait for it to be refilled.
+async fn take_token(
+    pool: &PgPool,
+    bucket: &str,
+    per_second: f64,
+) -> Result<Duration, sqlx::Error> {
+    // `clock_timestamp` rather than `now` so that the refill is measured
+    // once the row is locked, after the sends booked in the meantime
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO send_rate_buckets (bucket, tokens, refilled_at)
+        VALUES ($1, $2::float8 - 1, clock_timestamp())
+        ON CONFLICT (bucket) DO UPDATE SET
+            tokens = LEAST(
+                $2,
+                send_rate_buckets.tokens + $3 * extract(
+                    epoch FROM clock_timestamp() - send_rate_buckets.refilled_at
+                )::float8
+            ) - 1,
+            refilled_at = clock_timestamp()
+        RETURNING tokens
+        "#,
+        bucket,
+        capacity(per_second),
+        per_second
+    )
+    .fetch_one(pool)
+    .await?;
+    if row.tokens >= 0.0 {
+        Ok(Duration::ZERO)
+    } else {
+        Ok(Duration::from_secs_f64(-row.tokens / per_second))
+    }
+}
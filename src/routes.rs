@@ -1,10 +1,21 @@
 pub mod admin;
+mod archive;
+mod feeds;
 mod health_check;
 pub mod home;
 pub mod login;
+mod metrics;
+pub mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
+mod webhooks;
 
+pub use archive::*;
+pub use feeds::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
+pub use tracking::*;
+pub use webhooks::*;
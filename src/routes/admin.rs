@@ -1,7 +1,21 @@
 mod dashboard;
+pub mod export;
+mod layouts;
+mod lists;
 mod logout;
 pub mod newsletters;
 pub mod password;
+pub mod subscribers;
+mod suppressions;
+mod webhooks;
 
 pub use dashboard::admin_dashboard;
+pub use layouts::{create_layout, layout_page, layouts_page, update_layout};
+pub use lists::{create_list, lists_page};
 pub use logout::*;
+pub use suppressions::{
+    create_suppression, delete_suppression, suppressions_page,
+};
+pub use webhooks::{
+    create_webhook, delete_webhook, update_webhook, webhook_page, webhooks_page,
+};
@@ -4,11 +4,16 @@ use actix_web::{
     HttpResponse,
 };
 use anyhow::Context;
+use std::fmt::Write;
 
 use sqlx::PgPool;
 use uuid::Uuid;
 
-use crate::{authentication::middleware::UserId, utils::e500};
+use crate::{
+    authentication::middleware::UserId,
+    utils::e500,
+    workers::{get_live_workers, LiveWorker},
+};
 
 pub async fn admin_dashboard(
     user_id: ReqData<UserId>,
@@ -16,6 +21,7 @@ pub async fn admin_dashboard(
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = user_id.into_inner();
     let username = get_username(*user_id, &pool).await.map_err(e500)?;
+    let workers = get_live_workers(&pool).await.map_err(e500)?;
 
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
@@ -32,18 +38,58 @@ pub async fn admin_dashboard(
     <ol>
         <li><a href="/admin/password">Change Password</a></li>
         <li><a href="/admin/newsletters">Post Newsletters</a></li>
+        <li><a href="/admin/lists">Mailing lists</a></li>
+        <li><a href="/admin/layouts">Layouts</a></li>
+        <li><a href="/admin/subscribers">Subscribers</a></li>
+        <li><a href="/admin/suppressions">Suppressions</a></li>
+        <li><a href="/admin/webhooks">Webhooks</a></li>
         <li>
             <a href="javascript:document.logoutForm.submit()">Logout</a>
             <form name="logoutForm" action="/admin/logout" method="POST" hidden>
                 <input hidden type="submit" value="Logout">
             </form
     </ol>
+    <h2>Delivery workers</h2>
+    {}
 </body>
 </html>"#,
-            username
+            username,
+            workers_table(&workers)
         )))
 }
 
+fn workers_table(workers: &[LiveWorker]) -> String {
+    if workers.is_empty() {
+        return "<p>No delivery worker is running.</p>".into();
+    }
+    let mut rows = String::new();
+    for worker in workers {
+        writeln!(
+            rows,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td></tr>",
+            worker.worker_id,
+            worker.started_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            worker.heartbeat_at.format("%H:%M:%S UTC"),
+            worker.concurrency,
+            worker.deliveries_per_minute()
+        )
+        .unwrap();
+    }
+    format!(
+        r#"<table>
+        <tr>
+            <th>Worker</th>
+            <th>Started</th>
+            <th>Last heartbeat</th>
+            <th>Consumers</th>
+            <th>Deliveries/min</th>
+        </tr>
+        {}
+    </table>"#,
+        rows
+    )
+}
+
 #[tracing::instrument(skip(pool))]
 pub async fn get_username(
     user_id: Uuid,
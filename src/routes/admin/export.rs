@@ -0,0 +1,215 @@
+use actix_web::web::Bytes;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::stream::BoxStream;
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::attachment;
+
+// Number of encoded rows buffered between the database and the client.
+// A slow client stops the database stream once the buffer is full.
+const CHANNEL_CAPACITY: usize = 64;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        ExportFormat::Csv
+    }
+}
+
+impl ExportFormat {
+    fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ExportParameters {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+#[derive(Serialize)]
+struct SubscriptionRecord {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+impl SubscriptionRecord {
+    const COLUMNS: &'static [&'static str] =
+        &["id", "email", "name", "status", "subscribed_at"];
+}
+
+#[derive(Serialize)]
+struct NewsletterIssueRecord {
+    newsletter_issue_id: Uuid,
+    title: String,
+    text_content: String,
+    html_content: String,
+    published_at: String,
+}
+
+impl NewsletterIssueRecord {
+    const COLUMNS: &'static [&'static str] = &[
+        "newsletter_issue_id",
+        "title",
+        "text_content",
+        "html_content",
+        "published_at",
+    ];
+}
+
+#[tracing::instrument(name = "Export subscriptions", skip(pool))]
+pub async fn export_subscriptions(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    export(
+        pool.get_ref().clone(),
+        parameters.format,
+        "subscriptions",
+        SubscriptionRecord::COLUMNS,
+        |pool| {
+            sqlx::query_as!(
+                SubscriptionRecord,
+                r#"
+                SELECT id, email, name, status, subscribed_at
+                FROM subscriptions
+                ORDER BY subscribed_at
+                "#
+            )
+            .fetch(pool)
+        },
+    )
+}
+
+#[tracing::instrument(name = "Export newsletter issues", skip(pool))]
+pub async fn export_newsletter_issues(
+    parameters: web::Query<ExportParameters>,
+    pool: web::Data<PgPool>,
+) -> HttpResponse {
+    export(
+        pool.get_ref().clone(),
+        parameters.format,
+        "newsletter_issues",
+        NewsletterIssueRecord::COLUMNS,
+        |pool| {
+            sqlx::query_as!(
+                NewsletterIssueRecord,
+                r#"
+                SELECT
+                    newsletter_issue_id,
+                    title,
+                    text_content,
+                    html_content,
+                    published_at
+                FROM newsletter_issues
+                ORDER BY published_at
+                "#
+            )
+            .fetch(pool)
+        },
+    )
+}
+
+// Stream the rows returned by `query` to the client as they come out of
+// the database cursor, without collecting them first.
+//
+// The query runs in its own task, which owns the pool handle the row stream
+// borrows from, and hands encoded rows over through a bounded channel.
+// CSV exports start with a header row, even when there are no rows.
+fn export<T>(
+    pool: PgPool,
+    format: ExportFormat,
+    name: &str,
+    columns: &'static [&'static str],
+    query: fn(&PgPool) -> BoxStream<'_, Result<T, sqlx::Error>>,
+) -> HttpResponse
+where
+    T: Serialize + Send + 'static,
+{
+    let (mut sender, receiver) =
+        mpsc::channel::<Result<Bytes, anyhow::Error>>(CHANNEL_CAPACITY);
+
+    tokio::spawn(async move {
+        if let ExportFormat::Csv = format {
+            if sender.send(encode_header(columns)).await.is_err() {
+                return;
+            }
+        }
+        let mut rows = query(&pool);
+        while let Some(row) = rows.next().await {
+            let chunk = row
+                .map_err(anyhow::Error::from)
+                .and_then(|row| encode(&row, format));
+            let failed = chunk.is_err();
+            if let Err(e) = &chunk {
+                tracing::error!(
+                    error.cause_chain = ?e,
+                    error.message = %e,
+                    "Failed to export a row, aborting the export",
+                );
+            }
+            // The client went away or we could not produce the row:
+            // either way there is no point in carrying on.
+            if sender.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(attachment(format!("{}.{}", name, format.extension())))
+        .streaming(receiver)
+}
+
+fn encode_header(columns: &[&str]) -> Result<Bytes, anyhow::Error> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(columns)?;
+    Ok(Bytes::from(writer.into_inner()?))
+}
+
+fn encode<T: Serialize>(
+    row: &T,
+    format: ExportFormat,
+) -> Result<Bytes, anyhow::Error> {
+    let bytes = match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(false)
+                .from_writer(Vec::new());
+            writer.serialize(row)?;
+            writer.into_inner()?
+        }
+        ExportFormat::Ndjson => {
+            let mut line = serde_json::to_vec(row)?;
+            line.push(b'\n');
+            line
+        }
+    };
+    Ok(Bytes::from(bytes))
+}
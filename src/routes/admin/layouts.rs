@@ -0,0 +1,314 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::domain::Layout;
+use crate::layouts::get_layouts;
+use crate::utils::{e500, is_unique_violation, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+    html: String,
+    // A checkbox, only sent when checked
+    is_default: Option<String>,
+}
+
+struct LayoutDetails {
+    name: String,
+    html: String,
+    is_default: bool,
+}
+
+pub async fn layouts_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for layout in get_layouts(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/layouts/{}">{}</a></td><td>{}</td></tr>"#,
+            layout.layout_id,
+            htmlescape::encode_minimal(&layout.name),
+            if layout.is_default { "yes" } else { "no" },
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Layouts</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>Name</th><th>Default</th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/layouts" method="post">
+        {form_fields}
+        <button type="submit">Create layout</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            form_fields = form_fields("", "{{ content }}", false),
+        )))
+}
+
+pub async fn layout_page(
+    layout_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let layout =
+        match get_layout_details(&pool, layout_id).await.map_err(e500)? {
+            Some(layout) => layout,
+            None => return Ok(HttpResponse::NotFound().finish()),
+        };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Layout</title>
+</head>
+<body>
+    {msg_html}
+    <form action="/admin/layouts/{layout_id}" method="post">
+        {form_fields}
+        <button type="submit">Save</button>
+    </form>
+    <p><a href="/admin/layouts">&lt;- Back</a></p>
+</body>
+</html>"#,
+            form_fields =
+                form_fields(&layout.name, &layout.html, layout.is_default),
+        )))
+}
+
+fn form_fields(name: &str, html: &str, is_default: bool) -> String {
+    format!(
+        r#"<label>Name
+            <input type="text" placeholder="Enter the layout name" name="name" value="{}">
+        </label>
+        <br>
+        <label>HTML, with a {{{{ content }}}} slot for the issues
+            <textarea name="html" rows="20" cols="80">{}</textarea>
+        </label>
+        <br>
+        <label>
+            <input type="checkbox" name="is_default"{}>
+            Use for confirmation emails and preselect for new issues
+        </label>
+        <br>"#,
+        htmlescape::encode_minimal(name),
+        htmlescape::encode_minimal(html),
+        if is_default { " checked" } else { "" }
+    )
+}
+
+#[tracing::instrument(name = "Create a layout", skip(form, pool))]
+pub async fn create_layout(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        name,
+        html,
+        is_default,
+    } = form.into_inner();
+    let name = name.trim();
+    let layout = match validate(name, html) {
+        Ok(layout) => layout,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other("/admin/layouts"));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let layout_id = Uuid::new_v4();
+    if is_default.is_some() {
+        unset_default_layout(&mut transaction, layout_id)
+            .await
+            .context("Failed to unset the default layout")
+            .map_err(e500)?;
+    }
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO layouts (layout_id, name, html, is_default)
+        VALUES ($1, $2, $3, $4)
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        layout_id,
+        name,
+        layout.as_ref(),
+        is_default.is_some()
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to store the new layout")
+    .map_err(e500)?;
+
+    let name = htmlescape::encode_minimal(name);
+    if result.rows_affected() == 0 {
+        FlashMessage::error(format!("A layout named {} already exists.", name))
+            .send();
+        return Ok(see_other("/admin/layouts"));
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store a new layout")
+        .map_err(e500)?;
+    FlashMessage::info(format!("The {} layout has been created.", name)).send();
+    Ok(see_other("/admin/layouts"))
+}
+
+#[tracing::instrument(name = "Update a layout", skip(form, pool))]
+pub async fn update_layout(
+    layout_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let layout_id = layout_id.into_inner();
+    let location = format!("/admin/layouts/{}", layout_id);
+    let FormData {
+        name,
+        html,
+        is_default,
+    } = form.into_inner();
+    let name = name.trim();
+    let layout = match validate(name, html) {
+        Ok(layout) => layout,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    if is_default.is_some() {
+        unset_default_layout(&mut transaction, layout_id)
+            .await
+            .context("Failed to unset the default layout")
+            .map_err(e500)?;
+    }
+    let result = sqlx::query!(
+        r#"
+        UPDATE layouts
+        SET name = $2, html = $3, is_default = $4, updated_at = now()
+        WHERE layout_id = $1
+        "#,
+        layout_id,
+        name,
+        layout.as_ref(),
+        is_default.is_some()
+    )
+    .execute(&mut transaction)
+    .await;
+    match result {
+        Ok(result) if result.rows_affected() == 0 => {
+            return Ok(HttpResponse::NotFound().finish());
+        }
+        Ok(_) => {}
+        Err(e) if is_unique_violation(&e) => {
+            FlashMessage::error(format!(
+                "A layout named {} already exists.",
+                htmlescape::encode_minimal(name)
+            ))
+            .send();
+            return Ok(see_other(&location));
+        }
+        Err(e) => {
+            return Err(e500(
+                anyhow::Error::new(e).context("Failed to update the layout"),
+            ))
+        }
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update a layout")
+        .map_err(e500)?;
+
+    FlashMessage::info("The layout has been updated.").send();
+    Ok(see_other(&location))
+}
+
+fn validate(name: &str, html: String) -> Result<Layout, String> {
+    if name.is_empty() {
+        return Err("The layout name cannot be empty.".into());
+    }
+    Layout::parse(html)
+}
+
+// There is at most one default layout
+#[tracing::instrument(skip(transaction))]
+async fn unset_default_layout(
+    transaction: &mut Transaction<'_, Postgres>,
+    new_default_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE layouts SET is_default = false
+        WHERE is_default AND layout_id <> $1
+        "#,
+        new_default_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_layout_details(
+    pool: &PgPool,
+    layout_id: Uuid,
+) -> Result<Option<LayoutDetails>, anyhow::Error> {
+    sqlx::query_as!(
+        LayoutDetails,
+        r#"
+        SELECT name, html, is_default
+        FROM layouts
+        WHERE layout_id = $1
+        "#,
+        layout_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the layout")
+}
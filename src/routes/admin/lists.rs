@@ -0,0 +1,150 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    name: String,
+    #[serde(default)]
+    description: String,
+}
+
+struct ListOverview {
+    list_id: Uuid,
+    name: String,
+    description: String,
+    is_default: bool,
+    confirmed_members: i64,
+}
+
+pub async fn lists_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for list in get_list_overviews(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&list.name),
+            htmlescape::encode_minimal(&list.description),
+            list.list_id,
+            if list.is_default { "yes" } else { "no" },
+            list.confirmed_members
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Mailing lists</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr>
+            <th>Name</th>
+            <th>Description</th>
+            <th>Id</th>
+            <th>Default</th>
+            <th>Confirmed members</th>
+        </tr>
+        {rows_html}
+    </table>
+    <form action="/admin/lists" method="post">
+        <label>Name
+            <input type="text" placeholder="Enter the list name" name="name">
+        </label>
+        <br>
+        <label>Description
+            <input type="text" placeholder="What is it about?" name="description">
+        </label>
+        <br>
+        <button type="submit">Create list</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(name = "Create a mailing list", skip(form, pool))]
+pub async fn create_list(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData { name, description } = form.into_inner();
+    let name = name.trim();
+    if name.is_empty() {
+        FlashMessage::error("The list name cannot be empty.").send();
+        return Ok(see_other("/admin/lists"));
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO lists (list_id, name, description, created_at)
+        VALUES ($1, $2, $3, now())
+        ON CONFLICT (name) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        name,
+        description.trim()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the new mailing list")
+    .map_err(e500)?;
+
+    let name = htmlescape::encode_minimal(name);
+    if result.rows_affected() == 0 {
+        FlashMessage::error(format!("A list named {} already exists.", name))
+            .send();
+    } else {
+        FlashMessage::info(format!("The {} list has been created.", name))
+            .send();
+    }
+    Ok(see_other("/admin/lists"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_list_overviews(
+    pool: &PgPool,
+) -> Result<Vec<ListOverview>, anyhow::Error> {
+    sqlx::query_as!(
+        ListOverview,
+        r#"
+        SELECT
+            l.list_id,
+            l.name,
+            l.description,
+            l.is_default,
+            COUNT(s.id) AS "confirmed_members!"
+        FROM lists l
+        LEFT JOIN list_memberships m
+            ON m.list_id = l.list_id AND m.confirmed_at IS NOT NULL
+        LEFT JOIN subscriptions s
+            ON s.id = m.subscriber_id AND s.status = 'confirmed'
+        GROUP BY l.list_id
+        ORDER BY l.name
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the mailing lists")
+}
@@ -1,5 +1,9 @@
+mod delivery;
 mod get;
+mod issue;
 mod post;
 
+pub use delivery::*;
 pub use get::*;
+pub use issue::*;
 pub use post::*;
@@ -0,0 +1,281 @@
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::utils::{e500, see_other};
+
+#[derive(Clone, Copy, Debug)]
+enum DeliveryAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+impl DeliveryAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryAction::Pause => "pause",
+            DeliveryAction::Resume => "resume",
+            DeliveryAction::Cancel => "cancel",
+        }
+    }
+
+    // The delivery status an issue must have for the action to apply
+    fn applies_to(&self, delivery_status: &str) -> bool {
+        match self {
+            DeliveryAction::Pause => delivery_status == "active",
+            DeliveryAction::Resume => delivery_status == "paused",
+            DeliveryAction::Cancel => delivery_status != "cancelled",
+        }
+    }
+
+    fn past_participle(&self) -> &'static str {
+        match self {
+            DeliveryAction::Pause => "paused",
+            DeliveryAction::Resume => "resumed",
+            DeliveryAction::Cancel => "cancelled",
+        }
+    }
+
+    fn next_status(&self) -> &'static str {
+        match self {
+            DeliveryAction::Pause => "paused",
+            DeliveryAction::Resume => "active",
+            DeliveryAction::Cancel => "cancelled",
+        }
+    }
+}
+
+#[tracing::instrument(name = "Pause the delivery of an issue", skip_all)]
+pub async fn pause_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_delivery(
+        &pool,
+        newsletter_issue_id.into_inner(),
+        *user_id.into_inner(),
+        DeliveryAction::Pause,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Resume the delivery of an issue", skip_all)]
+pub async fn resume_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_delivery(
+        &pool,
+        newsletter_issue_id.into_inner(),
+        *user_id.into_inner(),
+        DeliveryAction::Resume,
+    )
+    .await
+}
+
+#[tracing::instrument(name = "Cancel the delivery of an issue", skip_all)]
+pub async fn cancel_issue(
+    newsletter_issue_id: web::Path<Uuid>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    change_delivery(
+        &pool,
+        newsletter_issue_id.into_inner(),
+        *user_id.into_inner(),
+        DeliveryAction::Cancel,
+    )
+    .await
+}
+
+async fn change_delivery(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+    user_id: Uuid,
+    action: DeliveryAction,
+) -> Result<HttpResponse, actix_web::Error> {
+    let location = format!("/admin/newsletters/{}", newsletter_issue_id);
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let delivery_status =
+        match lock_issue(&mut transaction, newsletter_issue_id)
+            .await
+            .context("Failed to retrieve the delivery status of the issue")
+            .map_err(e500)?
+        {
+            Some(delivery_status) => delivery_status,
+            None => return Ok(HttpResponse::NotFound().finish()),
+        };
+    if !action.applies_to(&delivery_status) {
+        FlashMessage::error(format!(
+            "The delivery of this issue is {}, it cannot be {}.",
+            delivery_status,
+            action.past_participle()
+        ))
+        .send();
+        return Ok(see_other(&location));
+    }
+
+    let n_sent = count_sent(&mut transaction, newsletter_issue_id)
+        .await
+        .context("Failed to count the recipients of the issue")
+        .map_err(e500)?;
+    let n_remaining = match action {
+        DeliveryAction::Cancel => {
+            remove_tasks(&mut transaction, newsletter_issue_id).await
+        }
+        _ => count_tasks(&mut transaction, newsletter_issue_id).await,
+    }
+    .context("Failed to go through the delivery queue of the issue")
+    .map_err(e500)?;
+    record_action(
+        &mut transaction,
+        newsletter_issue_id,
+        user_id,
+        action,
+        n_sent,
+        n_remaining,
+    )
+    .await
+    .context("Failed to record the delivery action")
+    .map_err(e500)?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a delivery")
+        .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "The delivery has been {}. {} recipients had already received \
+        the issue.",
+        action.past_participle(),
+        n_sent
+    ))
+    .send();
+    Ok(see_other(&location))
+}
+
+// Returns the delivery status of the issue, `None` if it does not exist.
+// The issue stays locked until the transaction ends, so that concurrent
+// actions are applied one after the other.
+#[tracing::instrument(skip(transaction))]
+async fn lock_issue(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<Option<String>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT delivery_status FROM newsletter_issues
+        WHERE newsletter_issue_id = $1
+        FOR UPDATE
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.delivery_status))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn count_sent(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM issue_delivery_log
+        WHERE newsletter_issue_id = $1 AND outcome = 'sent'
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(row.count)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn count_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COUNT(*) AS "count!" FROM issue_delivery_queue
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok(row.count)
+}
+
+// Empty the queue of an issue. Tasks being executed are waited for.
+#[tracing::instrument(skip(transaction))]
+async fn remove_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+) -> Result<i64, sqlx::Error> {
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM issue_delivery_queue WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(result.rows_affected() as i64)
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn record_action(
+    transaction: &mut Transaction<'_, Postgres>,
+    newsletter_issue_id: Uuid,
+    user_id: Uuid,
+    action: DeliveryAction,
+    n_sent: i64,
+    n_remaining: i64,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET delivery_status = $2
+        WHERE newsletter_issue_id = $1
+        "#,
+        newsletter_issue_id,
+        action.next_status()
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query!(
+        r#"
+        INSERT INTO issue_delivery_actions (
+            action_id,
+            newsletter_issue_id,
+            action,
+            user_id,
+            n_sent,
+            n_remaining
+        )
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        newsletter_issue_id,
+        action.as_str(),
+        user_id,
+        n_sent,
+        n_remaining
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
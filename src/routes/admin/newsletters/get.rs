@@ -1,8 +1,12 @@
-use actix_web::{http::header::ContentType, HttpResponse};
+use actix_web::{http::header::ContentType, web, HttpResponse};
 use actix_web_flash_messages::IncomingFlashMessages;
+use sqlx::PgPool;
 use std::fmt::Write;
 
 use crate::{
+    layouts::get_layouts,
+    mailing_lists::get_lists,
+    newsletter_issues::get_all_issue_stats,
     session_state::TypedSession,
     utils::{e500, see_other},
 };
@@ -10,6 +14,7 @@ use crate::{
 pub async fn newsletter_form(
     session: TypedSession,
     flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let idempotency_key = uuid::Uuid::new_v4();
 
@@ -22,6 +27,58 @@ pub async fn newsletter_form(
         writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
     }
 
+    let mut list_options = String::new();
+    for list in get_lists(&pool).await.map_err(e500)? {
+        writeln!(
+            list_options,
+            r#"<option value="{}"{}>{}</option>"#,
+            list.list_id,
+            if list.is_default { " selected" } else { "" },
+            htmlescape::encode_minimal(&list.name)
+        )
+        .unwrap();
+    }
+
+    // Issues are sent without a layout unless one is picked: the default
+    // layout is the one of the confirmation emails.
+    let mut layout_options = String::new();
+    for layout in get_layouts(&pool).await.map_err(e500)? {
+        writeln!(
+            layout_options,
+            r#"<option value="{}">{}</option>"#,
+            layout.layout_id,
+            htmlescape::encode_minimal(&layout.name)
+        )
+        .unwrap();
+    }
+
+    let mut issue_rows = String::new();
+    for issue in get_all_issue_stats(&pool).await.map_err(e500)? {
+        let (opens, clicks) = if issue.tracking_enabled {
+            (
+                issue.unique_opens.to_string(),
+                issue.unique_clicks.to_string(),
+            )
+        } else {
+            ("-".into(), "-".into())
+        };
+        writeln!(
+            issue_rows,
+            r#"<tr><td><a href="/admin/newsletters/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            issue.newsletter_issue_id,
+            htmlescape::encode_minimal(&issue.title),
+            htmlescape::encode_minimal(&issue.published_at),
+            issue.status(),
+            issue.queued,
+            issue.retrying,
+            issue.sent,
+            issue.failed,
+            opens,
+            clicks
+        )
+        .unwrap();
+    }
+
     Ok(HttpResponse::Ok()
         .content_type(ContentType::html())
         .body(format!(
@@ -40,18 +97,57 @@ pub async fn newsletter_form(
                         <input type="text" placeholder="Enter Title" name="title">
                     </label>
                     <br/>
+                    <label>Send to
+                        <select name="list_id" multiple required>
+                            {list_options}
+                        </select>
+                    </label>
+                    <br/>
+                    <label>Segment
+                        <input type="text" placeholder="e.g. tag beta and subscribed_at >= 2022-03-01" name="segment">
+                    </label>
+                    <br/>
+                    <label>Markdown Content
+                        <textarea name="markdown_content" placeholder="Write in Markdown, or fill in both contents below"></textarea>
+                    </label>
+                    <br/>
                     <label>Text Content
-                        <textarea name="text_content" placeholder="Your Email Body Here" required>
+                        <textarea name="text_content" placeholder="Your Email Body Here">
                         </textarea>
                     </label>
                     <br/>
                     <label>HTML Content
-                        <textarea name="html_content" placeholder="Your Email Body Here" required>
+                        <textarea name="html_content" placeholder="Your Email Body Here">
                         </textarea>
                     </label>
+                    <p>Personalise the title and content with {{{{ subscriber.name }}}},
+                    {{{{ subscriber.email }}}}, {{{{ unsubscribe_url }}}} or
+                    {{{{ subscriber.fields.plan | default: "free" }}}}.</p>
+                    <label>Layout
+                        <select name="layout_id">
+                            <option value="">No layout</option>
+                            {layout_options}
+                        </select>
+                    </label>
+                    <br/>
+                    <label>
+                        <input type="checkbox" name="tracking">
+                        Track opens and clicks
+                    </label>
+                    <br/>
+                    <label>
+                        <input type="checkbox" name="show_in_archive">
+                        Show in the public archive
+                    </label>
+                    <br/>
                     <input hidden type="text" name="idempotency_key" value="{idempotency_key}">
                     <button type="submit">Post</button>
                 </form>
+                <h2>Issues</h2>
+                <table>
+                    <tr><th>Title</th><th>Published</th><th>Status</th><th>Queued</th><th>Retrying</th><th>Sent</th><th>Failed</th><th>Unique opens</th><th>Unique clicks</th></tr>
+                    {issue_rows}
+                </table>
             </body>
             </html>"#,
         )))
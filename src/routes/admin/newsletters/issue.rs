@@ -0,0 +1,211 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::IncomingFlashMessages;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::newsletter_issues::get_issue_stats;
+use crate::utils::e500;
+
+// How many failed deliveries are listed on the page of an issue
+const FAILURES_SHOWN: i64 = 50;
+
+struct FailedDelivery {
+    subscriber_email: String,
+    n_attempts: i16,
+    error: Option<String>,
+}
+
+struct DeliveryAction {
+    action: String,
+    username: String,
+    n_sent: i64,
+    n_remaining: i64,
+    performed_at: DateTime<Utc>,
+}
+
+pub async fn issue_page(
+    newsletter_issue_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let newsletter_issue_id = newsletter_issue_id.into_inner();
+    let issue = match get_issue_stats(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let format_time = |t: Option<DateTime<Utc>>| match t {
+        Some(t) => t.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => "-".into(),
+    };
+    let ended_at = if issue.is_delivering() {
+        "In progress".into()
+    } else {
+        format_time(issue.ended_at)
+    };
+    let throughput = match issue.throughput() {
+        Some(t) => format!("{:.1} emails per minute", t),
+        None => "-".into(),
+    };
+    let tracking_html = if issue.tracking_enabled {
+        format!(
+            "<tr><th>Unique opens</th><td>{}</td></tr>\n\
+            <tr><th>Unique clicks</th><td>{}</td></tr>",
+            issue.unique_opens, issue.unique_clicks
+        )
+    } else {
+        "<tr><th>Tracking</th><td>Disabled</td></tr>".into()
+    };
+
+    let mut failures_html = String::new();
+    for failure in get_failed_deliveries(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        writeln!(
+            failures_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+            htmlescape::encode_minimal(&failure.subscriber_email),
+            failure.n_attempts,
+            htmlescape::encode_minimal(failure.error.as_deref().unwrap_or(""))
+        )
+        .unwrap();
+    }
+
+    let mut controls_html = String::new();
+    let actions: &[(&str, &str)] = match issue.delivery_status.as_str() {
+        "active" if issue.is_delivering() => {
+            &[("pause", "Pause"), ("cancel", "Cancel")]
+        }
+        "paused" => &[("resume", "Resume"), ("cancel", "Cancel")],
+        _ => &[],
+    };
+    for (action, label) in actions {
+        writeln!(
+            controls_html,
+            r#"<form action="/admin/newsletters/{}/{}" method="post"><button type="submit">{}</button></form>"#,
+            newsletter_issue_id, action, label
+        )
+        .unwrap();
+    }
+
+    let mut actions_html = String::new();
+    for action in get_delivery_actions(&pool, newsletter_issue_id)
+        .await
+        .map_err(e500)?
+    {
+        writeln!(
+            actions_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            format_time(Some(action.performed_at)),
+            htmlescape::encode_minimal(&action.action),
+            htmlescape::encode_minimal(&action.username),
+            action.n_sent,
+            action.n_remaining
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Newsletter issue</title>
+</head>
+<body>
+    {msg_html}
+    <h1>{title}</h1>
+    <table>
+        <tr><th>Published</th><td>{published_at}</td></tr>
+        <tr><th>Status</th><td>{status}</td></tr>
+        <tr><th>Queued</th><td>{queued}</td></tr>
+        <tr><th>Retrying</th><td>{retrying}</td></tr>
+        <tr><th>Sent</th><td>{sent}</td></tr>
+        <tr><th>Failed</th><td>{failed}</td></tr>
+        <tr><th>Delivery started</th><td>{started_at}</td></tr>
+        <tr><th>Delivery ended</th><td>{ended_at}</td></tr>
+        <tr><th>Throughput</th><td>{throughput}</td></tr>
+        {tracking_html}
+    </table>
+    {controls_html}
+    <h2>Delivery actions</h2>
+    <table>
+        <tr><th>Date</th><th>Action</th><th>By</th><th>Already sent</th><th>Remaining</th></tr>
+        {actions_html}
+    </table>
+    <h2>Failed deliveries</h2>
+    <table>
+        <tr><th>Email</th><th>Attempts</th><th>Error</th></tr>
+        {failures_html}
+    </table>
+    <p><a href="/admin/newsletters">&lt;- Back</a></p>
+</body>
+</html>"#,
+            title = htmlescape::encode_minimal(&issue.title),
+            published_at = htmlescape::encode_minimal(&issue.published_at),
+            status = issue.status(),
+            queued = issue.queued,
+            retrying = issue.retrying,
+            sent = issue.sent,
+            failed = issue.failed,
+            started_at = format_time(issue.started_at),
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_failed_deliveries(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<FailedDelivery>, anyhow::Error> {
+    sqlx::query_as!(
+        FailedDelivery,
+        r#"
+        SELECT subscriber_email, n_attempts, error
+        FROM issue_delivery_log
+        WHERE newsletter_issue_id = $1 AND outcome = 'failed'
+        ORDER BY logged_at DESC
+        LIMIT $2
+        "#,
+        newsletter_issue_id,
+        FAILURES_SHOWN
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the failed deliveries of the issue")
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_delivery_actions(
+    pool: &PgPool,
+    newsletter_issue_id: Uuid,
+) -> Result<Vec<DeliveryAction>, anyhow::Error> {
+    sqlx::query_as!(
+        DeliveryAction,
+        r#"
+        SELECT a.action, u.username, a.n_sent, a.n_remaining, a.performed_at
+        FROM issue_delivery_actions a
+        JOIN users u ON u.user_id = a.user_id
+        WHERE a.newsletter_issue_id = $1
+        ORDER BY a.performed_at
+        "#,
+        newsletter_issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the delivery actions of the issue")
+}
@@ -5,17 +5,26 @@ use actix_web::{web, HttpResponse, ResponseError};
 use actix_web_flash_messages::FlashMessage;
 use anyhow::Context;
 
-use sqlx::{PgPool, Postgres, Transaction};
+use sqlx::{PgPool, Postgres, Row, Transaction};
 use uuid::Uuid;
 
 use crate::authentication::middleware::UserId;
+use crate::domain::{Markdown, Segment, SqlParameter, Template};
+use crate::email_tracking::{store_links, tracked_links};
+use crate::html_sanitizer::HtmlSanitizer;
 
 use crate::idempotency::{
     save_response, try_processing, IdempotencyKey, NextAction,
 };
+use crate::issue_delivery_worker::finish_delivery_if_done;
+use crate::layouts::get_layout;
+use crate::mailing_lists::select_lists;
+use crate::newsletter_issues::slug;
+use crate::outgoing_webhooks::{enqueue_event, WebhookEvent};
 use crate::routes::error_chain_fmt;
+use crate::telemetry::current_trace_context;
 
-use crate::utils::{e400, e500, see_other};
+use crate::utils::{e400, e500, see_other, HtmlForm};
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
@@ -59,9 +68,10 @@ impl ResponseError for PublishError {
     fields(user_id=%*user_id)
 )]
 pub async fn publish_newsletter(
-    form: web::Form<FormData>,
+    form: HtmlForm<FormData>,
     user_id: ReqData<UserId>,
     pool: web::Data<PgPool>,
+    sanitizer: web::Data<HtmlSanitizer>,
 ) -> Result<HttpResponse, actix_web::Error> {
     let user_id = *user_id.into_inner();
     // We must destructure the form to avoid upsetting the borrow checker
@@ -70,10 +80,54 @@ pub async fn publish_newsletter(
         text_content,
         html_content,
         idempotency_key,
+        list_ids,
+        segment,
+        markdown_content,
+        layout_id,
+        tracking,
+        show_in_archive,
     } = form.into_inner();
 
     let idempotency_key: IdempotencyKey =
         idempotency_key.try_into().map_err(e400)?;
+    let list_ids = select_lists(&pool, &list_ids)
+        .await
+        .map_err(e500)?
+        .ok_or_else(|| e400("Unknown mailing list."))?;
+    let segment = match segment.trim() {
+        "" => None,
+        segment => Some(Segment::parse(segment).map_err(e400)?),
+    };
+    let (html_content, text_content, markdown) = match markdown_content.trim() {
+        "" if html_content.trim().is_empty()
+            || text_content.trim().is_empty() =>
+        {
+            return Err(e400(
+                "Provide either Markdown or both HTML and text content.",
+            ));
+        }
+        "" => (html_content, text_content, None),
+        _ => {
+            let markdown = Markdown::parse(markdown_content).map_err(e400)?;
+            (markdown.to_html(), markdown.to_text(), Some(markdown))
+        }
+    };
+    // Layouts are trusted, only the content of the issue is sanitized
+    let (html_content, sanitization_report) = sanitizer.sanitize(&html_content);
+    let html_content = match layout_id {
+        Some(layout_id) => get_layout(&pool, layout_id)
+            .await
+            .map_err(e500)?
+            .ok_or_else(|| e400("Unknown layout."))?
+            .render(&html_content)
+            .map_err(e400)?,
+        None => html_content,
+    };
+    // Merge tags are rendered by the delivery worker,
+    // catch the mistakes before anything is sent.
+    for content in [&title, &text_content, &html_content] {
+        Template::parse(content).map_err(e400)?;
+    }
 
     let mut transaction = match try_processing(&pool, &idempotency_key, user_id)
         .await
@@ -86,19 +140,51 @@ pub async fn publish_newsletter(
         }
     };
 
-    let issue_id = insert_newsletter_issue(
+    let issue = NewIssue {
+        title: &title,
+        text_content: &text_content,
+        html_content: &html_content,
+        markdown: markdown.as_ref(),
+        layout_id,
+        segment: segment.as_ref(),
+        tracking_enabled: tracking.is_some(),
+        show_in_archive: show_in_archive.is_some(),
+    };
+    let issue_id = insert_newsletter_issue(&mut transaction, &issue)
+        .await
+        .context("Failed to store newsletter issue details")
+        .map_err(e500)?;
+    if issue.tracking_enabled {
+        store_links(&mut transaction, issue_id, &tracked_links(&html_content))
+            .await
+            .context("Failed to store the links of the newsletter issue")
+            .map_err(e500)?;
+    }
+
+    enqueue_delivery_tasks(
         &mut transaction,
-        &title,
-        &text_content,
-        &html_content,
+        issue_id,
+        &list_ids,
+        segment.as_ref(),
     )
     .await
-    .context("Failed to store newsletter issue details")
+    .context("Failed to enqueue delivery tasks")
     .map_err(e500)?;
-
-    enqueue_delivery_tasks(&mut transaction, issue_id)
+    enqueue_event(
+        &mut transaction,
+        WebhookEvent::IssuePublished,
+        serde_json::json!({
+            "newsletter_issue_id": issue_id,
+            "title": title,
+        }),
+    )
+    .await
+    .context("Failed to enqueue the published webhook event")
+    .map_err(e500)?;
+    // Nobody may be receiving the issue
+    finish_delivery_if_done(&mut transaction, issue_id)
         .await
-        .context("Failed to enqueue delivery tasks")
+        .context("Failed to check whether the delivery is finished")
         .map_err(e500)?;
 
     // move send email logic to worker
@@ -109,6 +195,13 @@ pub async fn publish_newsletter(
         save_response(transaction, &idempotency_key, user_id, response)
             .await
             .map_err(e500)?;
+    if !sanitization_report.is_empty() {
+        FlashMessage::warning(htmlescape::encode_minimal(&format!(
+            "The HTML content was sanitized. {}",
+            sanitization_report
+        )))
+        .send();
+    }
     success_message().send();
     Ok(response)
 }
@@ -122,13 +215,48 @@ fn success_message() -> FlashMessage {
 
 // removed basic authentication
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Default)]
 pub struct FormData {
     pub title: String,
+    // Both are generated when the issue is written in Markdown
+    #[serde(default)]
     pub html_content: String,
+    #[serde(default)]
     pub text_content: String,
     // New field
     pub idempotency_key: String,
+    // Target lists, the default lists are used if there is none
+    #[serde(
+        default,
+        rename = "list_id",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub list_ids: Vec<Uuid>,
+    // Only send to the subscribers matching this segment, if any
+    #[serde(default)]
+    pub segment: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub markdown_content: String,
+    // The HTML content is put in this layout before being stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout_id: Option<Uuid>,
+    // A checkbox to track opens and clicks, only sent when checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tracking: Option<String>,
+    // A checkbox to publish the issue in the public archive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub show_in_archive: Option<String>,
+}
+
+struct NewIssue<'a> {
+    title: &'a str,
+    text_content: &'a str,
+    html_content: &'a str,
+    markdown: Option<&'a Markdown>,
+    layout_id: Option<Uuid>,
+    segment: Option<&'a Segment>,
+    tracking_enabled: bool,
+    show_in_archive: bool,
 }
 
 // confirmed subscriber to worker
@@ -136,9 +264,7 @@ pub struct FormData {
 #[tracing::instrument(skip_all)]
 async fn insert_newsletter_issue(
     transaction: &mut Transaction<'_, Postgres>,
-    title: &str,
-    text_content: &str,
-    html_content: &str,
+    issue: &NewIssue<'_>,
 ) -> Result<Uuid, sqlx::Error> {
     let newsletter_issue_id = Uuid::new_v4();
     sqlx::query!(
@@ -148,14 +274,26 @@ async fn insert_newsletter_issue(
             title,
             text_content,
             html_content,
+            markdown_content,
+            layout_id,
+            segment,
+            tracking_enabled,
+            show_in_archive,
+            slug,
             published_at
         )
-        VALUES ($1, $2, $3, $4, now())
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, now())
         "#,
         newsletter_issue_id,
-        title,
-        text_content,
-        html_content
+        issue.title,
+        issue.text_content,
+        issue.html_content,
+        issue.markdown.map(|m| m.as_ref()),
+        issue.layout_id,
+        issue.segment.map(|s| s.as_ref()),
+        issue.tracking_enabled,
+        issue.show_in_archive,
+        slug(issue.title, newsletter_issue_id)
     )
     .execute(transaction)
     .await?;
@@ -165,20 +303,90 @@ async fn insert_newsletter_issue(
 async fn enqueue_delivery_tasks(
     transaction: &mut Transaction<'_, Postgres>,
     newsletter_issue_id: Uuid,
+    list_ids: &[Uuid],
+    segment: Option<&Segment>,
 ) -> Result<(), sqlx::Error> {
-    sqlx::query!(
+    let (segment_predicate, segment_parameters) = match segment {
+        Some(segment) => segment.to_sql(4),
+        None => ("TRUE".into(), Vec::new()),
+    };
+    // The segment predicate is only known at runtime,
+    // so this query cannot be checked at compile time.
+    // Suppressed recipients are logged instead of being queued.
+    let sql = format!(
         r#"
-        INSERT INTO issue_delivery_queue (
-            newsletter_issue_id,
-            subscriber_email
+        WITH recipients AS (
+            SELECT
+                s.email,
+                (
+                    SELECT
+                        'The ' || x.kind || ' ' || x.value ||
+                        ' is suppressed (' || x.source || '): ' || x.reason
+                    FROM suppressions x
+                    WHERE
+                        (x.kind = 'address' AND x.value = lower(s.email)) OR
+                        (
+                            x.kind = 'domain' AND
+                            x.value = split_part(lower(s.email), '@', 2)
+                        )
+                    ORDER BY x.source = 'unsubscribe'
+                    LIMIT 1
+                ) AS suppression
+            FROM subscriptions s
+            WHERE
+                s.status = 'confirmed' AND
+                (s.paused_until IS NULL OR s.paused_until < now()) AND
+                EXISTS (
+                    SELECT 1 FROM list_memberships m
+                    WHERE
+                        m.subscriber_id = s.id AND
+                        m.list_id = ANY($2) AND
+                        m.confirmed_at IS NOT NULL
+                ) AND
+                ({})
+        ),
+        suppressed AS (
+            INSERT INTO suppressed_sends (
+                email,
+                context,
+                newsletter_issue_id,
+                reason
+            )
+            SELECT email, 'enqueue', $1, suppression
+            FROM recipients
+            WHERE suppression IS NOT NULL
+            RETURNING email, reason
+        ),
+        queued AS (
+            INSERT INTO issue_delivery_queue (
+                newsletter_issue_id,
+                subscriber_email,
+                trace_context
+            )
+            SELECT $1, email, $3
+            FROM recipients
+            WHERE suppression IS NULL
         )
-        SELECT $1, email
-        FROM subscriptions
-        WHERE status = 'confirmed'
+        SELECT email, reason FROM suppressed
         "#,
-        newsletter_issue_id
-    )
-    .execute(transaction)
-    .await?;
+        segment_predicate
+    );
+    // The worker continues the trace of this request
+    let mut query = sqlx::query(&sql)
+        .bind(newsletter_issue_id)
+        .bind(list_ids)
+        .bind(current_trace_context());
+    for parameter in segment_parameters {
+        query = match parameter {
+            SqlParameter::Text(value) => query.bind(value),
+            SqlParameter::Float(value) => query.bind(value),
+            SqlParameter::Timestamp(value) => query.bind(value),
+        };
+    }
+    for row in query.fetch_all(transaction).await? {
+        let email: String = row.try_get("email")?;
+        let reason: String = row.try_get("reason")?;
+        tracing::info!(%email, reason, "Skipping a suppressed address.");
+    }
     Ok(())
 }
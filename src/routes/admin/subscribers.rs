@@ -0,0 +1,9 @@
+mod edit;
+mod import;
+mod overview;
+mod rejections;
+
+pub use edit::{subscriber_page, update_subscriber};
+pub use import::*;
+pub use overview::*;
+pub use rejections::*;
@@ -0,0 +1,197 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::domain::{CustomFields, SubscriberTags};
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    custom_fields: String,
+}
+
+struct FeedbackEvent {
+    kind: String,
+    description: Option<String>,
+    new_status: Option<String>,
+    occurred_at: DateTime<Utc>,
+}
+
+struct SubscriberDetails {
+    email: String,
+    name: String,
+    status: String,
+    tags: Vec<String>,
+    custom_fields: serde_json::Value,
+}
+
+pub async fn subscriber_page(
+    subscriber_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let subscriber = match get_subscriber_details(&pool, subscriber_id)
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => subscriber,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+    let custom_fields = serde_json::to_string_pretty(&subscriber.custom_fields)
+        .map_err(e500)?;
+    let mut feedback_html = String::new();
+    for event in get_feedback_events(&pool, subscriber_id, &subscriber.email)
+        .await
+        .map_err(e500)?
+    {
+        writeln!(
+            feedback_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            event.occurred_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            event.kind,
+            htmlescape::encode_minimal(
+                event.description.as_deref().unwrap_or("")
+            ),
+            event.new_status.as_deref().unwrap_or("-")
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscriber</title>
+</head>
+<body>
+    {msg_html}
+    <p>{email} ({name}), {status}</p>
+    <form action="/admin/subscribers/{subscriber_id}" method="post">
+        <label>Tags, separated by commas
+            <input type="text" name="tags" value="{tags}">
+        </label>
+        <br>
+        <label>Custom fields, as a JSON object
+            <textarea name="custom_fields" rows="10" cols="50">{custom_fields}</textarea>
+        </label>
+        <br>
+        <button type="submit">Save</button>
+    </form>
+    <h2>Bounces and complaints</h2>
+    <table>
+        <tr><th>Date</th><th>Event</th><th>Description</th><th>New status</th></tr>
+        {feedback_html}
+    </table>
+    <p><a href="/admin/subscribers">&lt;- Back</a></p>
+</body>
+</html>"#,
+            email = htmlescape::encode_minimal(&subscriber.email),
+            name = htmlescape::encode_minimal(&subscriber.name),
+            status = subscriber.status,
+            tags = subscriber.tags.join(", "),
+            custom_fields = htmlescape::encode_minimal(&custom_fields),
+        )))
+}
+
+#[tracing::instrument(
+    name = "Update subscriber tags and custom fields",
+    skip(form, pool)
+)]
+pub async fn update_subscriber(
+    subscriber_id: web::Path<Uuid>,
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = subscriber_id.into_inner();
+    let location = format!("/admin/subscribers/{}", subscriber_id);
+    let parsed = SubscriberTags::parse(&form.tags).and_then(|tags| {
+        CustomFields::parse(&form.custom_fields).map(|fields| (tags, fields))
+    });
+    let (tags, custom_fields) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET tags = $2, custom_fields = $3
+        WHERE id = $1
+        "#,
+        subscriber_id,
+        tags.as_ref(),
+        custom_fields.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the subscriber")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    FlashMessage::info("The subscriber has been updated.").send();
+    Ok(see_other(&location))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber_details(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, anyhow::Error> {
+    sqlx::query_as!(
+        SubscriberDetails,
+        r#"
+        SELECT email, name, status, tags, custom_fields
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber")
+}
+
+// Also matched by address, for the events received before the address
+// was subscribed.
+#[tracing::instrument(skip(pool))]
+async fn get_feedback_events(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    email: &str,
+) -> Result<Vec<FeedbackEvent>, anyhow::Error> {
+    sqlx::query_as!(
+        FeedbackEvent,
+        r#"
+        SELECT kind, description, new_status, occurred_at
+        FROM email_feedback_events
+        WHERE subscriber_id = $1 OR email = $2
+        ORDER BY occurred_at DESC
+        "#,
+        subscriber_id,
+        email
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the bounces and complaints")
+}
@@ -0,0 +1,531 @@
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use futures::channel::mpsc;
+use futures::{AsyncRead, FutureExt, StreamExt, TryStreamExt};
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::{field::display, Span};
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::confirmation_emails::enqueue_confirmation_emails;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::routes::generate_subscription_token;
+use crate::utils::{e400, e500};
+
+// Valid rows are written in batches of this size, one transaction each,
+// so a failure halfway through a large file keeps the rows already stored.
+const BATCH_SIZE: usize = 500;
+// Chunks of the request body read ahead of the CSV parser.
+const PAYLOAD_CHUNKS_IN_FLIGHT: usize = 16;
+
+#[derive(serde::Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    // Rows are stored as confirmed subscribers straight away.
+    Confirmed,
+    // Rows are stored as pending and receive a confirmation email, sent
+    // by the delivery worker.
+    PendingConfirmation,
+}
+
+impl ImportStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportStatus::Confirmed => "confirmed",
+            ImportStatus::PendingConfirmation => "pending_confirmation",
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ImportParameters {
+    status: ImportStatus,
+}
+
+#[derive(serde::Serialize)]
+pub struct ImportSummary {
+    import_id: Uuid,
+    inserted: i32,
+    duplicates: i32,
+    rejected: i32,
+    report_url: String,
+}
+
+struct Rejection {
+    row_number: i32,
+    email: String,
+    name: String,
+    reason: String,
+}
+
+#[derive(Default)]
+struct ImportBatch {
+    subscribers: Vec<(i32, NewSubscriber)>,
+    rejections: Vec<Rejection>,
+}
+
+impl ImportBatch {
+    fn is_full(&self) -> bool {
+        self.subscribers.len() + self.rejections.len() >= BATCH_SIZE
+    }
+}
+
+/// Import subscribers from a CSV file sent as the request body.
+///
+/// The file must have a header row with (at least) an `email` and a `name`
+/// column. The body is parsed as it streams in, so large files are never
+/// held in memory as a whole.
+#[tracing::instrument(
+    name = "Import subscribers from a CSV file",
+    skip_all,
+    fields(user_id=%*user_id, import_id=tracing::field::Empty)
+)]
+pub async fn import_subscribers(
+    payload: web::Payload,
+    parameters: web::Query<ImportParameters>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let user_id = *user_id.into_inner();
+    let status = parameters.status;
+
+    // The CSV reader wants a `Send` source, which the request payload is
+    // not: its chunks are forwarded through a channel as they come in.
+    let (sender, receiver) = mpsc::channel(PAYLOAD_CHUNKS_IN_FLIGHT);
+    let forward_payload = payload
+        .map(|chunk| {
+            Ok(chunk.map_err(|e| {
+                std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
+            }))
+        })
+        // Fails once the import stopped reading, there is nothing to do
+        .forward(sender)
+        .map(|_| ());
+    let import =
+        import_rows(receiver.into_async_read(), user_id, status, &pool);
+    let ((), response) = futures::join!(forward_payload, import);
+    response
+}
+
+async fn import_rows<R>(
+    body: R,
+    user_id: Uuid,
+    status: ImportStatus,
+    pool: &PgPool,
+) -> Result<HttpResponse, actix_web::Error>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut reader = AsyncReaderBuilder::new()
+        .trim(Trim::All)
+        .flexible(true)
+        .create_reader(body);
+
+    let headers = reader
+        .headers()
+        .await
+        .context("Failed to read the CSV header row")
+        .map_err(e400)?
+        .clone();
+    let columns = Columns::from_headers(&headers).map_err(e400)?;
+
+    let import_id = insert_import(pool, user_id, status)
+        .await
+        .context("Failed to store the import details")
+        .map_err(e500)?;
+    Span::current().record("import_id", &display(import_id));
+
+    let mut summary = ImportSummary {
+        import_id,
+        inserted: 0,
+        duplicates: 0,
+        rejected: 0,
+        report_url: format!(
+            "/admin/subscribers/imports/{}/rejections",
+            import_id
+        ),
+    };
+    let mut batch = ImportBatch::default();
+    let mut records = reader.records();
+    let mut row_number = 0;
+    while let Some(record) = records.next().await {
+        row_number += 1;
+        let record = match record.with_context(|| {
+            format!(
+                "Failed to read row {} of the uploaded CSV file",
+                row_number
+            )
+        }) {
+            Ok(record) => record,
+            Err(e) => return Err(abort_import(pool, &summary, e, e400).await),
+        };
+        let email = columns.email(&record);
+        let name = columns.name(&record);
+        match parse_row(email, name) {
+            Ok(subscriber) => batch.subscribers.push((row_number, subscriber)),
+            Err(reason) => batch.rejections.push(Rejection {
+                row_number,
+                email: email.to_owned(),
+                name: name.to_owned(),
+                reason,
+            }),
+        }
+
+        if batch.is_full() {
+            let full_batch = std::mem::take(&mut batch);
+            if let Err(e) =
+                store_batch(pool, import_id, status, full_batch, &mut summary)
+                    .await
+            {
+                return Err(abort_import(pool, &summary, e, e500).await);
+            }
+        }
+    }
+    if let Err(e) =
+        store_batch(pool, import_id, status, batch, &mut summary).await
+    {
+        return Err(abort_import(pool, &summary, e, e500).await);
+    }
+
+    complete_import(pool, &summary)
+        .await
+        .context("Failed to record the import results")
+        .map_err(e500)?;
+
+    Ok(HttpResponse::Ok().json(summary))
+}
+
+// Position of the columns we care about in each CSV record.
+struct Columns {
+    email: usize,
+    name: usize,
+}
+
+impl Columns {
+    fn from_headers(headers: &StringRecord) -> Result<Self, String> {
+        let position = |column: &str| {
+            headers
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(column))
+                .ok_or_else(|| {
+                    format!("The CSV file is missing the `{}` column.", column)
+                })
+        };
+        Ok(Self {
+            email: position("email")?,
+            name: position("name")?,
+        })
+    }
+
+    fn email<'a>(&self, record: &'a StringRecord) -> &'a str {
+        record.get(self.email).unwrap_or_default()
+    }
+
+    fn name<'a>(&self, record: &'a StringRecord) -> &'a str {
+        record.get(self.name).unwrap_or_default()
+    }
+}
+
+fn parse_row(email: &str, name: &str) -> Result<NewSubscriber, String> {
+    let email = SubscriberEmail::parse(email.to_owned())?;
+    let name = SubscriberName::parse(name.to_owned())?;
+    Ok(NewSubscriber { email, name })
+}
+
+#[tracing::instrument(skip_all)]
+async fn insert_import(
+    pool: &PgPool,
+    user_id: Uuid,
+    status: ImportStatus,
+) -> Result<Uuid, sqlx::Error> {
+    let import_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_imports (
+            import_id,
+            user_id,
+            initial_status,
+            created_at
+        )
+        VALUES ($1, $2, $3, now())
+        "#,
+        import_id,
+        user_id,
+        status.as_str()
+    )
+    .execute(pool)
+    .await?;
+    Ok(import_id)
+}
+
+#[tracing::instrument(
+    skip_all,
+    fields(
+        subscribers = batch.subscribers.len(),
+        rejections = batch.rejections.len()
+    )
+)]
+async fn store_batch(
+    pool: &PgPool,
+    import_id: Uuid,
+    status: ImportStatus,
+    batch: ImportBatch,
+    summary: &mut ImportSummary,
+) -> Result<(), anyhow::Error> {
+    let ImportBatch {
+        subscribers,
+        mut rejections,
+    } = batch;
+    if subscribers.is_empty() && rejections.is_empty() {
+        return Ok(());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let subscriber_ids: Vec<Uuid> =
+        subscribers.iter().map(|_| Uuid::new_v4()).collect();
+    let inserted_ids = insert_subscribers(
+        &mut transaction,
+        &subscriber_ids,
+        &subscribers,
+        status,
+    )
+    .await
+    .context("Failed to insert a batch of imported subscribers")?;
+
+    let rejected = rejections.len() as i32;
+    let mut inserted = Vec::with_capacity(inserted_ids.len());
+    for (id, (row_number, subscriber)) in
+        subscriber_ids.into_iter().zip(subscribers)
+    {
+        if inserted_ids.contains(&id) {
+            inserted.push(id);
+        } else {
+            summary.duplicates += 1;
+            rejections.push(Rejection {
+                row_number,
+                email: subscriber.email.as_ref().to_owned(),
+                name: subscriber.name.as_ref().to_owned(),
+                reason: "A subscriber with this email address already exists."
+                    .into(),
+            });
+        }
+    }
+    summary.inserted += inserted.len() as i32;
+    summary.rejected += rejected;
+
+    join_default_lists(&mut transaction, &inserted, status)
+        .await
+        .context("Failed to add imported subscribers to the default lists")?;
+
+    if let ImportStatus::PendingConfirmation = status {
+        let tokens: Vec<String> = inserted
+            .iter()
+            .map(|_| generate_subscription_token())
+            .collect();
+        store_tokens(&mut transaction, &tokens, &inserted)
+            .await
+            .context("Failed to store the confirmation tokens")?;
+        enqueue_confirmation_emails(&mut transaction, &inserted, &tokens)
+            .await
+            .context("Failed to queue the confirmation emails")?;
+    }
+
+    insert_rejections(&mut transaction, import_id, &rejections)
+        .await
+        .context("Failed to store the rejected rows")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store an import batch")?;
+
+    Ok(())
+}
+
+async fn insert_subscribers(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    subscribers: &[(i32, NewSubscriber)],
+    status: ImportStatus,
+) -> Result<Vec<Uuid>, sqlx::Error> {
+    let emails: Vec<&str> =
+        subscribers.iter().map(|(_, s)| s.email.as_ref()).collect();
+    let names: Vec<&str> =
+        subscribers.iter().map(|(_, s)| s.name.as_ref()).collect();
+    // Conflicting emails, both with existing subscribers and within the
+    // file itself, are skipped instead of aborting the whole batch.
+    let rows = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, name, subscribed_at, status)
+        SELECT id, email, name, now(), $4
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[]) AS t(id, email, name)
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
+        "#,
+        subscriber_ids,
+        &emails as &[&str],
+        &names as &[&str],
+        status.as_str()
+    )
+    .fetch_all(transaction)
+    .await?;
+    Ok(rows.into_iter().map(|r| r.id).collect())
+}
+
+async fn join_default_lists(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    status: ImportStatus,
+) -> Result<(), sqlx::Error> {
+    let is_confirmed = matches!(status, ImportStatus::Confirmed);
+    sqlx::query!(
+        r#"
+        INSERT INTO list_memberships (
+            list_id,
+            subscriber_id,
+            created_at,
+            confirmed_at
+        )
+        SELECT l.list_id, t.id, now(), CASE WHEN $2 THEN now() END
+        FROM lists l, UNNEST($1::uuid[]) AS t(id)
+        WHERE l.is_default
+        "#,
+        subscriber_ids,
+        is_confirmed
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn store_tokens(
+    transaction: &mut Transaction<'_, Postgres>,
+    tokens: &[String],
+    subscriber_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_token (subscription_token, subscriber_id)
+        SELECT * FROM UNNEST($1::text[], $2::uuid[])
+        "#,
+        tokens,
+        subscriber_ids
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn insert_rejections(
+    transaction: &mut Transaction<'_, Postgres>,
+    import_id: Uuid,
+    rejections: &[Rejection],
+) -> Result<(), sqlx::Error> {
+    if rejections.is_empty() {
+        return Ok(());
+    }
+    let row_numbers: Vec<i32> =
+        rejections.iter().map(|r| r.row_number).collect();
+    let emails: Vec<&str> =
+        rejections.iter().map(|r| r.email.as_str()).collect();
+    let names: Vec<&str> = rejections.iter().map(|r| r.name.as_str()).collect();
+    let reasons: Vec<&str> =
+        rejections.iter().map(|r| r.reason.as_str()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriber_import_rejections (
+            import_id,
+            row_number,
+            email,
+            name,
+            reason
+        )
+        SELECT $1, *
+        FROM UNNEST($2::int4[], $3::text[], $4::text[], $5::text[])
+        "#,
+        import_id,
+        &row_numbers,
+        &emails as &[&str],
+        &names as &[&str],
+        &reasons as &[&str]
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn complete_import(
+    pool: &PgPool,
+    summary: &ImportSummary,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET
+            inserted_rows = $2,
+            duplicate_rows = $3,
+            rejected_rows = $4,
+            completed_at = now()
+        WHERE import_id = $1
+        "#,
+        summary.import_id,
+        summary.inserted,
+        summary.duplicates,
+        summary.rejected
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+// Record why the import stopped before reporting it to the caller.
+async fn abort_import(
+    pool: &PgPool,
+    summary: &ImportSummary,
+    error: anyhow::Error,
+    respond: fn(anyhow::Error) -> actix_web::Error,
+) -> actix_web::Error {
+    if let Err(e) = fail_import(pool, summary, &error).await {
+        tracing::error!(
+            error.cause_chain = ?e,
+            error.message = %e,
+            "Failed to record the failure of an import",
+        );
+    }
+    respond(error)
+}
+
+#[tracing::instrument(skip_all)]
+async fn fail_import(
+    pool: &PgPool,
+    summary: &ImportSummary,
+    error: &anyhow::Error,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriber_imports
+        SET
+            inserted_rows = $2,
+            duplicate_rows = $3,
+            rejected_rows = $4,
+            failed_at = now(),
+            error = $5
+        WHERE import_id = $1
+        "#,
+        summary.import_id,
+        summary.inserted,
+        summary.duplicates,
+        summary.rejected,
+        format!("{:#}", error)
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
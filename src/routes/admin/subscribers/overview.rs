@@ -0,0 +1,98 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::utils::e500;
+
+// Subscribers shown at once, narrow the search to find the others
+const PAGE_SIZE: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct SearchParameters {
+    #[serde(default)]
+    q: String,
+}
+
+struct SubscriberRow {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    tags: Vec<String>,
+}
+
+pub async fn subscribers_page(
+    parameters: web::Query<SearchParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let q = parameters.0.q;
+    let subscribers = search_subscribers(&pool, &q).await.map_err(e500)?;
+
+    let mut rows_html = String::new();
+    for s in subscribers {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+            s.id,
+            htmlescape::encode_minimal(&s.email),
+            htmlescape::encode_minimal(&s.name),
+            s.status,
+            s.tags.join(", ")
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscribers</title>
+</head>
+<body>
+    <form action="/admin/subscribers" method="get">
+        <input type="search" placeholder="Search by email or name" name="q" value="{q}">
+        <button type="submit">Search</button>
+    </form>
+    <table>
+        <tr><th>Email</th><th>Name</th><th>Status</th><th>Tags</th></tr>
+        {rows_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            q = htmlescape::encode_attribute(&q),
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn search_subscribers(
+    pool: &PgPool,
+    q: &str,
+) -> Result<Vec<SubscriberRow>, anyhow::Error> {
+    let escaped = q
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let pattern = format!("%{}%", escaped);
+    sqlx::query_as!(
+        SubscriberRow,
+        r#"
+        SELECT id, email, name, status, tags
+        FROM subscriptions
+        WHERE email ILIKE $1 OR name ILIKE $1
+        ORDER BY subscribed_at DESC
+        LIMIT $2
+        "#,
+        pattern,
+        PAGE_SIZE
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to search the subscribers")
+}
@@ -0,0 +1,71 @@
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::utils::{attachment, e500};
+
+#[derive(serde::Serialize)]
+struct RejectionRecord {
+    row_number: i32,
+    email: String,
+    name: String,
+    reason: String,
+}
+
+/// Download the rows of an import that were not stored, as a CSV file.
+#[tracing::instrument(name = "Download import rejections", skip(pool))]
+pub async fn import_rejections(
+    import_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let import_id = import_id.into_inner();
+    if !import_exists(&pool, import_id).await.map_err(e500)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let rejections = sqlx::query_as!(
+        RejectionRecord,
+        r#"
+        SELECT row_number, email, name, reason
+        FROM subscriber_import_rejections
+        WHERE import_id = $1
+        ORDER BY row_number
+        "#,
+        import_id
+    )
+    .fetch_all(pool.get_ref())
+    .await
+    .context("Failed to retrieve the rejected rows of an import")
+    .map_err(e500)?;
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for rejection in rejections {
+        writer.serialize(rejection).map_err(e500)?;
+    }
+    let body = writer.into_inner().map_err(e500)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .insert_header(attachment(format!(
+            "import-{}-rejections.csv",
+            import_id
+        )))
+        .body(body))
+}
+
+async fn import_exists(
+    pool: &PgPool,
+    import_id: Uuid,
+) -> Result<bool, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT import_id FROM subscriber_imports WHERE import_id = $1
+        "#,
+        import_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the import")?;
+    Ok(row.is_some())
+}
@@ -0,0 +1,234 @@
+use actix_web::http::header::ContentType;
+use actix_web::web::ReqData;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::authentication::middleware::UserId;
+use crate::domain::SubscriberEmail;
+use crate::suppressions::get_suppressions;
+use crate::utils::{e500, see_other};
+
+// How many of the latest suppressed sends are listed
+const SUPPRESSED_SENDS_SHOWN: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    // 'address' or 'domain'
+    kind: String,
+    value: String,
+    reason: String,
+}
+
+struct SuppressedSend {
+    email: String,
+    context: String,
+    reason: String,
+    logged_at: DateTime<Utc>,
+}
+
+pub async fn suppressions_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for s in get_suppressions(&pool)
+        .await
+        .context("Failed to retrieve the suppressions")
+        .map_err(e500)?
+    {
+        writeln!(
+            rows_html,
+            r#"<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td><form action="/admin/suppressions/{}/delete" method="post"><button type="submit">Remove</button></form></td></tr>"#,
+            s.kind,
+            htmlescape::encode_minimal(&s.value),
+            htmlescape::encode_minimal(&s.reason),
+            s.source,
+            s.suppression_id
+        )
+        .unwrap();
+    }
+
+    let mut sends_html = String::new();
+    for send in get_suppressed_sends(&pool).await.map_err(e500)? {
+        writeln!(
+            sends_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            send.logged_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            htmlescape::encode_minimal(&send.email),
+            send.context,
+            htmlescape::encode_minimal(&send.reason)
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Suppressions</title>
+</head>
+<body>
+    {msg_html}
+    <p>Suppressed addresses and domains are never mailed, even if they subscribe again.</p>
+    <table>
+        <tr><th>Kind</th><th>Value</th><th>Reason</th><th>Source</th><th></th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/suppressions" method="post">
+        <label>Suppress
+            <select name="kind">
+                <option value="address">an address</option>
+                <option value="domain">a domain</option>
+            </select>
+        </label>
+        <input type="text" placeholder="ursula@example.com or example.com" name="value">
+        <br>
+        <label>Reason
+            <input type="text" placeholder="Why must it not be mailed?" name="reason">
+        </label>
+        <br>
+        <button type="submit">Suppress</button>
+    </form>
+    <h2>Latest suppressed sends</h2>
+    <table>
+        <tr><th>Date</th><th>Email</th><th>Skipped at</th><th>Reason</th></tr>
+        {sends_html}
+    </table>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(
+    name = "Suppress an address or a domain",
+    skip(form, pool, user_id)
+)]
+pub async fn create_suppression(
+    form: web::Form<FormData>,
+    user_id: ReqData<UserId>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let FormData {
+        kind,
+        value,
+        reason,
+    } = form.into_inner();
+    let value = match kind.as_str() {
+        "address" => SubscriberEmail::parse(value.trim().to_lowercase())
+            .map(|email| email.as_ref().to_owned()),
+        "domain" => parse_domain(&value),
+        _ => Err("Choose between an address and a domain.".into()),
+    };
+    let value = match value {
+        Ok(value) => value,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other("/admin/suppressions"));
+        }
+    };
+    let reason = reason.trim();
+    if reason.is_empty() {
+        FlashMessage::error("Give a reason for the suppression.").send();
+        return Ok(see_other("/admin/suppressions"));
+    }
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO suppressions (
+            suppression_id,
+            kind,
+            value,
+            reason,
+            source,
+            created_by
+        )
+        VALUES ($1, $2, $3, $4, 'admin', $5)
+        ON CONFLICT (kind, value) DO NOTHING
+        "#,
+        Uuid::new_v4(),
+        kind,
+        value,
+        reason,
+        **user_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the suppression")
+    .map_err(e500)?;
+
+    let value = htmlescape::encode_minimal(&value);
+    if result.rows_affected() == 0 {
+        FlashMessage::error(format!("{} is already suppressed.", value)).send();
+    } else {
+        FlashMessage::info(format!("{} has been suppressed.", value)).send();
+    }
+    Ok(see_other("/admin/suppressions"))
+}
+
+#[tracing::instrument(name = "Remove a suppression", skip(pool))]
+pub async fn delete_suppression(
+    suppression_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let result = sqlx::query!(
+        "DELETE FROM suppressions WHERE suppression_id = $1",
+        suppression_id.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to remove the suppression")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    FlashMessage::info("The suppression has been removed.").send();
+    Ok(see_other("/admin/suppressions"))
+}
+
+fn parse_domain(s: &str) -> Result<String, String> {
+    let domain = s.trim().trim_start_matches('@').to_lowercase();
+    let is_valid = domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+    if is_valid {
+        Ok(domain)
+    } else {
+        Err(format!("{} is not a valid domain.", s))
+    }
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_suppressed_sends(
+    pool: &PgPool,
+) -> Result<Vec<SuppressedSend>, anyhow::Error> {
+    sqlx::query_as!(
+        SuppressedSend,
+        r#"
+        SELECT email, context, reason, logged_at
+        FROM suppressed_sends
+        ORDER BY logged_at DESC
+        LIMIT $1
+        "#,
+        SUPPRESSED_SENDS_SHOWN
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the suppressed sends")
+}
@@ -0,0 +1,385 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::outgoing_webhooks::WebhookEvent;
+use crate::utils::{e500, see_other, HtmlForm};
+
+// How many of the latest delivery attempts are listed for an endpoint
+const LOG_ENTRIES_SHOWN: i64 = 50;
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    url: String,
+    // Repeated once per checked event
+    #[serde(default, rename = "event")]
+    events: Vec<String>,
+}
+
+struct Endpoint {
+    endpoint_id: Uuid,
+    url: String,
+    secret: String,
+    events: Vec<String>,
+    // Events waiting to be delivered, including the ones being retried
+    pending: i64,
+}
+
+struct LogEntry {
+    event_id: Uuid,
+    event: String,
+    attempt: i16,
+    outcome: String,
+    status_code: Option<i16>,
+    error: Option<String>,
+    attempted_at: DateTime<Utc>,
+}
+
+pub async fn webhooks_page(
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut rows_html = String::new();
+    for endpoint in get_endpoints(&pool).await.map_err(e500)? {
+        writeln!(
+            rows_html,
+            r#"<tr><td><a href="/admin/webhooks/{}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+            endpoint.endpoint_id,
+            htmlescape::encode_minimal(&endpoint.url),
+            endpoint.events.join(", "),
+            endpoint.pending,
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Webhooks</title>
+</head>
+<body>
+    {msg_html}
+    <table>
+        <tr><th>URL</th><th>Events</th><th>Pending</th></tr>
+        {rows_html}
+    </table>
+    <form action="/admin/webhooks" method="post">
+        {form_fields}
+        <button type="submit">Add endpoint</button>
+    </form>
+    <p><a href="/admin/dashboard">&lt;- Back</a></p>
+</body>
+</html>"#,
+            form_fields = form_fields("", &[]),
+        )))
+}
+
+pub async fn webhook_page(
+    endpoint_id: web::Path<Uuid>,
+    flash_messages: IncomingFlashMessages,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let endpoint_id = endpoint_id.into_inner();
+    let endpoint = match get_endpoint(&pool, endpoint_id).await.map_err(e500)? {
+        Some(endpoint) => endpoint,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let mut log_html = String::new();
+    for entry in get_delivery_log(&pool, endpoint_id).await.map_err(e500)? {
+        writeln!(
+            log_html,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            entry.attempted_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            entry.event,
+            entry.event_id,
+            entry.attempt,
+            entry.outcome,
+            entry
+                .status_code
+                .map_or_else(|| "-".to_string(), |c| c.to_string()),
+            htmlescape::encode_minimal(entry.error.as_deref().unwrap_or("-")),
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Webhook endpoint</title>
+</head>
+<body>
+    {msg_html}
+    <p>Signing secret: <code>{secret}</code></p>
+    <p>Each request carries an <code>X-Webhook-Signature: t=&lt;timestamp&gt;,v1=&lt;signature&gt;</code> header,
+    the signature is the hex-encoded HMAC-SHA256 of <code>&lt;timestamp&gt;.&lt;body&gt;</code> with this secret.</p>
+    <p>{pending} event(s) waiting to be delivered.</p>
+    <form action="/admin/webhooks/{endpoint_id}" method="post">
+        {form_fields}
+        <button type="submit">Save</button>
+    </form>
+    <form action="/admin/webhooks/{endpoint_id}/delete" method="post">
+        <button type="submit">Delete endpoint</button>
+    </form>
+    <h2>Deliveries</h2>
+    <table>
+        <tr><th>Date</th><th>Event</th><th>Event id</th><th>Attempt</th><th>Outcome</th><th>Status</th><th>Error</th></tr>
+        {log_html}
+    </table>
+    <p><a href="/admin/webhooks">&lt;- Back</a></p>
+</body>
+</html>"#,
+            secret = htmlescape::encode_minimal(&endpoint.secret),
+            pending = endpoint.pending,
+            form_fields = form_fields(&endpoint.url, &endpoint.events),
+        )))
+}
+
+fn form_fields(url: &str, events: &[String]) -> String {
+    let mut checkboxes = String::new();
+    for event in WebhookEvent::ALL {
+        writeln!(
+            checkboxes,
+            r#"<label><input type="checkbox" name="event" value="{}"{}> {}</label><br>"#,
+            event.as_str(),
+            if events.iter().any(|e| e == event.as_str()) {
+                " checked"
+            } else {
+                ""
+            },
+            event.description()
+        )
+        .unwrap();
+    }
+    format!(
+        r#"<label>URL
+            <input type="text" placeholder="https://example.com/webhooks" name="url" value="{}">
+        </label>
+        <br>
+        {}"#,
+        htmlescape::encode_minimal(url),
+        checkboxes
+    )
+}
+
+fn validate(form: FormData) -> Result<(String, Vec<String>), String> {
+    let url = form.url.trim();
+    match reqwest::Url::parse(url) {
+        Ok(parsed) if ["http", "https"].contains(&parsed.scheme()) => {}
+        _ => return Err(format!("{} is not a valid HTTP(S) URL.", url)),
+    }
+    if form.events.is_empty() {
+        return Err("Choose at least one event.".into());
+    }
+    let events = form
+        .events
+        .iter()
+        .map(|e| WebhookEvent::parse(e).map(|e| e.as_str().to_owned()))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((url.to_owned(), events))
+}
+
+// Shared with the receiver, which checks the signatures with it
+fn generate_secret() -> String {
+    let mut rng = rand::thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(32)
+        .collect()
+}
+
+#[tracing::instrument(name = "Add a webhook endpoint", skip(form, pool))]
+pub async fn create_webhook(
+    form: HtmlForm<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (url, events) = match validate(form.into_inner()) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other("/admin/webhooks"));
+        }
+    };
+
+    let endpoint_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO webhook_endpoints (endpoint_id, url, secret, events)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        endpoint_id,
+        url,
+        generate_secret(),
+        &events
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the webhook endpoint")
+    .map_err(e500)?;
+
+    FlashMessage::info("The webhook endpoint has been added.").send();
+    Ok(see_other(&format!("/admin/webhooks/{}", endpoint_id)))
+}
+
+#[tracing::instrument(name = "Update a webhook endpoint", skip(form, pool))]
+pub async fn update_webhook(
+    endpoint_id: web::Path<Uuid>,
+    form: HtmlForm<FormData>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let endpoint_id = endpoint_id.into_inner();
+    let location = format!("/admin/webhooks/{}", endpoint_id);
+    let (url, events) = match validate(form.into_inner()) {
+        Ok(endpoint) => endpoint,
+        Err(e) => {
+            FlashMessage::error(htmlescape::encode_minimal(&e)).send();
+            return Ok(see_other(&location));
+        }
+    };
+
+    // Events already queued are still delivered
+    let result = sqlx::query!(
+        r#"
+        UPDATE webhook_endpoints SET url = $2, events = $3
+        WHERE endpoint_id = $1
+        "#,
+        endpoint_id,
+        url,
+        &events
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the webhook endpoint")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    FlashMessage::info("The webhook endpoint has been updated.").send();
+    Ok(see_other(&location))
+}
+
+#[tracing::instrument(name = "Delete a webhook endpoint", skip(pool))]
+pub async fn delete_webhook(
+    endpoint_id: web::Path<Uuid>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // Its queue and delivery log go with it
+    let result = sqlx::query!(
+        "DELETE FROM webhook_endpoints WHERE endpoint_id = $1",
+        endpoint_id.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to delete the webhook endpoint")
+    .map_err(e500)?;
+    if result.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    FlashMessage::info("The webhook endpoint has been deleted.").send();
+    Ok(see_other("/admin/webhooks"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_endpoints(pool: &PgPool) -> Result<Vec<Endpoint>, anyhow::Error> {
+    sqlx::query_as!(
+        Endpoint,
+        r#"
+        SELECT
+            e.endpoint_id,
+            e.url,
+            e.secret,
+            e.events,
+            (
+                SELECT COUNT(*) FROM webhook_delivery_queue q
+                WHERE q.endpoint_id = e.endpoint_id
+            ) AS "pending!"
+        FROM webhook_endpoints e
+        ORDER BY e.created_at
+        "#
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the webhook endpoints")
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_endpoint(
+    pool: &PgPool,
+    endpoint_id: Uuid,
+) -> Result<Option<Endpoint>, anyhow::Error> {
+    sqlx::query_as!(
+        Endpoint,
+        r#"
+        SELECT
+            e.endpoint_id,
+            e.url,
+            e.secret,
+            e.events,
+            (
+                SELECT COUNT(*) FROM webhook_delivery_queue q
+                WHERE q.endpoint_id = e.endpoint_id
+            ) AS "pending!"
+        FROM webhook_endpoints e
+        WHERE e.endpoint_id = $1
+        "#,
+        endpoint_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the webhook endpoint")
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_delivery_log(
+    pool: &PgPool,
+    endpoint_id: Uuid,
+) -> Result<Vec<LogEntry>, anyhow::Error> {
+    sqlx::query_as!(
+        LogEntry,
+        r#"
+        SELECT
+            event_id,
+            event,
+            attempt,
+            outcome,
+            status_code,
+            error,
+            attempted_at
+        FROM webhook_delivery_log
+        WHERE endpoint_id = $1
+        ORDER BY attempted_at DESC, attempt DESC
+        LIMIT $2
+        "#,
+        endpoint_id,
+        LOG_ENTRIES_SHOWN
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to retrieve the webhook delivery log")
+}
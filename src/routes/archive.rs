@@ -0,0 +1,121 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+use std::fmt::Write;
+
+use crate::newsletter_issues::{
+    archive_offset, get_archive_page, get_archived_issue, prepend_to_body,
+};
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize, Debug)]
+pub struct ArchiveParameters {
+    page: Option<i64>,
+}
+
+/// The issues published in the archive, latest first.
+#[tracing::instrument(name = "Browse the archive", skip(pool))]
+pub async fn archive(
+    parameters: web::Query<ArchiveParameters>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let page = parameters.0.page.unwrap_or(1);
+    if page < 1 {
+        return Err(e400("Pages start at 1."));
+    }
+    let offset = archive_offset(page)
+        .ok_or_else(|| e400("There is no such page in the archive."))?;
+    let (issues, has_more) =
+        get_archive_page(&pool, offset).await.map_err(e500)?;
+
+    let mut issues_html = String::new();
+    for issue in issues {
+        writeln!(
+            issues_html,
+            r#"<li><a href="/archive/{}">{}</a> - {}</li>"#,
+            htmlescape::encode_minimal(&issue.slug),
+            htmlescape::encode_minimal(&issue.title()),
+            issue.published_at.format("%B %-d, %Y")
+        )
+        .unwrap();
+    }
+    if issues_html.is_empty() {
+        issues_html.push_str("<li>No issues yet.</li>");
+    }
+    let mut pages_html = String::new();
+    if page > 1 {
+        write!(
+            pages_html,
+            r#"<a href="/archive?page={}">Newer issues</a> "#,
+            page - 1
+        )
+        .unwrap();
+    }
+    if has_more {
+        write!(
+            pages_html,
+            r#"<a href="/archive?page={}">Older issues</a>"#,
+            page + 1
+        )
+        .unwrap();
+    }
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Archive</title>
+</head>
+<body>
+    <h1>Archive</h1>
+    <ul>
+        {issues_html}
+    </ul>
+    <p>{pages_html}</p>
+</body>
+</html>"#,
+        )))
+}
+
+/// An issue as it was sent, with a header linking back to the archive.
+#[tracing::instrument(name = "Read an archived issue", skip(pool))]
+pub async fn archived_issue(
+    slug: web::Path<String>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let issue = match get_archived_issue(&pool, &slug).await.map_err(e500)? {
+        Some(issue) => issue,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let title = htmlescape::encode_minimal(&issue.title());
+    let published_at = issue.published_at.format("%B %-d, %Y");
+    let header = format!(
+        r#"<p><a href="/archive">&lt;- Archive</a></p><h1>{}</h1><p>{}</p>"#,
+        title, published_at
+    );
+    let html_content = issue.html_content();
+    // Most issues are a complete document, courtesy of their layout
+    let body = if html_content.contains("<body") {
+        prepend_to_body(&html_content, &header).map_err(e500)?
+    } else {
+        format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    {header}
+    {html_content}
+</body>
+</html>"#,
+        )
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(body))
+}
@@ -0,0 +1,219 @@
+use actix_web::http::header::{
+    self, EntityTag, HttpDate, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use std::fmt::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::newsletter_issues::{archive_url, get_archive_page, ArchivedIssue};
+use crate::startup::{ApplicationBaseUrl, NewsletterName};
+use crate::utils::e500;
+
+// Feeds list the latest issues, the first page of the archive
+async fn latest_issues(
+    pool: &PgPool,
+) -> Result<Vec<ArchivedIssue>, actix_web::Error> {
+    let (issues, _) = get_archive_page(pool, 0).await.map_err(e500)?;
+    Ok(issues)
+}
+
+// When the feed last changed: its latest issue, or the epoch if empty.
+// Truncated to the second, the precision of HTTP dates.
+fn updated_at(issues: &[ArchivedIssue]) -> DateTime<Utc> {
+    let seconds = issues.first().map_or(0, |i| i.published_at.timestamp());
+    (UNIX_EPOCH + Duration::from_secs(seconds as u64)).into()
+}
+
+// A stable identifier, whatever the title or the URL of the issue become
+fn guid(issue: &ArchivedIssue) -> String {
+    format!("urn:uuid:{}", issue.newsletter_issue_id)
+}
+
+#[tracing::instrument(name = "Get the Atom feed", skip_all)]
+pub async fn feed_atom(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    newsletter_name: web::Data<NewsletterName>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let base_url = &base_url.0;
+    let issues = latest_issues(&pool).await?;
+    let updated_at = updated_at(&issues);
+
+    let mut entries = String::new();
+    for issue in &issues {
+        let url = archive_url(base_url, &issue.slug);
+        writeln!(
+            entries,
+            r#"<entry>
+<title>{title}</title>
+<id>{id}</id>
+<link rel="alternate" type="text/html" href="{url}"/>
+<published>{published_at}</published>
+<updated>{published_at}</updated>
+<content type="html">{content}</content>
+</entry>"#,
+            title = htmlescape::encode_minimal(&issue.title()),
+            id = guid(issue),
+            url = htmlescape::encode_minimal(&url),
+            published_at = issue.published_at.to_rfc3339(),
+            content = htmlescape::encode_minimal(&issue.html_content()),
+        )
+        .unwrap();
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+<title>{name}</title>
+<id>{archive_url}</id>
+<link rel="alternate" type="text/html" href="{archive_url}"/>
+<link rel="self" type="application/atom+xml" href="{base_url}/feed.atom"/>
+<author><name>{name}</name></author>
+<updated>{updated_at}</updated>
+{entries}</feed>
+"#,
+        name = htmlescape::encode_minimal(&newsletter_name.0),
+        archive_url =
+            htmlescape::encode_minimal(&format!("{}/archive", base_url)),
+        base_url = htmlescape::encode_minimal(base_url),
+        updated_at = updated_at.to_rfc3339(),
+    );
+    Ok(feed_response(
+        &request,
+        "application/atom+xml; charset=utf-8",
+        body,
+        updated_at,
+    ))
+}
+
+#[tracing::instrument(name = "Get the RSS feed", skip_all)]
+pub async fn feed_rss(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    newsletter_name: web::Data<NewsletterName>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let base_url = &base_url.0;
+    let issues = latest_issues(&pool).await?;
+    let updated_at = updated_at(&issues);
+
+    let mut items = String::new();
+    for issue in &issues {
+        let url = archive_url(base_url, &issue.slug);
+        writeln!(
+            items,
+            r#"<item>
+<title>{title}</title>
+<link>{url}</link>
+<guid isPermaLink="false">{guid}</guid>
+<pubDate>{published_at}</pubDate>
+<description>{content}</description>
+</item>"#,
+            title = htmlescape::encode_minimal(&issue.title()),
+            url = htmlescape::encode_minimal(&url),
+            guid = guid(issue),
+            published_at = issue.published_at.to_rfc2822(),
+            content = htmlescape::encode_minimal(&issue.html_content()),
+        )
+        .unwrap();
+    }
+    let body = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0">
+<channel>
+<title>{name}</title>
+<link>{base_url}/archive</link>
+<description>The issues of {name}</description>
+<lastBuildDate>{updated_at}</lastBuildDate>
+{items}</channel>
+</rss>
+"#,
+        name = htmlescape::encode_minimal(&newsletter_name.0),
+        base_url = htmlescape::encode_minimal(base_url),
+        updated_at = updated_at.to_rfc2822(),
+    );
+    Ok(feed_response(
+        &request,
+        "application/rss+xml; charset=utf-8",
+        body,
+        updated_at,
+    ))
+}
+
+/// See https://www.jsonfeed.org/version/1.1/
+#[tracing::instrument(name = "Get the JSON feed", skip_all)]
+pub async fn feed_json(
+    request: HttpRequest,
+    pool: web::Data<PgPool>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    newsletter_name: web::Data<NewsletterName>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let base_url = &base_url.0;
+    let issues = latest_issues(&pool).await?;
+    let items: Vec<_> = issues
+        .iter()
+        .map(|issue| {
+            serde_json::json!({
+                "id": guid(issue),
+                "url": archive_url(base_url, &issue.slug),
+                "title": issue.title(),
+                "content_html": issue.html_content(),
+                "date_published": issue.published_at.to_rfc3339(),
+            })
+        })
+        .collect();
+    let feed = serde_json::json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": newsletter_name.0,
+        "home_page_url": format!("{}/archive", base_url),
+        "feed_url": format!("{}/feed.json", base_url),
+        "items": items,
+    });
+    Ok(feed_response(
+        &request,
+        "application/feed+json",
+        feed.to_string(),
+        updated_at(&issues),
+    ))
+}
+
+// Aggregators poll feeds: answer 304 Not Modified when they already have
+// the latest version, per RFC 7232. The ETag is checked first, as it also
+// catches issues removed from the feed.
+fn feed_response(
+    request: &HttpRequest,
+    content_type: &str,
+    body: String,
+    updated_at: DateTime<Utc>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(hex::encode(
+        &Sha256::digest(body.as_bytes())[..16],
+    ));
+    let last_modified = HttpDate::from(SystemTime::from(updated_at));
+    let not_modified = match request.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(etags)) => {
+            etags.iter().any(|e| e.weak_eq(&etag))
+        }
+        None => match request.get_header::<IfModifiedSince>() {
+            Some(IfModifiedSince(since)) => last_modified <= since,
+            None => false,
+        },
+    };
+    let mut response = if not_modified {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .insert_header(header::ETag(etag))
+        .insert_header(LastModified(last_modified));
+    if not_modified {
+        response.finish()
+    } else {
+        response.content_type(content_type).body(body)
+    }
+}
@@ -1,5 +1,174 @@
-use actix_web::HttpResponse;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
 
-pub async fn health_check() -> HttpResponse {
+use actix_web::{web, HttpResponse};
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::circuit_breaker::{get_circuit_status, CircuitState};
+use crate::workers::{last_heartbeat, HEARTBEAT_TIMEOUT_SECONDS};
+
+// How long each dependency has to answer before it is considered down
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+// Reported, but the API keeps serving requests while they are down
+const NON_CRITICAL: [&str; 1] = ["email_provider"];
+
+/// The process is up and serving requests.
+pub async fn health_live() -> HttpResponse {
     HttpResponse::Ok().finish()
 }
+
+#[derive(serde::Serialize)]
+struct Readiness {
+    // "ready" or "unavailable"
+    status: &'static str,
+    components: BTreeMap<&'static str, ComponentHealth>,
+}
+
+#[derive(serde::Serialize)]
+struct ComponentHealth {
+    // "up" or "down"
+    status: &'static str,
+    latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Whether the dependencies needed to serve requests and deliver issues
+/// are available. Answers 503 when any of them is down, except for the
+/// email provider whose outages only pause deliveries.
+pub async fn health_ready(
+    pool: web::Data<PgPool>,
+    redis_client: web::Data<redis::Client>,
+) -> HttpResponse {
+    let (database, redis, migrations, worker, email_provider) = futures::join!(
+        probe("database", check_database(&pool)),
+        probe("redis", check_redis(&redis_client)),
+        probe("migrations", check_migrations(&pool)),
+        probe("worker", check_worker(&pool)),
+        probe("email_provider", check_email_provider(&pool)),
+    );
+    let components = BTreeMap::from([
+        ("database", database),
+        ("redis", redis),
+        ("migrations", migrations),
+        ("worker", worker),
+        ("email_provider", email_provider),
+    ]);
+    if components
+        .iter()
+        .filter(|(name, _)| !NON_CRITICAL.contains(*name))
+        .all(|(_, c)| c.error.is_none())
+    {
+        HttpResponse::Ok().json(Readiness {
+            status: "ready",
+            components,
+        })
+    } else {
+        HttpResponse::ServiceUnavailable().json(Readiness {
+            status: "unavailable",
+            components,
+        })
+    }
+}
+
+async fn probe(
+    component: &str,
+    check: impl Future<Output = Result<(), anyhow::Error>>,
+) -> ComponentHealth {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, check).await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let error = match outcome {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{:#}", e)),
+        Err(_) => {
+            Some(format!("No answer within {}ms", CHECK_TIMEOUT.as_millis()))
+        }
+    };
+    if let Some(e) = &error {
+        tracing::warn!(component, error.message = %e, "A health check failed.");
+    }
+    ComponentHealth {
+        status: if error.is_none() { "up" } else { "down" },
+        latency_ms,
+        error,
+    }
+}
+
+async fn check_database(pool: &PgPool) -> Result<(), anyhow::Error> {
+    sqlx::query!("SELECT 1 AS one")
+        .fetch_one(pool)
+        .await
+        .context("Failed to query the database")?;
+    Ok(())
+}
+
+async fn check_redis(client: &redis::Client) -> Result<(), anyhow::Error> {
+    let mut connection = client
+        .get_async_connection()
+        .await
+        .context("Failed to connect to Redis")?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut connection)
+        .await
+        .context("Redis did not answer the PING")?;
+    Ok(())
+}
+
+// Every migration shipped with this build has been applied
+async fn check_migrations(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let applied: Vec<i64> =
+        sqlx::query!("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(pool)
+            .await
+            .context("Failed to read the applied migrations")?
+            .into_iter()
+            .map(|r| r.version)
+            .collect();
+    let pending: Vec<String> = sqlx::migrate!("./migrations")
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .map(|m| m.version.to_string())
+        .collect();
+    if !pending.is_empty() {
+        anyhow::bail!("Pending migrations: {}", pending.join(", "));
+    }
+    Ok(())
+}
+
+async fn check_worker(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let last = last_heartbeat(pool)
+        .await
+        .context("Failed to read the worker heartbeats")?
+        .context("No delivery worker has started")?;
+    let age = (Utc::now() - last).num_seconds();
+    if age > HEARTBEAT_TIMEOUT_SECONDS {
+        anyhow::bail!("The last worker heartbeat was {}s ago", age);
+    }
+    Ok(())
+}
+
+// Down while the circuit breaker around the provider is not closed
+async fn check_email_provider(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let circuit = get_circuit_status(pool)
+        .await
+        .context("Failed to read the state of the circuit breaker")?;
+    let since = (Utc::now() - circuit.changed_at).num_seconds();
+    match circuit.state {
+        CircuitState::Closed => Ok(()),
+        CircuitState::Open => anyhow::bail!(
+            "The circuit breaker opened {}s ago after {} consecutive \
+            failures, deliveries are paused",
+            since,
+            circuit.consecutive_failures
+        ),
+        CircuitState::HalfOpen => anyhow::bail!(
+            "The circuit breaker has been half-open for {}s, a delivery is \
+            probing the provider",
+            since
+        ),
+    }
+}
@@ -8,6 +8,7 @@ use serde::Deserialize;
 use sqlx::PgPool;
 
 use crate::authentication::{validate_credentials, AuthError, Credentials};
+use crate::metrics::LOGINS;
 use crate::routes::error_chain_fmt;
 use crate::session_state::TypedSession;
 
@@ -38,6 +39,7 @@ pub async fn login(
 
     match validate_credentials(credentials, &pool).await {
         Ok(user_id) => {
+            LOGINS.with_label_values(&["success"]).inc();
             tracing::Span::current()
                 .record("user_id", &tracing::field::display(&user_id));
             session.renew();
@@ -49,6 +51,7 @@ pub async fn login(
                 .finish())
         }
         Err(e) => {
+            LOGINS.with_label_values(&["failure"]).inc();
             let e = match e {
                 AuthError::InvalidCredentials(_) => {
                     LoginError::AuthError(e.into())
@@ -0,0 +1,96 @@
+use actix_web::http::header::{self, ContentType, HeaderMap};
+use actix_web::{web, HttpRequest, HttpResponse};
+use anyhow::Context;
+use secrecy::{ExposeSecret, Secret};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::circuit_breaker::{get_circuit_status, CircuitState};
+use crate::metrics::{
+    encode, CIRCUIT_BREAKER_STATE, DB_POOL_CONNECTIONS,
+    DB_POOL_MAX_CONNECTIONS, QUEUE_DEPTH,
+};
+use crate::startup::MAX_DB_CONNECTIONS;
+use crate::utils::e500;
+
+/// The token scrapers must send as `Authorization: Bearer <token>`,
+/// if any.
+pub struct MetricsToken(pub Option<Secret<String>>);
+
+pub async fn metrics(
+    request: HttpRequest,
+    token: web::Data<MetricsToken>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(token) = &token.0 {
+        if !is_authorized(request.headers(), token) {
+            return Ok(HttpResponse::Unauthorized()
+                .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+                .finish());
+        }
+    }
+
+    refresh_gauges(&pool).await.map_err(e500)?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType(
+            "text/plain; version=0.0.4; charset=utf-8".parse().unwrap(),
+        ))
+        .body(encode().map_err(e500)?))
+}
+
+fn is_authorized(headers: &HeaderMap, token: &Secret<String>) -> bool {
+    let sent = match headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+    {
+        Some(sent) => sent,
+        None => return false,
+    };
+    // Compare digests so the time taken does not leak the token
+    Sha256::digest(sent.as_bytes())
+        == Sha256::digest(token.expose_secret().as_bytes())
+}
+
+// Gauges that are read from the database or the pool rather than updated
+// as things happen
+async fn refresh_gauges(pool: &PgPool) -> Result<(), anyhow::Error> {
+    let depths = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM issue_delivery_queue) AS "issues!",
+            (SELECT COUNT(*) FROM webhook_delivery_queue) AS "webhooks!",
+            (SELECT COUNT(*) FROM confirmation_email_queue) AS "confirmations!"
+        "#
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to measure the delivery queues")?;
+    QUEUE_DEPTH
+        .with_label_values(&["issues"])
+        .set(depths.issues);
+    QUEUE_DEPTH
+        .with_label_values(&["webhooks"])
+        .set(depths.webhooks);
+    QUEUE_DEPTH
+        .with_label_values(&["confirmations"])
+        .set(depths.confirmations);
+
+    let circuit = get_circuit_status(pool)
+        .await
+        .context("Failed to read the state of the circuit breaker")?;
+    for state in CircuitState::ALL {
+        CIRCUIT_BREAKER_STATE
+            .with_label_values(&[state.as_str()])
+            .set(i64::from(state == circuit.state));
+    }
+
+    let size = i64::from(pool.size());
+    let idle = pool.num_idle() as i64;
+    DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+    DB_POOL_CONNECTIONS
+        .with_label_values(&["in_use"])
+        .set(size - idle);
+    DB_POOL_MAX_CONNECTIONS.set(i64::from(MAX_DB_CONNECTIONS));
+    Ok(())
+}
@@ -0,0 +1,62 @@
+mod get;
+mod link;
+mod post;
+
+pub use get::*;
+pub use link::*;
+pub use post::*;
+
+use actix_web::HttpResponse;
+use actix_web_flash_messages::FlashMessage;
+use secrecy::Secret;
+use uuid::Uuid;
+
+use crate::domain::PreferencesToken;
+use crate::utils::see_other;
+
+// How long a preferences link sent by email can be used for
+const LINK_LIFETIME_HOURS: i64 = 24;
+// Unsubscribe links are in every issue and must keep working for a while
+const UNSUBSCRIBE_LINK_LIFETIME_DAYS: i64 = 90;
+
+/// Build a signed link to the preferences page of a subscriber.
+pub fn preferences_url(
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+) -> String {
+    let token = PreferencesToken::new(
+        subscriber_id,
+        chrono::Duration::hours(LINK_LIFETIME_HOURS),
+    );
+    format!("{}/preferences?token={}", base_url, token.sign(hmac_secret))
+}
+
+/// Build the link to the preferences page included in newsletter issues.
+pub fn unsubscribe_url(
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+) -> String {
+    let token = PreferencesToken::new(
+        subscriber_id,
+        chrono::Duration::days(UNSUBSCRIBE_LINK_LIFETIME_DAYS),
+    );
+    format!("{}/preferences?token={}", base_url, token.sign(hmac_secret))
+}
+
+// Check the token carried by a preferences form.
+// If it is not valid anymore, send the subscriber back to request a new link.
+fn authorize(
+    token: &str,
+    hmac_secret: &Secret<String>,
+) -> Result<PreferencesToken, HttpResponse> {
+    PreferencesToken::parse(token, hmac_secret).map_err(|e| {
+        FlashMessage::error(format!("{} Please request a new one.", e)).send();
+        see_other("/preferences/link")
+    })
+}
+
+fn back_to_preferences(token: &str) -> HttpResponse {
+    see_other(&format!("/preferences?token={}", token))
+}
@@ -0,0 +1,158 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use htmlescape::{encode_attribute, encode_minimal};
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::routes::preferences::authorize;
+use crate::startup::HmacSecret;
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct Parameters {
+    token: String,
+}
+
+struct SubscriberPreferences {
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTime<Utc>,
+    paused_until: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(
+    name = "Show subscriber preferences",
+    skip(parameters, pool, hmac_secret, flash_messages)
+)]
+pub async fn preferences(
+    parameters: web::Query<Parameters>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+    flash_messages: IncomingFlashMessages,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = match authorize(&parameters.token, &hmac_secret.0) {
+        Ok(token) => token,
+        Err(response) => return Ok(response),
+    };
+    let subscriber = match get_preferences(&pool, token.subscriber_id())
+        .await
+        .map_err(e500)?
+    {
+        Some(subscriber) => subscriber,
+        None => {
+            FlashMessage::error("We could not find your subscription.").send();
+            return Ok(see_other("/preferences/link"));
+        }
+    };
+
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    let token = encode_attribute(&parameters.token);
+    let name = encode_minimal(&subscriber.name);
+    let email = encode_minimal(&subscriber.email);
+    let subscribed_at = subscriber.subscribed_at.format("%Y-%m-%d");
+    let status = match subscriber.status.as_str() {
+        "confirmed" => "You are subscribed to our newsletter.",
+        "pending_confirmation" => {
+            "Your subscription is waiting for you to confirm your email address."
+        }
+        _ => "You are not subscribed to our newsletter anymore.",
+    };
+    let delivery = match subscriber.paused_until {
+        Some(until) if until > Utc::now() => format!(
+            r#"<p>Delivery is paused until {}.</p>
+    <form action="/preferences/pause" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <input hidden type="number" name="weeks" value="0">
+        <button type="submit">Resume delivery now</button>
+    </form>"#,
+            until.format("%Y-%m-%d")
+        ),
+        _ => format!(
+            r#"<form action="/preferences/pause" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>Pause delivery for
+            <select name="weeks">
+                <option value="1">1 week</option>
+                <option value="2">2 weeks</option>
+                <option value="4">4 weeks</option>
+                <option value="8">8 weeks</option>
+            </select>
+        </label>
+        <button type="submit">Pause</button>
+    </form>"#
+        ),
+    };
+    let unsubscribe = if subscriber.status == "unsubscribed" {
+        String::new()
+    } else {
+        format!(
+            r#"<form action="/preferences/unsubscribe" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <button type="submit">Unsubscribe</button>
+    </form>"#
+        )
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Your subscription</title>
+</head>
+<body>
+    {msg_html}
+    <p>{status}</p>
+    <p>Subscribed as {email} since {subscribed_at}.</p>
+    <form action="/preferences/name" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>Name
+            <input type="text" name="name" value="{name}">
+        </label>
+        <button type="submit">Update name</button>
+    </form>
+    <form action="/preferences/email" method="post">
+        <input hidden type="text" name="token" value="{token}">
+        <label>Email
+            <input type="email" name="email" value="{email}">
+        </label>
+        <button type="submit">Change email</button>
+    </form>
+    {delivery}
+    {unsubscribe}
+    <p><a href="/home">Back to home</a></p>
+</body>
+</html>"#,
+        )))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberPreferences>, anyhow::Error> {
+    let row = sqlx::query_as!(
+        SubscriberPreferences,
+        r#"
+        SELECT email, name, status, subscribed_at, paused_until
+        FROM subscriptions
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to retrieve the subscriber preferences")?;
+    Ok(row)
+}
@@ -0,0 +1,142 @@
+use actix_web::http::header::ContentType;
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::{FlashMessage, IncomingFlashMessages};
+use anyhow::Context;
+use secrecy::Secret;
+use sqlx::PgPool;
+use std::fmt::Write;
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::routes::preferences::preferences_url;
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::suppressions::{
+    send_transactional_email, TransactionalEmailError, TransactionalSend,
+};
+use crate::utils::{e500, see_other};
+
+#[derive(serde::Deserialize)]
+pub struct FormData {
+    email: String,
+}
+
+pub async fn preferences_link_form(
+    flash_messages: IncomingFlashMessages,
+) -> HttpResponse {
+    let mut msg_html = String::new();
+    for m in flash_messages.iter() {
+        writeln!(msg_html, "<p><i>{}</i></p>", m.content()).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Manage your subscription</title>
+</head>
+<body>
+    {msg_html}
+    <p>Enter the address you subscribed with,
+    we will email you a link to manage your subscription.</p>
+    <form action="/preferences/link" method="post">
+        <label>Email
+            <input type="email" placeholder="Enter your email" name="email">
+        </label>
+        <button type="submit">Send me a link</button>
+    </form>
+    <p><a href="/home">Back to home</a></p>
+</body>
+</html>"#,
+        ))
+}
+
+#[tracing::instrument(
+    name = "Send a preferences link",
+    skip(form, pool, email_client, base_url, hmac_secret),
+    fields(subscriber_email = %form.email)
+)]
+pub async fn send_preferences_link(
+    form: web::Form<FormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The answer is the same whether the address is subscribed or not,
+    // to avoid disclosing who is on the list.
+    FlashMessage::info(
+        "If this address is subscribed, \
+        a link to manage your subscription is on its way.",
+    )
+    .send();
+
+    let email = match SubscriberEmail::parse(form.0.email) {
+        Ok(email) => email,
+        Err(_) => return Ok(see_other("/preferences/link")),
+    };
+    if let Some(subscriber_id) =
+        get_subscriber_id(&pool, &email).await.map_err(e500)?
+    {
+        send_preferences_link_email(
+            &pool,
+            &email_client,
+            &email,
+            &base_url.0,
+            &hmac_secret.0,
+            subscriber_id,
+        )
+        .await
+        .context("Failed to send a preferences link")
+        .map_err(e500)?;
+    }
+    Ok(see_other("/preferences/link"))
+}
+
+#[tracing::instrument(skip(pool))]
+async fn get_subscriber_id(
+    pool: &PgPool,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, anyhow::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions WHERE email = $1
+        "#,
+        email.as_ref()
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up the subscriber")?;
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(
+    name = "Send a preferences link email",
+    skip(pool, email_client, hmac_secret)
+)]
+async fn send_preferences_link_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    email: &SubscriberEmail,
+    base_url: &str,
+    hmac_secret: &Secret<String>,
+    subscriber_id: Uuid,
+) -> Result<TransactionalSend, TransactionalEmailError> {
+    let link = preferences_url(base_url, hmac_secret, subscriber_id);
+    send_transactional_email(
+        pool,
+        email_client,
+        email,
+        "preferences_link",
+        "Manage your subscription",
+        &format!(
+            "Click <a href=\"{}\">here</a> to manage your subscription.",
+            link
+        ),
+        &format!("Visit {} to manage your subscription.", link),
+    )
+    .await
+}
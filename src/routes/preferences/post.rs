@@ -0,0 +1,291 @@
+use actix_web::{web, HttpResponse};
+use actix_web_flash_messages::FlashMessage;
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::email_client::EmailClient;
+use crate::outgoing_webhooks::{enqueue_event, WebhookEvent};
+use crate::routes::generate_subscription_token;
+use crate::routes::preferences::{authorize, back_to_preferences};
+use crate::startup::{ApplicationBaseUrl, HmacSecret};
+use crate::suppressions::{
+    send_transactional_email, suppress_address, TransactionalEmailError,
+    TransactionalSend,
+};
+use crate::utils::{e400, e500};
+
+#[derive(serde::Deserialize)]
+pub struct NameFormData {
+    token: String,
+    name: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct EmailFormData {
+    token: String,
+    email: String,
+}
+
+#[derive(serde::Deserialize)]
+pub struct PauseFormData {
+    token: String,
+    weeks: i64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UnsubscribeFormData {
+    token: String,
+}
+
+#[tracing::instrument(
+    name = "Update subscriber name",
+    skip(form, pool, hmac_secret)
+)]
+pub async fn update_name(
+    form: web::Form<NameFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let NameFormData { token, name } = form.into_inner();
+    let subscriber_id = match authorize(&token, &hmac_secret.0) {
+        Ok(t) => t.subscriber_id(),
+        Err(response) => return Ok(response),
+    };
+    let name = match SubscriberName::parse(name) {
+        Ok(name) => name,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(back_to_preferences(&token));
+        }
+    };
+
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET name = $2 WHERE id = $1
+        "#,
+        subscriber_id,
+        name.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to update the subscriber name")
+    .map_err(e500)?;
+
+    FlashMessage::info("Your name has been updated.").send();
+    Ok(back_to_preferences(&token))
+}
+
+#[tracing::instrument(
+    name = "Request a subscriber email change",
+    skip(form, pool, email_client, base_url, hmac_secret)
+)]
+pub async fn change_email(
+    form: web::Form<EmailFormData>,
+    pool: web::Data<PgPool>,
+    email_client: web::Data<EmailClient>,
+    base_url: web::Data<ApplicationBaseUrl>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let EmailFormData { token, email } = form.into_inner();
+    let subscriber_id = match authorize(&token, &hmac_secret.0) {
+        Ok(t) => t.subscriber_id(),
+        Err(response) => return Ok(response),
+    };
+    let new_email = match SubscriberEmail::parse(email) {
+        Ok(email) => email,
+        Err(e) => {
+            FlashMessage::error(e).send();
+            return Ok(back_to_preferences(&token));
+        }
+    };
+
+    // The new address is only used once its owner confirms it,
+    // through the same token mechanism as new subscriptions.
+    let subscription_token = generate_subscription_token();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_token (
+            subscription_token,
+            subscriber_id,
+            new_email,
+            created_at
+        )
+        VALUES ($1, $2, $3, now())
+        "#,
+        subscription_token,
+        subscriber_id,
+        new_email.as_ref()
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store the email change token")
+    .map_err(e500)?;
+
+    send_email_change_confirmation(
+        &pool,
+        &email_client,
+        &new_email,
+        &base_url.0,
+        &subscription_token,
+    )
+    .await
+    .context("Failed to send the email change confirmation")
+    .map_err(e500)?;
+
+    FlashMessage::info(format!(
+        "We sent a confirmation link to {}. \
+        Your address will change once you click it.",
+        new_email
+    ))
+    .send();
+    Ok(back_to_preferences(&token))
+}
+
+#[tracing::instrument(
+    name = "Send an email change confirmation",
+    skip(pool, email_client)
+)]
+async fn send_email_change_confirmation(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    subscription_token: &str,
+) -> Result<TransactionalSend, TransactionalEmailError> {
+    let confirmation_link = format!(
+        "{}/subscriptions/confirm?subscription_token={}",
+        base_url, subscription_token
+    );
+    send_transactional_email(
+        pool,
+        email_client,
+        new_email,
+        "email_change",
+        "Confirm your new email address",
+        &format!(
+            "Click <a href=\"{}\">here</a> to receive our newsletter \
+            at this address.",
+            confirmation_link
+        ),
+        &format!(
+            "Visit {} to receive our newsletter at this address.",
+            confirmation_link
+        ),
+    )
+    .await
+}
+
+#[tracing::instrument(
+    name = "Pause newsletter delivery",
+    skip(form, pool, hmac_secret),
+    fields(weeks = form.weeks)
+)]
+pub async fn pause_delivery(
+    form: web::Form<PauseFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let PauseFormData { token, weeks } = form.into_inner();
+    let subscriber_id = match authorize(&token, &hmac_secret.0) {
+        Ok(t) => t.subscriber_id(),
+        Err(response) => return Ok(response),
+    };
+    if !(0..=52).contains(&weeks) {
+        return Err(e400("Delivery can be paused for up to 52 weeks."));
+    }
+
+    // Pausing for zero weeks resumes delivery straight away.
+    let paused_until =
+        (weeks > 0).then(|| Utc::now() + chrono::Duration::weeks(weeks));
+    set_paused_until(&pool, subscriber_id, paused_until)
+        .await
+        .map_err(e500)?;
+
+    if weeks > 0 {
+        FlashMessage::info("Delivery has been paused.").send();
+    } else {
+        FlashMessage::info("Delivery has been resumed.").send();
+    }
+    Ok(back_to_preferences(&token))
+}
+
+async fn set_paused_until(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+    paused_until: Option<chrono::DateTime<Utc>>,
+) -> Result<(), anyhow::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET paused_until = $2 WHERE id = $1
+        "#,
+        subscriber_id,
+        paused_until
+    )
+    .execute(pool)
+    .await
+    .context("Failed to update the subscriber pause")?;
+    Ok(())
+}
+
+#[tracing::instrument(name = "Unsubscribe", skip(form, pool, hmac_secret))]
+pub async fn unsubscribe(
+    form: web::Form<UnsubscribeFormData>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let subscriber_id = match authorize(&form.token, &hmac_secret.0) {
+        Ok(t) => t.subscriber_id(),
+        Err(response) => return Ok(response),
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")
+        .map_err(e500)?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = 'unsubscribed' WHERE id = $1
+        RETURNING email
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to unsubscribe the subscriber")
+    .map_err(e500)?;
+    // Keep the address out of the sends until it subscribes again
+    if let Some(row) = row {
+        suppress_address(
+            &mut transaction,
+            &row.email,
+            "unsubscribe",
+            "Unsubscribed from the preferences page",
+        )
+        .await
+        .context("Failed to suppress the address")
+        .map_err(e500)?;
+        enqueue_event(
+            &mut transaction,
+            WebhookEvent::Unsubscribed,
+            serde_json::json!({
+                "subscriber_id": subscriber_id,
+                "email": row.email,
+            }),
+        )
+        .await
+        .context("Failed to enqueue the unsubscribed webhook event")
+        .map_err(e500)?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe")
+        .map_err(e500)?;
+
+    FlashMessage::info("You have been unsubscribed.").send();
+    Ok(back_to_preferences(&form.token))
+}
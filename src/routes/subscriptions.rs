@@ -10,17 +10,31 @@ use sqlx::Postgres;
 use sqlx::Transaction;
 use uuid::Uuid;
 
+use crate::domain::Layout;
 use crate::domain::NewSubscriber;
+use crate::domain::Recipient;
 use crate::domain::SubscriberEmail;
 use crate::domain::SubscriberName;
+use crate::domain::Template;
 use crate::email_client::EmailClient;
+use crate::layouts::get_default_layout;
+use crate::mailing_lists::{add_memberships, select_lists};
+use crate::outgoing_webhooks::{enqueue_event, WebhookEvent};
 use crate::startup::ApplicationBaseUrl;
+use crate::suppressions::{
+    find_suppression, log_suppressed_send, send_transactional_email,
+    TransactionalEmailError, TransactionalSend,
+};
+use crate::utils::HtmlForm;
 
 #[allow(dead_code)]
 #[derive(serde::Deserialize)]
 pub struct FormData {
     email: String,
     name: String,
+    // Repeated once per list, the default lists are used if there is none
+    #[serde(default, rename = "list_id")]
+    list_ids: Vec<Uuid>,
 }
 
 impl TryFrom<FormData> for NewSubscriber {
@@ -43,24 +57,114 @@ impl TryFrom<FormData> for NewSubscriber {
     )
 )]
 pub async fn subscribe(
-    form: web::Form<FormData>,
+    form: HtmlForm<FormData>,
     connection_pool: web::Data<PgPool>,
     email_client: web::Data<EmailClient>,
     base_url: web::Data<ApplicationBaseUrl>,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form
-        .into_inner()
-        .try_into()
-        .map_err(SubscribeError::ValidationError)?;
+    let mut form = form.into_inner();
+    let list_ids = std::mem::take(&mut form.list_ids);
+    let new_subscriber: NewSubscriber =
+        form.try_into().map_err(SubscribeError::ValidationError)?;
+    let list_ids = select_lists(&connection_pool, &list_ids)
+        .await
+        .context("Failed to look up the mailing lists")?
+        .ok_or_else(|| {
+            SubscribeError::ValidationError("Unknown mailing list.".into())
+        })?;
 
     let mut transaction = connection_pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber)
-        .await
-        .context("Failed to insert new subscriber in the database")?;
+    let suppression =
+        find_suppression(&mut transaction, new_subscriber.email.as_ref())
+            .await
+            .context("Failed to look up the suppressions")?;
+    if let Some(suppression) = suppression.filter(|s| s.blocks_subscriptions())
+    {
+        return skip_confirmation_email(
+            transaction,
+            &new_subscriber,
+            &suppression.describe(),
+        )
+        .await;
+    }
+
+    let (subscriber_id, is_confirmed) =
+        match insert_subscriber(&mut transaction, &new_subscriber)
+            .await
+            .context("Failed to insert new subscriber in the database")?
+        {
+            Some(subscriber_id) => {
+                notify_subscribed(
+                    &mut transaction,
+                    subscriber_id,
+                    &new_subscriber,
+                )
+                .await
+                .context("Failed to enqueue the subscribed webhook event")?;
+                (subscriber_id, false)
+            }
+            None => {
+                let (subscriber_id, status) =
+                    get_subscriber(&mut transaction, &new_subscriber.email)
+                        .await
+                        .context(
+                            "Failed to look up the subscriber in the database",
+                        )?;
+                match status.as_str() {
+                    // They left and want to come back: confirm the address
+                    // again.
+                    "unsubscribed" => {
+                        resubscribe(&mut transaction, subscriber_id)
+                            .await
+                            .context(
+                                "Failed to resubscribe a former subscriber",
+                            )?;
+                        notify_subscribed(
+                            &mut transaction,
+                            subscriber_id,
+                            &new_subscriber,
+                        )
+                        .await
+                        .context(
+                            "Failed to enqueue the subscribed webhook event",
+                        )?;
+                        (subscriber_id, false)
+                    }
+                    // Only an admin can bring them back
+                    "bounced" | "complained" => {
+                        let reason = format!(
+                            "The address {} has {}",
+                            new_subscriber.email.as_ref(),
+                            status
+                        );
+                        return skip_confirmation_email(
+                            transaction,
+                            &new_subscriber,
+                            &reason,
+                        )
+                        .await;
+                    }
+                    // Pending subscribers lost the first confirmation email
+                    // and get a fresh token, confirmed ones may be joining a
+                    // new list.
+                    _ => (subscriber_id, status == "confirmed"),
+                }
+            }
+        };
+
+    let joined_lists =
+        add_memberships(&mut transaction, subscriber_id, &list_ids)
+            .await
+            .context("Failed to add the subscriber to the mailing lists")?;
+    if is_confirmed && joined_lists == 0 {
+        // Respond exactly as we would to a new subscriber, to avoid
+        // disclosing who is on the list.
+        return Ok(HttpResponse::Ok().finish());
+    }
 
     let subscription_token = generate_subscription_token();
 
@@ -72,11 +176,14 @@ pub async fn subscribe(
         "Failed to commit SQL transaction to store a new subscriber",
     )?;
 
+    let layout = get_default_layout(&connection_pool).await?;
     send_confirmation_email(
+        &connection_pool,
         &email_client,
         new_subscriber,
         &base_url.0,
         &subscription_token,
+        layout.as_ref(),
     )
     .await
     .context("Failed to send a confirmation email")?;
@@ -86,35 +193,135 @@ pub async fn subscribe(
 
 #[tracing::instrument(
     name = "Send a confirmation email to a subscriber",
-    skip(email_client)
+    skip(pool, email_client)
 )]
 pub async fn send_confirmation_email(
+    pool: &PgPool,
     email_client: &EmailClient,
     new_subscriber: NewSubscriber,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    layout: Option<&Layout>,
+) -> Result<TransactionalSend, TransactionalEmailError> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
+    let html_body = format!(
+        "<p>Welcome to our newsletter!</p>
+        <p>Click <a href=\"{}\">here</a> to confirm your subscription.</p>",
+        confirmation_link
+    );
+    let html_body = match layout {
+        Some(layout) => {
+            match render_in_layout(layout, &html_body, &new_subscriber) {
+                Ok(html_body) => html_body,
+                Err(e) => {
+                    tracing::warn!(
+                        error.message = %e,
+                        "Failed to render the default layout, \
+                        sending the confirmation email without it.",
+                    );
+                    html_body
+                }
+            }
+        }
+        None => html_body,
+    };
     // send a (useless) email to the new subscriber
-    email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome",
-            &format!(
-                "Welcome to our newsletter!<br/>
-            Click <a href=\"{}\">here</a> to confirm your subscription.",
-                confirmation_link
-            ),
-            &format!(
-                "Welcome to our newsletter!\n
+    send_transactional_email(
+        pool,
+        email_client,
+        &new_subscriber.email,
+        "subscribe",
+        "Welcome",
+        &html_body,
+        &format!(
+            "Welcome to our newsletter!\n
             Visit {} to confirm your subscription.",
-                confirmation_link
-            ),
-        )
+            confirmation_link
+        ),
+    )
+    .await
+}
+
+// Layouts can use merge tags, the subscriber is not confirmed yet
+// so there are no custom fields and nothing to unsubscribe from.
+fn render_in_layout(
+    layout: &Layout,
+    content: &str,
+    new_subscriber: &NewSubscriber,
+) -> Result<String, String> {
+    let custom_fields = serde_json::json!({});
+    let recipient = Recipient {
+        name: new_subscriber.name.as_ref(),
+        email: new_subscriber.email.as_ref(),
+        unsubscribe_url: "",
+        custom_fields: &custom_fields,
+    };
+    Ok(Template::parse(&layout.render(content)?)?.render(&recipient, true))
+}
+
+// Respond as if the confirmation email was sent, to avoid disclosing which
+// addresses are suppressed.
+async fn skip_confirmation_email(
+    mut transaction: Transaction<'_, Postgres>,
+    new_subscriber: &NewSubscriber,
+    reason: &str,
+) -> Result<HttpResponse, SubscribeError> {
+    log_suppressed_send(
+        &mut transaction,
+        new_subscriber.email.as_ref(),
+        "subscribe",
+        None,
+        reason,
+    )
+    .await
+    .context("Failed to log the suppressed confirmation email")?;
+    transaction
+        .commit()
         .await
+        .context("Failed to commit SQL transaction to log a suppression")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Lock an existing subscriber and get their id and status.
+#[tracing::instrument(
+    name = "Get subscriber id and status by email",
+    skip(transaction)
+)]
+pub async fn get_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &SubscriberEmail,
+) -> Result<(Uuid, String), sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, status FROM subscriptions WHERE email = $1
+        FOR UPDATE
+        "#,
+        email.as_ref()
+    )
+    .fetch_one(transaction)
+    .await?;
+    Ok((row.id, row.status))
+}
+
+#[tracing::instrument(name = "Resubscribe a subscriber", skip(transaction))]
+pub async fn resubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET status = 'pending_confirmation', paused_until = NULL
+        WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
 }
 
 #[tracing::instrument(
@@ -124,29 +331,49 @@ pub async fn send_confirmation_email(
 pub async fn insert_subscriber(
     transaction: &mut Transaction<'_, Postgres>,
     new_subscriber: &NewSubscriber,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-    let _a = sqlx::query!(
+) -> Result<Option<Uuid>, sqlx::Error> {
+    // Concurrent subscriptions of the same address wait for each other
+    // here, the later ones find the subscriber already there.
+    let row = sqlx::query!(
         r#"
         INSERT INTO subscriptions (id, email, name, subscribed_at, status)
         VALUES ($1, $2, $3, $4, 'pending_confirmation')
+        ON CONFLICT (email) DO NOTHING
+        RETURNING id
         "#,
-        subscriber_id,
+        Uuid::new_v4(),
         new_subscriber.email.as_ref(),
         new_subscriber.name.as_ref(),
         Utc::now()
     )
-    .execute(transaction)
+    .fetch_optional(transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
-    Ok(subscriber_id)
+    Ok(row.map(|r| r.id))
+}
+
+async fn notify_subscribed(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    new_subscriber: &NewSubscriber,
+) -> Result<(), sqlx::Error> {
+    enqueue_event(
+        transaction,
+        WebhookEvent::Subscribed,
+        serde_json::json!({
+            "subscriber_id": subscriber_id,
+            "email": new_subscriber.email.as_ref(),
+            "name": new_subscriber.name.as_ref(),
+        }),
+    )
+    .await
 }
 
 // Generate a random 25-character-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = rand::thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
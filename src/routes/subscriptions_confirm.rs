@@ -1,7 +1,15 @@
+use actix_web::http::header::ContentType;
+use actix_web::http::StatusCode;
 use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::outgoing_webhooks::{enqueue_event, WebhookEvent};
+use crate::startup::SubscriptionTokenExpiry;
+use crate::suppressions::lift_unsubscribe;
+use crate::utils::is_unique_violation;
+
 #[derive(serde::Deserialize)]
 pub struct Parameters {
     subscription_token: String,
@@ -10,53 +18,113 @@ pub struct Parameters {
 #[allow(clippy::async_yields_async)]
 #[tracing::instrument(
     name = "Confirm a pending subscriber",
-    skip(parameters, pool)
+    skip(parameters, pool, expiry)
 )]
 pub async fn subscribe_confirm(
     parameters: web::Query<Parameters>,
     pool: web::Data<PgPool>,
+    expiry: web::Data<SubscriptionTokenExpiry>,
 ) -> HttpResponse {
-    // 1. get subscriber_id from subscription_token
-    let id = match get_subscriber_id_from_token(
-        &pool,
-        &parameters.subscription_token,
-    )
-    .await
-    {
-        Ok(id) => id,
-        Err(err) => {
-            tracing::error!("{}", err);
-            return HttpResponse::InternalServerError().finish();
-        }
-    };
+    // 1. get subscriber from subscription_token
+    let token =
+        match get_token_details(&pool, &parameters.subscription_token).await {
+            Ok(token) => token,
+            Err(err) => {
+                tracing::error!("{}", err);
+                return error_page();
+            }
+        };
 
-    // 2. update subscriber_id to confirmed
-    match id {
+    let token = match token {
+        Some(token) => token,
         None => {
             tracing::error!("No subscriber_id found");
-            HttpResponse::Unauthorized().finish()
+            return invalid_token_page();
         }
-        Some(id) => {
-            if let Err(err) = update_subscriber_to_confirmed(&pool, id).await {
+    };
+    let is_expired = token.created_at <= Utc::now() - expiry.0;
+
+    // 2a. tokens carrying a new email confirm an email change
+    if let Some(new_email) = &token.new_email {
+        if *new_email == token.email {
+            return email_updated_page();
+        }
+        if is_expired {
+            tracing::error!("The email change token has expired");
+            return expired_token_page();
+        }
+        return match update_subscriber_email(
+            &pool,
+            token.subscriber_id,
+            new_email,
+        )
+        .await
+        {
+            Ok(()) => email_updated_page(),
+            Err(err) if is_unique_violation(&err) => email_taken_page(),
+            Err(err) => {
                 tracing::error!("{}", err);
-                return HttpResponse::InternalServerError().finish();
+                error_page()
             }
-            HttpResponse::Ok().finish()
+        };
+    }
+
+    // 2b. update subscriber to confirmed, unless there is nothing to do
+    if token.status == "confirmed" && !token.has_pending_memberships {
+        return already_confirmed_page();
+    }
+    if is_expired {
+        tracing::error!("The subscription token has expired");
+        return expired_token_page();
+    }
+    match update_subscriber_to_confirmed(&pool, token.subscriber_id).await {
+        Ok(true) => confirmed_page(),
+        // They left since: links sent before do not bring them back
+        Ok(false) => {
+            tracing::error!("The subscriber is no longer pending");
+            expired_token_page()
+        }
+        Err(err) => {
+            tracing::error!("{}", err);
+            error_page()
         }
     }
 }
 
+pub struct TokenDetails {
+    pub subscriber_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub new_email: Option<String>,
+    pub email: String,
+    pub status: String,
+    pub has_pending_memberships: bool,
+}
+
 #[tracing::instrument(
-    name = "Get subscriber id from token",
+    name = "Get subscriber details from token",
     skip(connection_pool)
 )]
-pub async fn get_subscriber_id_from_token(
+pub async fn get_token_details(
     connection_pool: &PgPool,
     subscription_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
+) -> Result<Option<TokenDetails>, sqlx::Error> {
+    sqlx::query_as!(
+        TokenDetails,
         r#"
-        SELECT subscriber_id FROM subscription_token WHERE subscription_token = $1
+        SELECT
+            t.subscriber_id,
+            t.created_at,
+            t.new_email,
+            s.email,
+            s.status,
+            EXISTS (
+                SELECT 1 FROM list_memberships m
+                WHERE m.subscriber_id = t.subscriber_id
+                AND m.confirmed_at IS NULL
+            ) AS "has_pending_memberships!"
+        FROM subscription_token t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
         "#,
         subscription_token
     )
@@ -65,8 +133,7 @@ pub async fn get_subscriber_id_from_token(
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
-    })?;
-    Ok(result.map(|row| row.subscriber_id))
+    })
 }
 
 #[tracing::instrument(
@@ -76,18 +143,164 @@ pub async fn get_subscriber_id_from_token(
 pub async fn update_subscriber_to_confirmed(
     connection_pool: &PgPool,
     subscriber_id: Uuid,
-) -> Result<(), sqlx::Error> {
-    let _result = sqlx::query!(
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = connection_pool.begin().await?;
+    let subscriber = sqlx::query!(
         r#"
-        UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
+        SELECT email, status FROM subscriptions WHERE id = $1
+        FOR UPDATE
         "#,
         subscriber_id
     )
-    .execute(connection_pool)
+    .fetch_one(&mut transaction)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to execute query: {:?}", e);
+        e
+    })?;
+    match subscriber.status.as_str() {
+        "pending_confirmation" => {
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions SET status = 'confirmed' WHERE id = $1
+                "#,
+                subscriber_id
+            )
+            .execute(&mut transaction)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to execute query: {:?}", e);
+                e
+            })?;
+            enqueue_event(
+                &mut transaction,
+                WebhookEvent::Confirmed,
+                serde_json::json!({
+                    "subscriber_id": subscriber_id,
+                    "email": subscriber.email,
+                }),
+            )
+            .await?;
+            // They subscribed again after unsubscribing
+            lift_unsubscribe(&mut transaction, subscriber_id).await?;
+        }
+        // Nothing changes for confirmed subscribers joining more lists
+        "confirmed" => {}
+        // Unsubscribed, bounced or complained since the link was sent
+        _ => return Ok(false),
+    }
+    // Confirming the address also confirms the lists it was signed up to
+    sqlx::query!(
+        r#"
+        UPDATE list_memberships SET confirmed_at = now()
+        WHERE subscriber_id = $1 AND confirmed_at IS NULL
+        "#,
+        subscriber_id
+    )
+    .execute(&mut transaction)
     .await
     .map_err(|e| {
         tracing::error!("Failed to execute query: {:?}", e);
         e
     })?;
+    transaction.commit().await?;
+    Ok(true)
+}
+
+#[tracing::instrument(name = "Update subscriber email", skip(connection_pool))]
+pub async fn update_subscriber_email(
+    connection_pool: &PgPool,
+    subscriber_id: Uuid,
+    new_email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions SET email = $2 WHERE id = $1
+        "#,
+        subscriber_id,
+        new_email
+    )
+    .execute(connection_pool)
+    .await?;
     Ok(())
 }
+
+fn confirmed_page() -> HttpResponse {
+    page(
+        StatusCode::OK,
+        "Subscription confirmed",
+        "Thanks for confirming your subscription, \
+        the next issue will land in your inbox.",
+    )
+}
+
+fn already_confirmed_page() -> HttpResponse {
+    page(
+        StatusCode::OK,
+        "Already confirmed",
+        "Your subscription has already been confirmed, \
+        there is nothing else to do.",
+    )
+}
+
+fn email_updated_page() -> HttpResponse {
+    page(
+        StatusCode::OK,
+        "Email address updated",
+        "From now on the newsletter will be sent to this address.",
+    )
+}
+
+fn email_taken_page() -> HttpResponse {
+    page(
+        StatusCode::CONFLICT,
+        "Email address already subscribed",
+        "This address is already subscribed to our newsletter, \
+        so we kept your previous one.",
+    )
+}
+
+fn expired_token_page() -> HttpResponse {
+    page(
+        StatusCode::UNAUTHORIZED,
+        "Confirmation link expired",
+        "This confirmation link has expired. \
+        Subscribe again to receive a new one.",
+    )
+}
+
+fn invalid_token_page() -> HttpResponse {
+    page(
+        StatusCode::UNAUTHORIZED,
+        "Invalid confirmation link",
+        "This confirmation link is not valid. \
+        Make sure you copied the whole link from the email.",
+    )
+}
+
+fn error_page() -> HttpResponse {
+    page(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Something went wrong",
+        "We could not confirm your subscription, please try again later.",
+    )
+}
+
+fn page(status: StatusCode, title: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{message}</p>
+    <p><a href="/home">Back to home</a></p>
+</body>
+</html>"#,
+        ))
+}
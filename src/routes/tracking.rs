@@ -0,0 +1,78 @@
+use actix_web::http::header::{self, CacheControl, CacheDirective};
+use actix_web::{web, HttpResponse};
+use sqlx::PgPool;
+
+use crate::domain::TrackingToken;
+use crate::email_tracking::{is_issue_link, record_event, EmailEvent};
+use crate::startup::HmacSecret;
+use crate::utils::e500;
+
+// A transparent 1x1 GIF
+const PIXEL: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0xf9, 0x04, 0x01, 0x00,
+    0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00,
+    0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// The tracking pixel of an issue, loaded when a subscriber opens it.
+#[tracing::instrument(name = "Track an email open", skip_all)]
+pub async fn track_open(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> HttpResponse {
+    match TrackingToken::parse(&token, &hmac_secret.0) {
+        Ok(token) if token.link.is_none() => {
+            // The pixel is served even if the event is lost
+            if let Err(e) = record_event(&pool, EmailEvent::Open, &token).await
+            {
+                tracing::error!(error.cause_chain = ?e, "{}", e);
+            }
+        }
+        _ => return HttpResponse::NotFound().finish(),
+    }
+    HttpResponse::Ok()
+        .content_type("image/gif")
+        .insert_header(CacheControl(vec![
+            CacheDirective::NoStore,
+            CacheDirective::Private,
+        ]))
+        .body(PIXEL.to_vec())
+}
+
+/// Redirect a subscriber to the destination of a link of an issue.
+/// Only the links of the issue itself are followed, so that the redirect
+/// cannot be used to send people anywhere else.
+#[tracing::instrument(name = "Track a link click", skip_all)]
+pub async fn track_click(
+    token: web::Path<String>,
+    pool: web::Data<PgPool>,
+    hmac_secret: web::Data<HmacSecret>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let token = match TrackingToken::parse(&token, &hmac_secret.0) {
+        Ok(token) => token,
+        Err(_) => return Ok(HttpResponse::NotFound().finish()),
+    };
+    let link = match &token.link {
+        Some(link) => link,
+        None => return Ok(HttpResponse::NotFound().finish()),
+    };
+    if !is_issue_link(&pool, token.newsletter_issue_id, link)
+        .await
+        .map_err(e500)?
+    {
+        tracing::warn!(
+            newsletter_issue_id = %token.newsletter_issue_id,
+            "Rejected a redirect to a link that is not part of the issue"
+        );
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    // The subscriber gets to the link even if the event is lost
+    if let Err(e) = record_event(&pool, EmailEvent::Click, &token).await {
+        tracing::error!(error.cause_chain = ?e, "{}", e);
+    }
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, link.as_str()))
+        .finish())
+}
@@ -0,0 +1,358 @@
+use actix_web::http::header::{self, HeaderMap, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use secrecy::ExposeSecret;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::configuration::PostmarkWebhookSettings;
+use crate::outgoing_webhooks::{enqueue_event, WebhookEvent};
+use crate::routes::error_chain_fmt;
+use crate::suppressions::suppress_address;
+
+#[derive(thiserror::Error)]
+pub enum WebhookError {
+    #[error("Authentication failed.")]
+    AuthError(#[source] anyhow::Error),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for WebhookError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            WebhookError::ValidationError(_) => {
+                HttpResponse::new(StatusCode::BAD_REQUEST)
+            }
+            WebhookError::UnexpectedError(_) => {
+                HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+            WebhookError::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value =
+                    HeaderValue::from_str(r#"Basic realm="postmark""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+}
+
+// The records Postmark sends to the webhook, see
+// https://postmarkapp.com/developer/webhooks/webhooks-overview
+#[derive(serde::Deserialize)]
+#[serde(tag = "RecordType")]
+enum PostmarkRecord {
+    Bounce(Bounce),
+    SpamComplaint(Bounce),
+    SubscriptionChange(SubscriptionChange),
+    // Deliveries, opens and clicks are acknowledged and ignored
+    #[serde(other)]
+    Other,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Bounce {
+    email: String,
+    bounced_at: DateTime<Utc>,
+    #[serde(rename = "Type")]
+    kind: String,
+    description: Option<String>,
+    // Postmark stopped sending to the address, e.g. after a hard bounce
+    #[serde(default)]
+    inactive: bool,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SubscriptionChange {
+    recipient: String,
+    changed_at: DateTime<Utc>,
+    suppress_sending: bool,
+    // 'HardBounce', 'SpamComplaint' or 'ManualSuppression'
+    suppression_reason: Option<String>,
+}
+
+/// What a record means for the subscriber it is about.
+struct Feedback {
+    email: String,
+    kind: &'static str,
+    description: Option<String>,
+    // The subscription status to set, if the address must not be mailed
+    new_status: Option<&'static str>,
+    occurred_at: DateTime<Utc>,
+}
+
+impl PostmarkRecord {
+    fn feedback(self) -> Option<Feedback> {
+        match self {
+            PostmarkRecord::Bounce(bounce) => Some(Feedback {
+                email: bounce.email,
+                kind: "bounce",
+                description: Some(match bounce.description {
+                    Some(d) => format!("{}: {}", bounce.kind, d),
+                    None => bounce.kind,
+                }),
+                // Soft bounces are only recorded
+                new_status: if bounce.inactive {
+                    Some("bounced")
+                } else {
+                    None
+                },
+                occurred_at: bounce.bounced_at,
+            }),
+            PostmarkRecord::SpamComplaint(complaint) => Some(Feedback {
+                email: complaint.email,
+                kind: "spam_complaint",
+                description: complaint.description,
+                new_status: Some("complained"),
+                occurred_at: complaint.bounced_at,
+            }),
+            PostmarkRecord::SubscriptionChange(change) => {
+                let new_status = match change.suppression_reason.as_deref() {
+                    _ if !change.suppress_sending => None,
+                    Some("HardBounce") => Some("bounced"),
+                    Some("SpamComplaint") => Some("complained"),
+                    _ => Some("unsubscribed"),
+                };
+                let description = if change.suppress_sending {
+                    change.suppression_reason
+                } else {
+                    Some("Reactivated".into())
+                };
+                Some(Feedback {
+                    email: change.recipient,
+                    kind: "subscription_change",
+                    description,
+                    new_status,
+                    occurred_at: change.changed_at,
+                })
+            }
+            PostmarkRecord::Other => None,
+        }
+    }
+}
+
+/// Bounces, spam complaints and subscription changes reported by Postmark.
+/// Subscribers who must not be mailed anymore are taken out of the queue
+/// and their address is suppressed.
+#[tracing::instrument(
+    name = "Receive a Postmark webhook",
+    skip_all,
+    fields(email = tracing::field::Empty, kind = tracing::field::Empty)
+)]
+pub async fn postmark_webhook(
+    request: HttpRequest,
+    payload: web::Json<serde_json::Value>,
+    pool: web::Data<PgPool>,
+    settings: web::Data<PostmarkWebhookSettings>,
+) -> Result<HttpResponse, WebhookError> {
+    authenticate(request.headers(), &settings)
+        .map_err(WebhookError::AuthError)?;
+    let payload = payload.into_inner();
+    let record: PostmarkRecord = serde_json::from_value(payload.clone())
+        .map_err(|e| WebhookError::ValidationError(e.to_string()))?;
+    let feedback = match record.feedback() {
+        Some(feedback) => feedback,
+        None => return Ok(HttpResponse::Ok().finish()),
+    };
+    tracing::Span::current()
+        .record("email", &tracing::field::display(&feedback.email))
+        .record("kind", &feedback.kind);
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let subscriber_id = match feedback.new_status {
+        Some(status) => {
+            let subscriber_id =
+                set_status(&mut transaction, &feedback.email, status)
+                    .await
+                    .context("Failed to update the subscription status")?;
+            remove_tasks(&mut transaction, &feedback.email)
+                .await
+                .context("Failed to remove the deliveries of the address")?;
+            let source = match status {
+                "bounced" => "bounce",
+                "complained" => "complaint",
+                _ => "unsubscribe",
+            };
+            let reason = feedback.description.as_deref().unwrap_or(status);
+            suppress_address(&mut transaction, &feedback.email, source, reason)
+                .await
+                .context("Failed to suppress the address")?;
+            if let (Some(subscriber_id), "unsubscribed") =
+                (subscriber_id, status)
+            {
+                enqueue_event(
+                    &mut transaction,
+                    WebhookEvent::Unsubscribed,
+                    serde_json::json!({
+                        "subscriber_id": subscriber_id,
+                        "email": feedback.email,
+                    }),
+                )
+                .await
+                .context("Failed to enqueue the unsubscribed webhook event")?;
+            }
+            subscriber_id
+        }
+        None => get_subscriber_id(&mut transaction, &feedback.email)
+            .await
+            .context("Failed to look up the subscriber")?,
+    };
+    record_feedback(&mut transaction, &feedback, subscriber_id, &payload)
+        .await
+        .context("Failed to record the feedback event")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record feedback")?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+fn authenticate(
+    headers: &HeaderMap,
+    settings: &PostmarkWebhookSettings,
+) -> Result<(), anyhow::Error> {
+    let password = match headers.get("X-Webhook-Secret") {
+        Some(secret) => secret
+            .to_str()
+            .context("The 'X-Webhook-Secret' header was not a valid string.")?
+            .to_owned(),
+        None => {
+            let (username, password) = basic_credentials(headers)?;
+            if username != settings.username {
+                return Err(anyhow!("Unknown username."));
+            }
+            password
+        }
+    };
+    // Compare digests, so that the time taken does not depend on how
+    // much of the password is right
+    if Sha256::digest(password.as_bytes())
+        == Sha256::digest(settings.password.expose_secret().as_bytes())
+    {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid password."))
+    }
+}
+
+fn basic_credentials(
+    headers: &HeaderMap,
+) -> Result<(String, String), anyhow::Error> {
+    let header_value = headers
+        .get("Authorization")
+        .context("The 'Authorization' header was missing.")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid string.")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'.")?;
+    let decoded_bytes =
+        base64::decode_config(base64encoded_segment, base64::STANDARD)
+            .context("Failed to base64-decode 'Basic' credentials.")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8.")?;
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth.")?;
+    Ok((username.to_owned(), password.to_owned()))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn set_status(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    status: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row = sqlx::query!(
+        r#"
+        UPDATE subscriptions SET status = $2 WHERE email = $1
+        RETURNING id
+        "#,
+        email,
+        status
+    )
+    .fetch_optional(transaction)
+    .await?;
+    Ok(row.map(|r| r.id))
+}
+
+#[tracing::instrument(skip(transaction))]
+async fn get_subscriber_id(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let row =
+        sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_optional(transaction)
+            .await?;
+    Ok(row.map(|r| r.id))
+}
+
+// Issues being delivered must not reach the address either
+#[tracing::instrument(skip(transaction))]
+async fn remove_tasks(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        "DELETE FROM issue_delivery_queue WHERE subscriber_email = $1",
+        email
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip_all)]
+async fn record_feedback(
+    transaction: &mut Transaction<'_, Postgres>,
+    feedback: &Feedback,
+    subscriber_id: Option<Uuid>,
+    payload: &serde_json::Value,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO email_feedback_events (
+            event_id,
+            email,
+            subscriber_id,
+            kind,
+            description,
+            new_status,
+            payload,
+            occurred_at
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        "#,
+        Uuid::new_v4(),
+        feedback.email,
+        subscriber_id,
+        feedback.kind,
+        feedback.description,
+        feedback.new_status,
+        payload,
+        feedback.occurred_at
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
@@ -20,25 +20,76 @@ use std::net::TcpListener;
 use tracing_actix_web::TracingLogger;
 
 use crate::authentication::middleware::reject_anonymous_users;
+use crate::configuration::ApplicationSettings;
 use crate::configuration::DatabaseSettings;
+use crate::configuration::MetricsSettings;
+use crate::configuration::PostmarkWebhookSettings;
 use crate::configuration::Settings;
 use crate::email_client::EmailClient;
+use crate::html_sanitizer::HtmlSanitizer;
+use crate::metrics::record_http_metrics;
 use crate::routes::admin::admin_dashboard;
+use crate::routes::admin::create_layout;
+use crate::routes::admin::create_list;
+use crate::routes::admin::create_suppression;
+use crate::routes::admin::create_webhook;
+use crate::routes::admin::delete_suppression;
+use crate::routes::admin::delete_webhook;
+use crate::routes::admin::export::export_newsletter_issues;
+use crate::routes::admin::export::export_subscriptions;
+use crate::routes::admin::layout_page;
+use crate::routes::admin::layouts_page;
+use crate::routes::admin::lists_page;
 use crate::routes::admin::log_out;
+use crate::routes::admin::newsletters::cancel_issue;
+use crate::routes::admin::newsletters::issue_page;
 use crate::routes::admin::newsletters::newsletter_form;
+use crate::routes::admin::newsletters::pause_issue;
 use crate::routes::admin::newsletters::publish_newsletter;
+use crate::routes::admin::newsletters::resume_issue;
 use crate::routes::admin::password::change_password;
 use crate::routes::admin::password::change_password_form;
-use crate::routes::health_check;
+use crate::routes::admin::subscribers::import_rejections;
+use crate::routes::admin::subscribers::import_subscribers;
+use crate::routes::admin::subscribers::subscriber_page;
+use crate::routes::admin::subscribers::subscribers_page;
+use crate::routes::admin::subscribers::update_subscriber;
+use crate::routes::admin::suppressions_page;
+use crate::routes::admin::update_layout;
+use crate::routes::admin::update_webhook;
+use crate::routes::admin::webhook_page;
+use crate::routes::admin::webhooks_page;
+use crate::routes::archive;
+use crate::routes::archived_issue;
+use crate::routes::feed_atom;
+use crate::routes::feed_json;
+use crate::routes::feed_rss;
+use crate::routes::health_live;
+use crate::routes::health_ready;
 use crate::routes::home::home;
 use crate::routes::login::login;
 use crate::routes::login::login_form;
+use crate::routes::metrics;
+use crate::routes::postmark_webhook;
+use crate::routes::preferences::change_email;
+use crate::routes::preferences::pause_delivery;
+use crate::routes::preferences::preferences;
+use crate::routes::preferences::preferences_link_form;
+use crate::routes::preferences::send_preferences_link;
+use crate::routes::preferences::unsubscribe;
+use crate::routes::preferences::update_name;
 use crate::routes::subscribe;
 use crate::routes::subscribe_confirm;
+use crate::routes::track_click;
+use crate::routes::track_open;
+use crate::routes::MetricsToken;
 
 pub struct Application {
     port: u16,
     server: Server,
+    // Only set when the metrics have a port of their own
+    metrics_port: Option<u16>,
+    metrics_server: Option<Server>,
 }
 
 impl Application {
@@ -52,6 +103,7 @@ impl Application {
             .expect("Failed to migrate database");
 
         let email_client = configuration.email_client.client();
+        let html_sanitizer = configuration.sanitization.sanitizer();
 
         let address = format!(
             "{}:{}",
@@ -60,31 +112,82 @@ impl Application {
         tracing::info!("app started at: {}", &address);
         let listener = TcpListener::bind(address)?;
         let port = listener.local_addr().unwrap().port();
+        let (metrics_port, metrics_server) = match configuration.metrics.port {
+            Some(metrics_port) => {
+                let address = format!(
+                    "{}:{}",
+                    configuration.application.host, metrics_port
+                );
+                tracing::info!("metrics served at: {}", &address);
+                let listener = TcpListener::bind(address)?;
+                let metrics_port = listener.local_addr().unwrap().port();
+                let metrics_server = run_metrics_server(
+                    listener,
+                    connection_pool.clone(),
+                    configuration.metrics.bearer_token.clone(),
+                )?;
+                (Some(metrics_port), Some(metrics_server))
+            }
+            None => (None, None),
+        };
         let server = run(
             listener,
             connection_pool,
             email_client,
-            configuration.application.base_url,
-            configuration.application.hmac_secret,
+            configuration.application,
             configuration.redis_uri,
+            html_sanitizer,
+            configuration.postmark_webhook,
+            configuration.metrics,
         )
         .await?;
-        Ok(Self { port, server })
+        Ok(Self {
+            port,
+            server,
+            metrics_port,
+            metrics_server,
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    pub fn metrics_port(&self) -> Option<u16> {
+        self.metrics_port
+    }
+
     // A more expressive name that makes it clear that
     // this function only returns when the application is stopped
     pub async fn run_until_stopped(self) -> Result<(), std::io::Error> {
-        self.server.await
+        match self.metrics_server {
+            Some(metrics_server) => {
+                tokio::try_join!(self.server, metrics_server)?;
+                Ok(())
+            }
+            None => self.server.await,
+        }
     }
 }
 
+// Reported in the metrics to tell how busy the pool is
+pub const MAX_DB_CONNECTIONS: u32 = 10;
+
 pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
     PgPoolOptions::new()
+        .max_connections(MAX_DB_CONNECTIONS)
+        .connect_timeout(std::time::Duration::from_secs(2))
+        .connect_lazy_with(configuration.with_db())
+}
+
+/// The pool of the delivery worker. Each consumer holds a transaction on
+/// its task while querying besides it, and the heartbeat needs one more.
+pub fn get_worker_connection_pool(
+    configuration: &DatabaseSettings,
+    concurrency: u16,
+) -> PgPool {
+    PgPoolOptions::new()
+        .max_connections(2 * u32::from(concurrency) + 1)
         .connect_timeout(std::time::Duration::from_secs(2))
         .connect_lazy_with(configuration.with_db())
 }
@@ -107,23 +210,46 @@ async fn migrate(
 // using a raw String would expose us to conflictts
 pub struct ApplicationBaseUrl(pub String);
 
+// How long after being issued a subscription token can still be used
+pub struct SubscriptionTokenExpiry(pub chrono::Duration);
+
+pub struct NewsletterName(pub String);
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     listener: TcpListener,
     connection_pool: PgPool,
     email_client: EmailClient,
-    base_url: String,
-    hmac_secret: Secret<String>,
+    configuration: ApplicationSettings,
     redis_uri: Secret<String>,
+    html_sanitizer: HtmlSanitizer,
+    webhook_settings: PostmarkWebhookSettings,
+    metrics_settings: MetricsSettings,
 ) -> Result<Server, anyhow::Error> {
+    // With a port of their own the metrics are not served here
+    let serve_metrics = metrics_settings.port.is_none()
+        && metrics_settings.bearer_token.is_some();
+    let metrics_token = Data::new(MetricsToken(metrics_settings.bearer_token));
     let connection_pool = web::Data::new(connection_pool);
     let email_client = Data::new(email_client);
-    let base_url = Data::new(ApplicationBaseUrl(base_url));
+    let subscription_token_expiry = Data::new(SubscriptionTokenExpiry(
+        configuration.subscription_token_expiry(),
+    ));
+    let base_url = Data::new(ApplicationBaseUrl(configuration.base_url));
+    let newsletter_name =
+        Data::new(NewsletterName(configuration.newsletter_name));
+    let hmac_secret = configuration.hmac_secret;
+    let html_sanitizer = Data::new(html_sanitizer);
+    let webhook_settings = Data::new(webhook_settings);
 
     let secret_key = Key::from(hmac_secret.expose_secret().as_bytes());
     let message_store = CookieMessageStore::builder(secret_key.clone()).build();
     let message_framework =
         FlashMessagesFramework::builder(message_store).build();
     let redis_store = RedisSessionStore::new(redis_uri.expose_secret()).await?;
+    // Only used to probe Redis, sessions go through their store
+    let redis_client =
+        Data::new(redis::Client::open(redis_uri.expose_secret().as_str())?);
     let server = HttpServer::new(move || {
         App::new()
             .wrap(message_framework.clone())
@@ -132,13 +258,40 @@ pub async fn run(
                 secret_key.clone(),
             ))
             // Middlewares are added using the `wrap` method on `App`
+            .wrap(from_fn(record_http_metrics))
             .wrap(TracingLogger::default())
-            .route("/health_check", web::get().to(health_check))
+            .configure(|cfg| {
+                if serve_metrics {
+                    cfg.route("/metrics", web::get().to(metrics));
+                }
+            })
+            // Kept for the monitors set up before the probes were split
+            .route("/health_check", web::get().to(health_live))
+            .route("/health/live", web::get().to(health_live))
+            .route("/health/ready", web::get().to(health_ready))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(subscribe_confirm))
             .route("/home", web::get().to(home))
             .route("/login", web::get().to(login_form))
             .route("/login", web::post().to(login))
+            .route("/t/o/{token}", web::get().to(track_open))
+            .route("/t/c/{token}", web::get().to(track_click))
+            .route("/archive", web::get().to(archive))
+            .route("/archive/{slug}", web::get().to(archived_issue))
+            .route("/feed.atom", web::get().to(feed_atom))
+            .route("/feed.rss", web::get().to(feed_rss))
+            .route("/feed.json", web::get().to(feed_json))
+            .route("/webhooks/postmark", web::post().to(postmark_webhook))
+            .service(
+                web::scope("/preferences")
+                    .route("", web::get().to(preferences))
+                    .route("/link", web::get().to(preferences_link_form))
+                    .route("/link", web::post().to(send_preferences_link))
+                    .route("/name", web::post().to(update_name))
+                    .route("/email", web::post().to(change_email))
+                    .route("/pause", web::post().to(pause_delivery))
+                    .route("/unsubscribe", web::post().to(unsubscribe)),
+            )
             .service(
                 web::scope("/admin")
                     .wrap(from_fn(reject_anonymous_users))
@@ -147,12 +300,88 @@ pub async fn run(
                     .route("/password", web::post().to(change_password))
                     .route("/logout", web::post().to(log_out))
                     .route("/newsletters", web::post().to(publish_newsletter))
-                    .route("/newsletters", web::get().to(newsletter_form)),
+                    .route("/newsletters", web::get().to(newsletter_form))
+                    .route(
+                        "/newsletters/{newsletter_issue_id}",
+                        web::get().to(issue_page),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/pause",
+                        web::post().to(pause_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/resume",
+                        web::post().to(resume_issue),
+                    )
+                    .route(
+                        "/newsletters/{newsletter_issue_id}/cancel",
+                        web::post().to(cancel_issue),
+                    )
+                    .route("/lists", web::get().to(lists_page))
+                    .route("/lists", web::post().to(create_list))
+                    .route("/layouts", web::get().to(layouts_page))
+                    .route("/layouts", web::post().to(create_layout))
+                    .route("/layouts/{layout_id}", web::get().to(layout_page))
+                    .route(
+                        "/layouts/{layout_id}",
+                        web::post().to(update_layout),
+                    )
+                    .route("/subscribers", web::get().to(subscribers_page))
+                    .route(
+                        "/subscribers/import",
+                        web::post().to(import_subscribers),
+                    )
+                    .route(
+                        "/subscribers/imports/{import_id}/rejections",
+                        web::get().to(import_rejections),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::get().to(subscriber_page),
+                    )
+                    .route(
+                        "/subscribers/{subscriber_id}",
+                        web::post().to(update_subscriber),
+                    )
+                    .route("/suppressions", web::get().to(suppressions_page))
+                    .route("/suppressions", web::post().to(create_suppression))
+                    .route(
+                        "/suppressions/{suppression_id}/delete",
+                        web::post().to(delete_suppression),
+                    )
+                    .route("/webhooks", web::get().to(webhooks_page))
+                    .route("/webhooks", web::post().to(create_webhook))
+                    .route(
+                        "/webhooks/{endpoint_id}",
+                        web::get().to(webhook_page),
+                    )
+                    .route(
+                        "/webhooks/{endpoint_id}",
+                        web::post().to(update_webhook),
+                    )
+                    .route(
+                        "/webhooks/{endpoint_id}/delete",
+                        web::post().to(delete_webhook),
+                    )
+                    .route(
+                        "/export/subscriptions",
+                        web::get().to(export_subscriptions),
+                    )
+                    .route(
+                        "/export/newsletter_issues",
+                        web::get().to(export_newsletter_issues),
+                    ),
             )
             .app_data(connection_pool.clone())
             .app_data(email_client.clone())
             .app_data(base_url.clone())
+            .app_data(subscription_token_expiry.clone())
+            .app_data(newsletter_name.clone())
             .app_data(Data::new(HmacSecret(hmac_secret.clone())))
+            .app_data(html_sanitizer.clone())
+            .app_data(webhook_settings.clone())
+            .app_data(metrics_token.clone())
+            .app_data(redis_client.clone())
     })
     .listen(listener)?
     .run();
@@ -160,5 +389,25 @@ pub async fn run(
     Ok(server)
 }
 
+// Serve the metrics alone, for a port that is not exposed to the public
+pub fn run_metrics_server(
+    listener: TcpListener,
+    connection_pool: PgPool,
+    bearer_token: Option<Secret<String>>,
+) -> Result<Server, anyhow::Error> {
+    let connection_pool = web::Data::new(connection_pool);
+    let metrics_token = Data::new(MetricsToken(bearer_token));
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(TracingLogger::default())
+            .route("/metrics", web::get().to(metrics))
+            .app_data(connection_pool.clone())
+            .app_data(metrics_token.clone())
+    })
+    .listen(listener)?
+    .run();
+    Ok(server)
+}
+
 #[derive(Clone)]
 pub struct HmacSecret(pub Secret<String>);
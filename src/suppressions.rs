@@ -0,0 +1,214 @@
+use sqlx::postgres::PgExecutor;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::{EmailClient, SendEmailError};
+
+/// An address or a whole domain that must never be mailed.
+pub struct Suppression {
+    pub suppression_id: Uuid,
+    // 'address' or 'domain'
+    pub kind: String,
+    pub value: String,
+    pub reason: String,
+    // 'admin', 'bounce', 'complaint' or 'unsubscribe'
+    pub source: String,
+}
+
+impl Suppression {
+    /// Unsubscribes are lifted when the address subscribes again,
+    /// the other suppressions only by an admin.
+    pub fn blocks_subscriptions(&self) -> bool {
+        self.source != "unsubscribe"
+    }
+
+    /// Why an address is left out, for the log of suppressed sends.
+    pub fn describe(&self) -> String {
+        format!(
+            "The {} {} is suppressed ({}): {}",
+            self.kind, self.value, self.source, self.reason
+        )
+    }
+}
+
+/// The suppression matching an address, if any. Suppressions blocking
+/// subscriptions come first.
+#[tracing::instrument(skip(executor))]
+pub async fn find_suppression(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+) -> Result<Option<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT suppression_id, kind, value, reason, source
+        FROM suppressions
+        WHERE
+            (kind = 'address' AND value = lower($1)) OR
+            (kind = 'domain' AND value = split_part(lower($1), '@', 2))
+        ORDER BY source = 'unsubscribe'
+        LIMIT 1
+        "#,
+        email
+    )
+    .fetch_optional(executor)
+    .await
+}
+
+/// Stop mailing an address. Unsubscribes do not replace the suppressions
+/// with a stronger source, the other way around they do.
+#[tracing::instrument(skip(transaction))]
+pub async fn suppress_address(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &str,
+    source: &str,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressions (suppression_id, kind, value, reason, source)
+        VALUES ($1, 'address', lower($2), $3, $4)
+        ON CONFLICT (kind, value) DO UPDATE
+        SET reason = EXCLUDED.reason, source = EXCLUDED.source
+        WHERE suppressions.source = 'unsubscribe'
+        "#,
+        Uuid::new_v4(),
+        email,
+        reason,
+        source
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+/// Lift the suppression of a subscriber who unsubscribed, once they confirm
+/// a new subscription.
+#[tracing::instrument(skip(transaction))]
+pub async fn lift_unsubscribe(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        DELETE FROM suppressions
+        WHERE
+            kind = 'address' AND
+            source = 'unsubscribe' AND
+            value = (SELECT lower(email) FROM subscriptions WHERE id = $1)
+        "#,
+        subscriber_id
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(executor))]
+pub async fn log_suppressed_send(
+    executor: impl PgExecutor<'_>,
+    email: &str,
+    context: &str,
+    newsletter_issue_id: Option<Uuid>,
+    reason: &str,
+) -> Result<(), sqlx::Error> {
+    tracing::info!(reason, "Skipping a suppressed address.");
+    sqlx::query!(
+        r#"
+        INSERT INTO suppressed_sends (
+            email,
+            context,
+            newsletter_issue_id,
+            reason
+        )
+        VALUES ($1, $2, $3, $4)
+        "#,
+        email,
+        context,
+        newsletter_issue_id,
+        reason
+    )
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Whether an email the recipient asked for, such as a confirmation link,
+/// must be skipped. Unsubscribes do not stop these. The skip is logged.
+#[tracing::instrument(skip(pool))]
+pub async fn skip_transactional_email(
+    pool: &PgPool,
+    email: &str,
+    context: &str,
+) -> Result<bool, sqlx::Error> {
+    let suppression = find_suppression(pool, email).await?;
+    match suppression.filter(|s| s.blocks_subscriptions()) {
+        Some(suppression) => {
+            log_suppressed_send(
+                pool,
+                email,
+                context,
+                None,
+                &suppression.describe(),
+            )
+            .await?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+pub enum TransactionalSend {
+    Sent,
+    Suppressed,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TransactionalEmailError {
+    #[error("Failed to check the suppressions")]
+    Suppressions(#[from] sqlx::Error),
+    #[error(transparent)]
+    Send(#[from] SendEmailError),
+}
+
+/// Send an email the recipient asked for, unless their address or its
+/// domain is suppressed. Every email outside of the newsletter issues goes
+/// through here.
+#[tracing::instrument(
+    skip(pool, email_client, html_content, text_content),
+    fields(recipient = %recipient.as_ref())
+)]
+pub async fn send_transactional_email(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    recipient: &SubscriberEmail,
+    context: &str,
+    subject: &str,
+    html_content: &str,
+    text_content: &str,
+) -> Result<TransactionalSend, TransactionalEmailError> {
+    if skip_transactional_email(pool, recipient.as_ref(), context).await? {
+        return Ok(TransactionalSend::Suppressed);
+    }
+    email_client
+        .send_email(recipient, subject, html_content, text_content)
+        .await?;
+    Ok(TransactionalSend::Sent)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_suppressions(
+    pool: &PgPool,
+) -> Result<Vec<Suppression>, sqlx::Error> {
+    sqlx::query_as!(
+        Suppression,
+        r#"
+        SELECT suppression_id, kind, value, reason, source
+        FROM suppressions
+        ORDER BY created_at DESC
+        "#
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -1,11 +1,22 @@
+use std::collections::HashMap;
+
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::trace::{self, Tracer, TracerProvider};
+use opentelemetry::sdk::Resource;
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
 use tokio::task::JoinHandle;
 use tracing::subscriber::set_global_default;
-use tracing::Subscriber;
+use tracing::{Span, Subscriber};
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::{layer::SubscriberExt, EnvFilter, Registry};
 
+use crate::configuration::TelemetrySettings;
+
 //// Compose multiple layers into a `tracing`'s subscriber.
 //// # Implementation Notes
 //// We are using `impl Subscriber` as return type to avoid having to
@@ -18,6 +29,7 @@ pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer: Tracer,
 ) -> impl Subscriber + Send + Sync
 where
     // This "weird" syntax is a higher-ranked trait bound (HRTB)
@@ -34,6 +46,7 @@ where
 
     Registry::default()
         .with(env_filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
         .with(JsonStorageLayer) // removing this cause no effect
         .with(formatting_layer)
 }
@@ -41,6 +54,7 @@ where
 pub fn get_line_subscriber<Sink>(
     env_filter: String,
     sink: Sink,
+    tracer: Tracer,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -52,7 +66,50 @@ where
         .with_writer(sink)
         .with_test_writer();
 
-    Registry::default().with(env_filter).with(fmt_layer)
+    Registry::default()
+        .with(env_filter)
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .with(fmt_layer)
+}
+
+/// Build the provider of the tracer given to the subscriber.
+///
+/// Spans are batched and exported with OTLP over HTTP when an endpoint is
+/// configured, which needs a Tokio runtime. Without one, spans still get
+/// trace and span ids to propagate, but are not exported.
+pub fn get_tracer_provider(
+    name: String,
+    settings: &TelemetrySettings,
+) -> Result<TracerProvider, TraceError> {
+    let config =
+        trace::config().with_resource(Resource::new(vec![KeyValue::new(
+            "service.name",
+            name,
+        )]));
+    let provider = TracerProvider::builder().with_config(config);
+    let provider = match &settings.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporterBuilder::from(
+                opentelemetry_otlp::new_exporter()
+                    .http()
+                    .with_endpoint(endpoint),
+            )
+            .build_span_exporter()?;
+            provider
+                .with_batch_exporter(exporter, opentelemetry::runtime::Tokio)
+        }
+        None => provider,
+    };
+    Ok(provider.build())
+}
+
+/// Set the provider as global default and return a tracer from it.
+/// The provider is shut down, exporting the remaining spans, by
+/// `global::shutdown_tracer_provider`.
+pub fn init_tracer(provider: TracerProvider) -> Tracer {
+    let tracer = provider.tracer("zero2prod");
+    global::set_tracer_provider(provider);
+    tracer
 }
 
 /// Register a subscriber as global default to process span data.
@@ -61,6 +118,30 @@ where
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
+    // Read and written as W3C `traceparent` and `tracestate` headers
+    global::set_text_map_propagator(TraceContextPropagator::new());
+}
+
+/// The trace context of the current span, to be stored with work picked up
+/// later, e.g. by the delivery worker.
+pub fn current_trace_context() -> serde_json::Value {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    serde_json::json!(carrier)
+}
+
+/// Make the span a child of the one the trace context was taken from, with
+/// `current_trace_context`. It stays a root span if there is none.
+pub fn set_parent_from(span: &Span, trace_context: Option<serde_json::Value>) {
+    let carrier: HashMap<String, String> = trace_context
+        .and_then(|c| serde_json::from_value(c).ok())
+        .unwrap_or_default();
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&carrier)
+    });
+    span.set_parent(parent);
 }
 
 // Just copied trait bounds and signature from `spawn_blocking`
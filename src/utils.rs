@@ -1,5 +1,10 @@
-use actix_web::http::header::LOCATION;
-use actix_web::HttpResponse;
+use actix_web::http::header::{
+    ContentDisposition, DispositionParam, DispositionType, LOCATION,
+};
+use actix_web::{dev::Payload, web, FromRequest, HttpRequest, HttpResponse};
+use futures::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use std::ops::Deref;
 
 pub fn e500<T>(e: T) -> actix_web::Error
 where
@@ -23,3 +28,55 @@ where
 {
     actix_web::error::ErrorBadRequest(e)
 }
+
+// `Content-Disposition` header telling the browser to download the body
+// as a file named `filename`.
+pub fn attachment(filename: String) -> ContentDisposition {
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
+
+// Like `web::Form`, but a field can be repeated to fill a `Vec`,
+// the way browsers submit checkboxes and multiple selects.
+pub struct HtmlForm<T>(pub T);
+
+impl<T> HtmlForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for HtmlForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for HtmlForm<T>
+where
+    T: DeserializeOwned + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let body = web::Bytes::from_request(req, payload);
+        Box::pin(async move {
+            let body = body.await?;
+            serde_html_form::from_bytes(&body)
+                .map(HtmlForm)
+                .map_err(e400)
+        })
+    }
+}
+
+// Inserting or updating a row conflicted with a unique constraint.
+pub fn is_unique_violation(e: &sqlx::Error) -> bool {
+    e.as_database_error()
+        .and_then(|e| e.code())
+        .map_or(false, |code| code == "23505")
+}
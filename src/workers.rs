@@ -0,0 +1,89 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// A worker whose last heartbeat is older than this is considered gone,
+/// they beat every few seconds unless stuck.
+pub const HEARTBEAT_TIMEOUT_SECONDS: i64 = 30;
+// The window over which the throughput of the workers is measured
+const THROUGHPUT_WINDOW_MINUTES: i32 = 5;
+
+/// A delivery worker process that is still beating.
+pub struct LiveWorker {
+    pub worker_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub heartbeat_at: DateTime<Utc>,
+    pub concurrency: i16,
+    // Recipients the worker was done with over the throughput window
+    pub delivered: i64,
+}
+
+impl LiveWorker {
+    pub fn deliveries_per_minute(&self) -> f64 {
+        self.delivered as f64 / f64::from(THROUGHPUT_WINDOW_MINUTES)
+    }
+}
+
+/// Record that the worker is alive, registering it on its first heartbeat.
+/// Workers gone for a day are forgotten.
+#[tracing::instrument(skip(pool))]
+pub async fn heartbeat(
+    pool: &PgPool,
+    worker_id: Uuid,
+    concurrency: u16,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH forgotten AS (
+            DELETE FROM workers
+            WHERE heartbeat_at < now() - interval '1 day'
+        )
+        INSERT INTO workers (worker_id, concurrency) VALUES ($1, $2)
+        ON CONFLICT (worker_id) DO UPDATE SET heartbeat_at = now()
+        "#,
+        worker_id,
+        concurrency as i16
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// The latest heartbeat of any worker, if one ever registered.
+pub async fn last_heartbeat(
+    pool: &PgPool,
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    let row = sqlx::query!("SELECT max(heartbeat_at) AS last FROM workers")
+        .fetch_one(pool)
+        .await?;
+    Ok(row.last)
+}
+
+#[tracing::instrument(skip(pool))]
+pub async fn get_live_workers(
+    pool: &PgPool,
+) -> Result<Vec<LiveWorker>, sqlx::Error> {
+    sqlx::query_as!(
+        LiveWorker,
+        r#"
+        SELECT
+            w.worker_id,
+            w.started_at,
+            w.heartbeat_at,
+            w.concurrency,
+            (
+                SELECT COUNT(*) FROM issue_delivery_log l
+                WHERE
+                    l.worker_id = w.worker_id AND
+                    l.logged_at > now() - make_interval(mins => $2)
+            ) AS "delivered!"
+        FROM workers w
+        WHERE w.heartbeat_at > now() - make_interval(secs => $1)
+        ORDER BY w.started_at
+        "#,
+        HEARTBEAT_TIMEOUT_SECONDS as f64,
+        THROUGHPUT_WINDOW_MINUTES
+    )
+    .fetch_all(pool)
+    .await
+}
@@ -0,0 +1,131 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{newsletter_form, spawn_app, TestApp};
+
+/// Publish an issue and return its slug.
+async fn publish_and_get_slug(
+    app: &TestApp,
+    title: &str,
+    show_in_archive: bool,
+) -> String {
+    let newsletter_issue_id = app
+        .publish_newsletter(&FormData {
+            title: title.into(),
+            show_in_archive: show_in_archive.then(|| "on".to_string()),
+            ..newsletter_form()
+        })
+        .await;
+    sqlx::query!(
+        "SELECT slug FROM newsletter_issues WHERE newsletter_issue_id = $1",
+        newsletter_issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .slug
+}
+
+#[tokio::test]
+async fn archived_issues_are_listed_and_can_be_read() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let slug = publish_and_get_slug(&app, "Newsletter title", true).await;
+    assert!(slug.starts_with("newsletter-title-"));
+
+    // Act - Part 1 - The list of issues
+    let html_page = app.get_archive("").await.text().await.unwrap();
+    assert!(html_page.contains(&format!(
+        r#"<a href="/archive/{}">Newsletter title</a>"#,
+        slug
+    )));
+
+    // Act - Part 2 - The issue
+    let response = app.get_archive(&format!("/{}", slug)).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("<h1>Newsletter title</h1>"));
+    assert!(html_page.contains("<p>Newsletter body as HTML</p>"));
+}
+
+#[tokio::test]
+async fn issues_left_out_of_the_archive_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let slug = publish_and_get_slug(&app, "Newsletter title", false).await;
+
+    // Act
+    let response = app.get_archive(&format!("/{}", slug)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let html_page = app.get_archive("").await.text().await.unwrap();
+    assert!(!html_page.contains(&slug));
+}
+
+#[tokio::test]
+async fn emails_link_to_the_archived_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let slug = publish_and_get_slug(&app, "Newsletter title", true).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    let archive_path = format!("/archive/{}", slug);
+    assert!(body["HtmlBody"]
+        .as_str()
+        .unwrap()
+        .contains(&format!(r#"{}">View in browser</a>"#, archive_path)));
+    let text_body = body["TextBody"].as_str().unwrap();
+    assert!(text_body.starts_with("View in browser: "));
+    assert!(text_body.lines().next().unwrap().ends_with(&archive_path));
+}
+
+#[tokio::test]
+async fn the_archive_is_paginated() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let oldest = publish_and_get_slug(&app, "Issue 0", true).await;
+    for i in 1..=10 {
+        publish_and_get_slug(&app, &format!("Issue {}", i), true).await;
+    }
+
+    // Act - Part 1 - The latest issues
+    let html_page = app.get_archive("").await.text().await.unwrap();
+    assert!(html_page.contains(r#"<a href="/archive?page=2">Older issues</a>"#));
+    assert!(!html_page.contains(&oldest));
+
+    // Act - Part 2 - The next page
+    let html_page = app.get_archive("?page=2").await.text().await.unwrap();
+    assert!(html_page.contains(&oldest));
+    assert!(html_page.contains(r#"<a href="/archive?page=1">Newer issues</a>"#));
+    assert!(!html_page.contains("Older issues"));
+
+    // Act - Part 3 - A page too far away to exist
+    let response = app.get_archive(&format!("?page={}", i64::MAX)).await;
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,199 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::workers::heartbeat;
+
+use crate::helpers::{newsletter_form, spawn_app, TestApp};
+
+// As in `configuration/base.yml`
+const FAILURE_THRESHOLD: usize = 5;
+
+/// Publish an issue to more subscribers than the failures that open the
+/// circuit.
+async fn publish_to_subscribers(app: &TestApp) {
+    for i in 0..=FAILURE_THRESHOLD {
+        let name = format!("subscriber{}", i);
+        app.create_confirmed_subscriber(&name, &format!("{}@gmail.com", name))
+            .await;
+    }
+    app.test_user.login(app).await;
+    app.publish_newsletter(&newsletter_form()).await;
+}
+
+async fn get_readiness(app: &TestApp) -> serde_json::Value {
+    reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.")
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn deliveries_are_paused_once_the_provider_failed_too_many_times() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_to_subscribers(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(FAILURE_THRESHOLD as u64)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let untouched = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue
+        WHERE n_retries = 0"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(untouched.count, 1);
+    let body = get_readiness(&app).await;
+    let email_provider = &body["components"]["email_provider"];
+    assert_eq!(email_provider["status"], "down");
+    assert!(email_provider["error"]
+        .as_str()
+        .unwrap()
+        .contains("after 5 consecutive failures"));
+    // Mock verifies on Drop that the provider was not called again
+}
+
+#[tokio::test]
+async fn a_successful_probe_after_the_cooldown_resumes_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_to_subscribers(&app).await;
+    {
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(FAILURE_THRESHOLD as u64)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        app.dispatch_all_pending_emails().await;
+    }
+    // Skip the cooldown of the circuit and the backoff of the retries
+    sqlx::query!(
+        "UPDATE circuit_breakers SET changed_at = now() - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(FAILURE_THRESHOLD as u64 + 1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let remaining = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM issue_delivery_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(remaining.count, 0);
+    let body = get_readiness(&app).await;
+    assert_eq!(body["components"]["email_provider"]["status"], "up");
+}
+
+#[tokio::test]
+async fn an_open_circuit_does_not_make_the_api_unavailable() {
+    // Arrange
+    let app = spawn_app().await;
+    // Only the email provider is down
+    heartbeat(&app.db_pool, Uuid::new_v4(), 1).await.unwrap();
+    sqlx::query!(
+        "UPDATE circuit_breakers SET state = 'open', changed_at = now()"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["components"]["email_provider"]["status"], "down");
+}
+
+#[tokio::test]
+async fn the_cooldown_is_not_spent_when_there_is_nothing_to_send() {
+    // Arrange
+    let app = spawn_app().await;
+    sqlx::query!(
+        "UPDATE circuit_breakers
+        SET state = 'open', changed_at = now() - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let circuit = sqlx::query!(
+        r#"SELECT state, changed_at < now() - interval '30 minutes'
+            AS "cooled_down!"
+        FROM circuit_breakers"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(circuit.state, "open");
+    assert!(circuit.cooled_down);
+}
+
+#[tokio::test]
+async fn a_probe_refused_by_the_provider_hands_the_circuit_back() {
+    // Arrange
+    let app = spawn_app().await;
+    publish_to_subscribers(&app).await;
+    sqlx::query!(
+        "UPDATE circuit_breakers
+        SET state = 'open', changed_at = now() - interval '1 hour'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    // Refusing an email says nothing about the health of the provider
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(FAILURE_THRESHOLD as u64 + 1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let circuit = sqlx::query!(
+        r#"SELECT state, changed_at < now() - interval '30 minutes'
+            AS "cooled_down!"
+        FROM circuit_breakers"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(circuit.state, "open");
+    assert!(circuit.cooled_down);
+}
@@ -0,0 +1,147 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+async fn queue_length(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_pause_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.post_delivery_action(Uuid::new_v4(), "pause").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn paused_issues_are_not_delivered_until_resumed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let issue_id = app.publish_newsletter(&newsletter_form()).await;
+    let location = format!("/admin/newsletters/{}", issue_id);
+
+    // Act - Part 1 - Pause
+    let response = app.post_delivery_action(issue_id, "pause").await;
+    assert_is_redirect_to(&response, &location);
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains(
+        "The delivery has been paused. \
+        0 recipients had already received the issue."
+    ));
+    assert!(html_page.contains("<tr><th>Status</th><td>Paused</td></tr>"));
+    assert!(html_page.contains(&format!(
+        "<td>pause</td><td>{}</td><td>0</td><td>1</td>",
+        app.test_user.username
+    )));
+
+    // Act - Part 2 - The worker leaves the issue alone
+    let mock_guard = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+    assert_eq!(queue_length(&app).await, 1);
+
+    // Act - Part 3 - Resume
+    let response = app.post_delivery_action(issue_id, "resume").await;
+    assert_is_redirect_to(&response, &location);
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(queue_length(&app).await, 0);
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Status</th><td>Delivered</td></tr>"));
+    assert!(html_page.contains(&format!(
+        "<td>resume</td><td>{}</td><td>0</td><td>1</td>",
+        app.test_user.username
+    )));
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_removed_from_the_queue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let issue_id = app.publish_newsletter(&newsletter_form()).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_delivery_action(issue_id, "cancel").await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/newsletters/{}", issue_id),
+    );
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(queue_length(&app).await, 0);
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Status</th><td>Cancelled</td></tr>"));
+    assert!(html_page.contains(&format!(
+        "<td>cancel</td><td>{}</td><td>0</td><td>1</td>",
+        app.test_user.username
+    )));
+}
+
+#[tokio::test]
+async fn an_issue_being_delivered_cannot_be_resumed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let issue_id = app.publish_newsletter(&newsletter_form()).await;
+
+    // Act
+    app.post_delivery_action(issue_id, "resume").await;
+
+    // Assert
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains(
+        "The delivery of this issue is active, it cannot be resumed."
+    ));
+    assert!(!html_page.contains("<td>resume</td>"));
+}
+
+#[tokio::test]
+async fn actions_on_unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.post_delivery_action(Uuid::new_v4(), "cancel").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
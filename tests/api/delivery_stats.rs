@@ -0,0 +1,146 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+// Skip the backoff of the tasks waiting for another attempt
+async fn make_retries_due(app: &TestApp) {
+    sqlx::query!("UPDATE issue_delivery_queue SET execute_after = now()")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_see_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_newsletter_issue(Uuid::new_v4()).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn unknown_issues_are_not_found() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_newsletter_issue(Uuid::new_v4()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_issue_page_shows_the_delivery_progress() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Before the worker runs
+    let issue_id = app.publish_newsletter(&newsletter_form()).await;
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Queued</th><td>1</td></tr>"));
+    assert!(html_page
+        .contains("<tr><th>Delivery ended</th><td>In progress</td></tr>"));
+
+    // Act - Part 2 - Once the issue is delivered
+    app.dispatch_all_pending_emails().await;
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Queued</th><td>0</td></tr>"));
+    assert!(html_page.contains("<tr><th>Sent</th><td>1</td></tr>"));
+    assert!(html_page.contains("emails per minute"));
+
+    // Act - Part 3 - The list of issues
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<a href="/admin/newsletters/{}">Newsletter title</a>"#,
+        issue_id
+    )));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let issue_id = app.publish_newsletter(&newsletter_form()).await;
+
+    // Act - Part 1 - Postmark is down
+    let mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+    drop(mock_guard);
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Retrying</th><td>1</td></tr>"));
+
+    // Act - Part 2 - Postmark is back
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    make_retries_due(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Retrying</th><td>0</td></tr>"));
+    assert!(html_page.contains("<tr><th>Sent</th><td>1</td></tr>"));
+    let log = sqlx::query!("SELECT n_attempts FROM issue_delivery_log")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(log.n_attempts, 2);
+}
+
+#[tokio::test]
+async fn deliveries_fail_after_too_many_attempts() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let issue_id = app.publish_newsletter(&newsletter_form()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    for _ in 0..5 {
+        make_retries_due(&app).await;
+        app.dispatch_all_pending_emails().await;
+    }
+
+    // Assert
+    let html_page = app.get_newsletter_issue_html(issue_id).await;
+    assert!(html_page.contains("<tr><th>Retrying</th><td>0</td></tr>"));
+    assert!(html_page.contains("<tr><th>Failed</th><td>1</td></tr>"));
+    assert!(html_page.contains("<td>ursula_le_guin@gmail.com</td><td>5</td>"));
+}
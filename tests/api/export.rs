@@ -0,0 +1,94 @@
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{assert_is_redirect_to, newsletter_form, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_export_subscriptions() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app.get_export("subscriptions", "csv").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn subscriptions_are_exported_as_csv() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_export("subscriptions", "csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers().get("Content-Type").unwrap(),
+        "text/csv; charset=utf-8"
+    );
+    let csv = response.text().await.unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert!(lines[1]
+        .contains("ursula_le_guin@gmail.com,le guin,pending_confirmation"));
+}
+
+#[tokio::test]
+async fn an_empty_csv_export_still_has_a_header_row() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app.get_export("newsletter_issues", "csv").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let csv = response.text().await.unwrap();
+    assert_eq!(
+        csv,
+        "newsletter_issue_id,title,text_content,html_content,published_at\n"
+    );
+}
+
+#[tokio::test]
+async fn newsletter_issues_are_exported_as_ndjson() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    for title in ["First issue", "Second issue"] {
+        let newsletter_request_body = FormData {
+            title: title.to_string(),
+            ..newsletter_form()
+        };
+        app.post_newsletters(&newsletter_request_body).await;
+    }
+
+    // Act
+    let response = app.get_export("newsletter_issues", "ndjson").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    let issues: Vec<serde_json::Value> = body
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(issues.len(), 2);
+    assert_eq!(issues[0]["title"], "First issue");
+    assert_eq!(issues[1]["title"], "Second issue");
+    assert_eq!(issues[0]["html_content"], "<p>Newsletter body as HTML</p>");
+}
@@ -0,0 +1,140 @@
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{newsletter_form, spawn_app, TestApp};
+
+/// An issue published in the archive.
+fn archived_newsletter(title: &str) -> FormData {
+    FormData {
+        title: title.into(),
+        show_in_archive: Some("on".into()),
+        ..newsletter_form()
+    }
+}
+
+async fn get_feed_with_header(
+    app: &TestApp,
+    name: &str,
+    header: &str,
+    value: &str,
+) -> reqwest::Response {
+    app.api_client
+        .get(&format!("{}/{}", app.address, name))
+        .header(header, value)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn feeds_list_the_archived_issues() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let issue_id = app
+        .publish_newsletter(&archived_newsletter("Newsletter title"))
+        .await;
+    let guid = format!("urn:uuid:{}", issue_id);
+
+    // Act - Part 1 - Atom
+    let response = app.get_feed("feed.atom").await;
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let atom = response.text().await.unwrap();
+    assert!(atom.contains("<title>Newsletter title</title>"));
+    assert!(atom.contains(&format!("<id>{}</id>", guid)));
+    assert!(atom.contains("&lt;p&gt;Newsletter body as HTML&lt;/p&gt;"));
+
+    // Act - Part 2 - RSS
+    let rss = app.get_feed("feed.rss").await.text().await.unwrap();
+    assert!(rss.contains("<title>Newsletter title</title>"));
+    assert!(
+        rss.contains(&format!(r#"<guid isPermaLink="false">{}</guid>"#, guid))
+    );
+
+    // Act - Part 3 - JSON Feed
+    let json: serde_json::Value =
+        app.get_feed("feed.json").await.json().await.unwrap();
+    let item = &json["items"][0];
+    assert_eq!(item["id"], guid);
+    assert_eq!(item["title"], "Newsletter title");
+    assert_eq!(item["content_html"], "<p>Newsletter body as HTML</p>");
+}
+
+#[tokio::test]
+async fn issues_left_out_of_the_archive_are_not_in_the_feeds() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&newsletter_form()).await;
+
+    // Act
+    let json: serde_json::Value =
+        app.get_feed("feed.json").await.json().await.unwrap();
+
+    // Assert
+    assert_eq!(json["items"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn unchanged_feeds_are_not_sent_again() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&archived_newsletter("First issue"))
+        .await;
+    for name in ["feed.atom", "feed.rss", "feed.json"] {
+        let response = app.get_feed(name).await;
+        assert_eq!(response.status().as_u16(), 200);
+        let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+        let last_modified = response.headers()["Last-Modified"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // Act - Part 1 - The feed did not change
+        let response =
+            get_feed_with_header(&app, name, "If-None-Match", &etag).await;
+        assert_eq!(response.status().as_u16(), 304);
+        let response = get_feed_with_header(
+            &app,
+            name,
+            "If-Modified-Since",
+            &last_modified,
+        )
+        .await;
+        assert_eq!(response.status().as_u16(), 304);
+
+        // Act - Part 2 - A different version
+        let response =
+            get_feed_with_header(&app, name, "If-None-Match", r#""stale""#)
+                .await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+}
+
+#[tokio::test]
+async fn feeds_change_when_an_issue_is_published() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&archived_newsletter("First issue"))
+        .await;
+    let response = app.get_feed("feed.atom").await;
+    let etag = response.headers()["ETag"].to_str().unwrap().to_owned();
+
+    // Act
+    app.publish_newsletter(&archived_newsletter("Second issue"))
+        .await;
+    let response =
+        get_feed_with_header(&app, "feed.atom", "If-None-Match", &etag).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("<title>Second issue</title>"));
+}
@@ -1,3 +1,6 @@
+use uuid::Uuid;
+use zero2prod::workers::heartbeat;
+
 use crate::helpers::spawn_app;
 
 // 'actix_rt::test' is the testing equivalent of 'actix_web::main'.
@@ -9,7 +12,7 @@ use crate::helpers::spawn_app;
 // You can inspect what code gets generated using
 // 'cargo expand --test health_check' (<- name of the test file)
 #[tokio::test]
-async fn health_check_works() {
+async fn liveness_check_works() {
     // No .await, no .expect
     let app = spawn_app().await;
     // We need to bring in 'request'
@@ -19,6 +22,24 @@ async fn health_check_works() {
     // it under '[dev-dependencies]' in Cargo.toml
     let client = reqwest::Client::new();
 
+    // Act
+    let response = client
+        .get(format!("{}/health/live", &app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert!(response.status().is_success());
+    assert_eq!(Some(0), response.content_length());
+}
+
+#[tokio::test]
+async fn health_check_is_kept_as_an_alias_of_the_liveness_check() {
+    // Arrange
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
     // Act
     let response = client
         .get(format!("{}/health_check", &app.address))
@@ -30,3 +51,73 @@ async fn health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length());
 }
+
+#[tokio::test]
+async fn readiness_check_reports_each_component() {
+    // Arrange
+    let app = spawn_app().await;
+    heartbeat(&app.db_pool, Uuid::new_v4(), 1).await.unwrap();
+
+    // Act
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "ready");
+    for component in [
+        "database",
+        "redis",
+        "migrations",
+        "worker",
+        "email_provider",
+    ] {
+        assert_eq!(body["components"][component]["status"], "up");
+        assert!(body["components"][component]["latency_ms"].is_f64());
+    }
+}
+
+#[tokio::test]
+async fn instances_without_a_live_worker_are_not_ready() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act - Part 1 - No worker ever started
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert - Part 1
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "unavailable");
+    assert_eq!(body["components"]["database"]["status"], "up");
+    assert_eq!(body["components"]["worker"]["status"], "down");
+    assert_eq!(
+        body["components"]["worker"]["error"],
+        "No delivery worker has started"
+    );
+
+    // Act - Part 2 - The worker stopped beating
+    let worker_id = Uuid::new_v4();
+    heartbeat(&app.db_pool, worker_id, 1).await.unwrap();
+    sqlx::query!(
+        "UPDATE workers SET heartbeat_at = now() - interval '1 minute'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = reqwest::get(format!("{}/health/ready", &app.address))
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert - Part 2
+    assert_eq!(response.status().as_u16(), 503);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["components"]["worker"]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("The last worker heartbeat was"));
+}
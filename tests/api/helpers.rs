@@ -2,24 +2,45 @@ use argon2::password_hash::SaltString;
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
 use dotenv::dotenv;
 use once_cell::sync::Lazy;
+use secrecy::{ExposeSecret, Secret};
 use serde_json::Value;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 
 use uuid::Uuid;
-use wiremock::MockServer;
-use zero2prod::configuration::{get_configuration, DatabaseSettings};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::circuit_breaker::CircuitBreaker;
+use zero2prod::configuration::{
+    get_configuration, DatabaseSettings, PostmarkWebhookSettings,
+    TelemetrySettings,
+};
+use zero2prod::confirmation_emails::try_send_confirmation_email;
+use zero2prod::domain::PreferencesToken;
 
 use zero2prod::email_client::EmailClient;
 use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::outgoing_webhooks::try_deliver_webhook;
+use zero2prod::rate_limiter::SendRateLimiter;
+use zero2prod::routes::admin::newsletters::FormData;
 use zero2prod::startup::{get_connection_pool, Application};
 use zero2prod::telemetry::{
-    get_line_subscriber, get_subscriber, init_subscriber,
+    get_line_subscriber, get_subscriber, get_tracer_provider, init_subscriber,
+    init_tracer,
 };
 
 // Ensure that the `tracing` stack is only initialised once using `once_cell`
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    // Spans are not exported, but trace contexts are still propagated
+    let tracer_provider = get_tracer_provider(
+        subscriber_name.clone(),
+        &TelemetrySettings {
+            otlp_endpoint: None,
+        },
+    )
+    .expect("Failed to build the tracer provider");
+    let tracer = init_tracer(tracer_provider);
     // We cannot assign the output of `get_subscriber` to a variable based on the value of `TEST_LOG`
     // because the sink is part of the type returned by `get_subscriber`, therefore they are not the
     // same type. We could work around it, but this is the most straight-forward way of moving forward.
@@ -32,12 +53,14 @@ static TRACING: Lazy<()> = Lazy::new(|| {
                     subscriber_name,
                     default_filter_level,
                     std::io::stdout,
+                    tracer,
                 ));
             } else {
                 println!("Using text output");
                 init_subscriber(get_line_subscriber(
                     default_filter_level,
                     std::io::stdout,
+                    tracer,
                 ));
             }
         }
@@ -46,6 +69,7 @@ static TRACING: Lazy<()> = Lazy::new(|| {
                 subscriber_name,
                 default_filter_level,
                 std::io::sink,
+                tracer,
             );
             init_subscriber(subscriber);
         }
@@ -60,6 +84,11 @@ pub struct TestApp {
     pub test_user: TestUser,
     pub api_client: reqwest::Client,
     pub email_client: EmailClient,
+    pub rate_limiter: SendRateLimiter,
+    pub circuit_breaker: CircuitBreaker,
+    pub hmac_secret: Secret<String>,
+    pub postmark_webhook: PostmarkWebhookSettings,
+    pub metrics_token: Secret<String>,
 }
 
 pub struct ConfirmationLinks {
@@ -69,9 +98,49 @@ pub struct ConfirmationLinks {
 
 impl TestApp {
     pub async fn dispatch_all_pending_emails(&self) {
+        let worker_id = Uuid::new_v4();
+        loop {
+            // Paused deliveries are left in the queue
+            if let ExecutionOutcome::EmptyQueue | ExecutionOutcome::Paused =
+                try_execute_task(
+                    &self.db_pool,
+                    &self.email_client,
+                    &self.rate_limiter,
+                    &self.circuit_breaker,
+                    &self.address,
+                    &self.hmac_secret,
+                    worker_id,
+                )
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_pending_confirmation_emails(&self) {
+        loop {
+            if let ExecutionOutcome::EmptyQueue | ExecutionOutcome::Paused =
+                try_send_confirmation_email(
+                    &self.db_pool,
+                    &self.email_client,
+                    &self.rate_limiter,
+                    &self.circuit_breaker,
+                    &self.address,
+                )
+                .await
+                .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    pub async fn dispatch_all_pending_webhooks(&self) {
         loop {
             if let ExecutionOutcome::EmptyQueue =
-                try_execute_task(&self.db_pool, &self.email_client)
+                try_deliver_webhook(&self.db_pool, &reqwest::Client::new())
                     .await
                     .unwrap()
             {
@@ -84,18 +153,38 @@ impl TestApp {
     where
         Body: serde::Serialize + ?Sized,
     {
+        // Target lists are sent as a repeated field, like a browser would
+        let body = serde_html_form::to_string(body).unwrap();
         self.api_client
             .post(&format!("{}/admin/newsletters", &self.address))
             .basic_auth(
                 &self.test_user.username,
                 Some(&self.test_user.password),
             )
-            .form(body)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
 
+    /// Publish an issue through the admin form and return its id.
+    pub async fn publish_newsletter(&self, form: &FormData) -> Uuid {
+        let response = self.post_newsletters(form).await;
+        assert_is_redirect_to(&response, "/admin/newsletters");
+        sqlx::query!(
+            r#"
+            SELECT newsletter_issue_id FROM newsletter_issues
+            ORDER BY published_at::timestamptz DESC
+            LIMIT 1
+            "#
+        )
+        .fetch_one(&self.db_pool)
+        .await
+        .expect("Failed to fetch the published issue")
+        .newsletter_issue_id
+    }
+
     pub async fn get_newsletters(&self) -> reqwest::Response {
         self.api_client
             .get(&format!("{}/admin/newsletters", &self.address))
@@ -108,6 +197,92 @@ impl TestApp {
         self.get_newsletters().await.text().await.unwrap()
     }
 
+    pub async fn get_newsletter_issue(
+        &self,
+        newsletter_issue_id: Uuid,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(&format!(
+                "{}/admin/newsletters/{}",
+                &self.address, newsletter_issue_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_newsletter_issue_html(
+        &self,
+        newsletter_issue_id: Uuid,
+    ) -> String {
+        self.get_newsletter_issue(newsletter_issue_id)
+            .await
+            .text()
+            .await
+            .unwrap()
+    }
+
+    /// Pause, resume or cancel the delivery of an issue.
+    pub async fn post_delivery_action(
+        &self,
+        newsletter_issue_id: Uuid,
+        action: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/newsletters/{}/{}",
+                &self.address, newsletter_issue_id, action
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `path` is relative to the archive, e.g. "?page=2" or "/{slug}".
+    pub async fn get_archive(&self, path: &str) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/archive{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// `name` is the file name of the feed, e.g. "feed.atom".
+    pub async fn get_feed(&self, name: &str) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/{}", &self.address, name))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Call the Postmark webhook with the configured basic auth credentials.
+    pub async fn post_postmark_webhook(
+        &self,
+        record: &serde_json::Value,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/webhooks/postmark", &self.address))
+            .basic_auth(
+                &self.postmark_webhook.username,
+                Some(self.postmark_webhook.password.expose_secret()),
+            )
+            .json(record)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// Scrape the metrics, sending the given bearer token if any.
+    pub async fn get_metrics(&self, token: Option<&str>) -> reqwest::Response {
+        let mut request =
+            self.api_client.get(&format!("{}/metrics", &self.address));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
     pub async fn post_logout(&self) -> reqwest::Response {
         self.api_client
             .post(&format!("{}/admin/logout", &self.address))
@@ -167,6 +342,197 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    pub async fn get_lists_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/lists", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_lists<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/lists", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/suppressions", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_suppressions<Body>(
+        &self,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/suppressions", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_webhooks_html(&self, path: &str) -> String {
+        self.api_client
+            .get(&format!("{}/admin/webhooks{}", &self.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_webhooks<Body>(
+        &self,
+        path: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize + ?Sized,
+    {
+        self.api_client
+            .post(&format!("{}/admin/webhooks{}", &self.address, path))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_layouts_html(&self) -> String {
+        self.api_client
+            .get(&format!("{}/admin/layouts", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_layouts<Body>(&self, body: &Body) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/layouts", &self.address))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_layout<Body>(
+        &self,
+        layout_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/admin/layouts/{}", &self.address, layout_id))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_subscriber_html(&self, subscriber_id: Uuid) -> String {
+        self.api_client
+            .get(&format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn post_subscriber<Body>(
+        &self,
+        subscriber_id: Uuid,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!(
+                "{}/admin/subscribers/{}",
+                &self.address, subscriber_id
+            ))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_subscribers_import(
+        &self,
+        csv: impl Into<reqwest::Body>,
+        status: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!(
+                "{}/admin/subscribers/import?status={}",
+                &self.address, status
+            ))
+            .header("Content-Type", "text/csv")
+            .body(csv)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_import_rejections(
+        &self,
+        report_url: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}{}", &self.address, report_url))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_export(
+        &self,
+        resource: &str,
+        format: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .get(&format!(
+                "{}/admin/export/{}?format={}",
+                &self.address, resource, format
+            ))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         self.api_client
             .post(&format!("{}/subscriptions", &self.address))
@@ -177,6 +543,113 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Use the public API of the application under test to create
+    /// an unconfirmed subscriber.
+    pub async fn create_unconfirmed_subscriber(
+        &self,
+        name: &str,
+        email: &str,
+    ) -> ConfirmationLinks {
+        let body = serde_urlencoded::to_string(&serde_json::json!({
+            "name": name,
+            "email": email,
+        }))
+        .unwrap();
+
+        let _mock_guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .named("Create unconfirmed subscriber")
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+
+        self.post_subscriptions(body)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        // We now inspect the requests received by the mock Postmark server
+        // to retrieve the confirmation link and return it
+        let email_request = &self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+
+        self.get_confirmation_links(email_request)
+    }
+
+    /// Create a subscriber and follow their confirmation link.
+    /// Returns the id of the new subscriber.
+    pub async fn create_confirmed_subscriber(
+        &self,
+        name: &str,
+        email: &str,
+    ) -> Uuid {
+        let confirmation_links =
+            self.create_unconfirmed_subscriber(name, email).await;
+        reqwest::get(confirmation_links.html)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+
+        sqlx::query!("SELECT id FROM subscriptions WHERE email = $1", email)
+            .fetch_one(&self.db_pool)
+            .await
+            .expect("Failed to fetch the subscriber")
+            .id
+    }
+
+    pub fn preferences_token(&self, subscriber_id: Uuid) -> String {
+        PreferencesToken::new(subscriber_id, chrono::Duration::hours(1))
+            .sign(&self.hmac_secret)
+    }
+
+    pub async fn get_preferences(&self, token: &str) -> reqwest::Response {
+        self.api_client
+            .get(&format!("{}/preferences", &self.address))
+            .query(&[("token", token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_preferences_html(&self, token: &str) -> String {
+        self.get_preferences(token).await.text().await.unwrap()
+    }
+
+    pub async fn post_preferences_link(
+        &self,
+        email: &str,
+    ) -> reqwest::Response {
+        self.api_client
+            .post(&format!("{}/preferences/link", &self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_preferences<Body>(
+        &self,
+        action: &str,
+        body: &Body,
+    ) -> reqwest::Response
+    where
+        Body: serde::Serialize,
+    {
+        self.api_client
+            .post(&format!("{}/preferences/{}", &self.address, action))
+            .form(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     // accept mock server request (json that will be send to postframe) and search and return links in request
     pub fn get_confirmation_links(
         &self,
@@ -253,7 +726,20 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         test_user: TestUser::generate(),
         api_client: client,
+        rate_limiter: SendRateLimiter::new(
+            &configuration.email_client.rate_limit,
+        ),
+        circuit_breaker: CircuitBreaker::new(
+            &configuration.email_client.circuit_breaker,
+        ),
         email_client: configuration.email_client.client(),
+        hmac_secret: configuration.application.hmac_secret.clone(),
+        postmark_webhook: configuration.postmark_webhook.clone(),
+        metrics_token: configuration
+            .metrics
+            .bearer_token
+            .clone()
+            .expect("The metrics must be served with a bearer token"),
     };
     test_app.test_user.store(&test_app.db_pool).await;
     test_app
@@ -332,6 +818,18 @@ impl TestUser {
     }
 }
 
+/// An issue with content in both formats, sent to the default lists.
+/// Tests override the fields they are about with the struct update syntax.
+pub fn newsletter_form() -> FormData {
+    FormData {
+        title: "Newsletter title".into(),
+        text_content: "Newsletter body as plain text".into(),
+        html_content: "<p>Newsletter body as HTML</p>".into(),
+        idempotency_key: Uuid::new_v4().to_string(),
+        ..Default::default()
+    }
+}
+
 pub fn assert_is_redirect_to(response: &reqwest::Response, location: &str) {
     assert_eq!(response.status().as_u16(), 303);
     assert_eq!(response.headers().get("Location").unwrap(), location);
@@ -0,0 +1,197 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+const BRANDED_LAYOUT: &str = r#"<html><head><style>
+    p { color: #333333; }
+    .footer { font-size: 12px }
+</style></head><body>{{ content }}<p class="footer">Sent to {{ subscriber.email }}</p></body></html>"#;
+
+async fn create_layout(app: &TestApp, name: &str, html: &str) -> Uuid {
+    let response = app
+        .post_layouts(&serde_json::json!({"name": name, "html": html}))
+        .await;
+    assert_is_redirect_to(&response, "/admin/layouts");
+    sqlx::query!("SELECT layout_id FROM layouts WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the layout")
+        .layout_id
+}
+
+fn newsletter(layout_id: Option<Uuid>) -> FormData {
+    FormData {
+        layout_id,
+        ..newsletter_form()
+    }
+}
+
+#[tokio::test]
+async fn issues_are_sent_in_their_layout_with_inlined_styles() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, "Branded", BRANDED_LAYOUT).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.publish_newsletter(&newsletter(Some(layout_id))).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["HtmlBody"],
+        "<html><head></head><body>\
+        <p style=\"color: #333333\">Newsletter body as HTML</p>\
+        <p class=\"footer\" style=\"color: #333333; font-size: 12px\">\
+        Sent to ursula_le_guin@gmail.com</p></body></html>"
+    );
+    let saved = sqlx::query!("SELECT layout_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.layout_id, Some(layout_id));
+}
+
+#[tokio::test]
+async fn issues_without_a_layout_are_sent_as_typed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_newsletters(&newsletter(None)).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["HtmlBody"], "<p>Newsletter body as HTML</p>");
+}
+
+#[tokio::test]
+async fn an_unknown_layout_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(&newsletter(Some(Uuid::new_v4())))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn layouts_without_a_content_slot_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_layouts(&serde_json::json!({
+            "name": "Broken",
+            "html": "<html><body>No slot</body></html>"
+        }))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/layouts");
+    let html_page = app.get_layouts_html().await;
+    assert!(html_page.contains("The layout has no {{ content }} slot."));
+    assert!(!html_page.contains("Broken"));
+}
+
+#[tokio::test]
+async fn confirmation_emails_use_the_default_layout() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let layout_id = create_layout(&app, "Branded", BRANDED_LAYOUT).await;
+    let response = app
+        .post_layout(
+            layout_id,
+            &serde_json::json!({
+                "name": "Branded",
+                "html": BRANDED_LAYOUT,
+                "is_default": "on"
+            }),
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/layouts/{}", layout_id));
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com".into(),
+    )
+    .await;
+
+    // Assert
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    let html_body = body["HtmlBody"].as_str().unwrap();
+    assert!(html_body.contains(
+        "<p class=\"footer\" style=\"color: #333333; font-size: 12px\">\
+        Sent to ursula_le_guin@gmail.com</p>"
+    ));
+    // There is a single default layout
+    let defaults = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM layouts WHERE is_default"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(defaults.count, 1);
+    // It is not preselected for issues
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(&format!(
+        r#"<option value="{}">Branded</option>"#,
+        layout_id
+    )));
+}
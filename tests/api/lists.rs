@@ -0,0 +1,244 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+async fn create_list(app: &TestApp, name: &str) -> Uuid {
+    let response = app
+        .post_lists(&serde_json::json!({
+            "name": name,
+            "description": "A list for tests"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/lists");
+    sqlx::query!("SELECT list_id FROM lists WHERE name = $1", name)
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the list")
+        .list_id
+}
+
+// Subscribe to the given lists and click on the confirmation link
+async fn subscribe_and_confirm(app: &TestApp, email: &str, lists: &[Uuid]) {
+    let mut body = format!("name=le%20guin&email={}", email);
+    for list_id in lists {
+        body.push_str(&format!("&list_id={}", list_id));
+    }
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn publish_to(app: &TestApp, list_ids: Vec<Uuid>) {
+    app.publish_newsletter(&FormData {
+        list_ids,
+        ..newsletter_form()
+    })
+    .await;
+}
+
+async fn queued_emails(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.subscriber_email)
+        .collect()
+}
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_manage_lists() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_lists(&serde_json::json!({"name": "Weekly digest"}))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn lists_can_be_created() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    create_list(&app, "Weekly digest").await;
+
+    // Assert
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("The Weekly digest list has been created."));
+    assert!(html_page.contains("<td>Weekly digest</td>"));
+}
+
+#[tokio::test]
+async fn list_names_must_be_unique() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_list(&app, "Weekly digest").await;
+
+    // Act
+    app.post_lists(&serde_json::json!({"name": "Weekly digest"}))
+        .await;
+
+    // Assert
+    let html_page = app.get_lists_html().await;
+    assert!(html_page.contains("A list named Weekly digest already exists."));
+}
+
+#[tokio::test]
+async fn subscribing_to_an_unknown_list_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list_id={}",
+        Uuid::new_v4()
+    );
+
+    // Act
+    let response = app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn issues_are_only_enqueued_for_members_of_the_chosen_lists() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let digest = create_list(&app, "Weekly digest").await;
+    let updates = create_list(&app, "Product updates").await;
+    subscribe_and_confirm(&app, "digest%40example.com", &[digest]).await;
+    subscribe_and_confirm(&app, "updates%40example.com", &[updates]).await;
+    subscribe_and_confirm(&app, "both%40example.com", &[digest, updates]).await;
+
+    // Act
+    publish_to(&app, vec![digest]).await;
+
+    // Assert
+    let mut queued = queued_emails(&app).await;
+    queued.sort();
+    assert_eq!(queued, vec!["both@example.com", "digest@example.com"]);
+}
+
+#[tokio::test]
+async fn subscribers_on_several_chosen_lists_get_a_single_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let digest = create_list(&app, "Weekly digest").await;
+    let updates = create_list(&app, "Product updates").await;
+    subscribe_and_confirm(&app, "both%40example.com", &[digest, updates]).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_to(&app, vec![digest, updates]).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we have sent the newsletter email once
+}
+
+#[tokio::test]
+async fn joining_another_list_requires_a_new_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let digest = create_list(&app, "Weekly digest").await;
+    let updates = create_list(&app, "Product updates").await;
+    subscribe_and_confirm(&app, "a%40example.com", &[digest]).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Join a second list without confirming
+    let body =
+        format!("name=le%20guin&email=a%40example.com&list_id={}", updates);
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    publish_to(&app, vec![updates]).await;
+
+    // Assert
+    assert!(queued_emails(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn issues_without_a_chosen_list_go_to_the_default_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let digest = create_list(&app, "Weekly digest").await;
+    subscribe_and_confirm(&app, "default%40example.com", &[]).await;
+    subscribe_and_confirm(&app, "digest%40example.com", &[digest]).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    publish_to(&app, vec![]).await;
+
+    // Assert
+    assert_eq!(queued_emails(&app).await, vec!["default@example.com"]);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn the_newsletter_form_offers_every_list() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let digest = create_list(&app, "Weekly digest").await;
+
+    // Act
+    let html_page = app.get_newsletters_html().await;
+
+    // Assert
+    assert!(html_page.contains(&format!(r#"<option value="{}">"#, digest)));
+    assert!(html_page.contains("Newsletter</option>"));
+}
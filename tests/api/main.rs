@@ -1,7 +1,28 @@
+mod archive;
 mod change_password;
+mod circuit_breaker;
+mod delivery_actions;
+mod delivery_stats;
+mod export;
+mod feeds;
 mod health_check;
 mod helpers;
+mod layouts;
+mod lists;
 mod login;
+mod merge_tags;
+mod metrics;
 mod newsletters;
+mod outgoing_webhooks;
+mod postmark_webhook;
+mod preferences;
+mod rate_limiting;
+mod sanitization;
+mod segments;
+mod subscribers_import;
 mod subscription_confirm;
 mod subscriptions;
+mod suppressions;
+mod telemetry;
+mod tracking;
+mod workers;
@@ -0,0 +1,189 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+fn newsletter(title: &str, text_content: &str, html_content: &str) -> FormData {
+    FormData {
+        title: title.into(),
+        text_content: text_content.into(),
+        html_content: html_content.into(),
+        ..newsletter_form()
+    }
+}
+
+/// Publish an issue and return the body of the email delivered for it.
+async fn deliver(app: &TestApp, newsletter: &FormData) -> serde_json::Value {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    serde_json::from_slice(&email_request.body).unwrap()
+}
+
+#[tokio::test]
+async fn merge_tags_are_filled_in_for_each_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("Ursula", "ursula@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let body = deliver(
+        &app,
+        &newsletter(
+            "News for {{ subscriber.name }}",
+            "Hi {{ subscriber.name }}, this was sent to {{ subscriber.email }}",
+            "<p>Hi {{ subscriber.name }}</p>",
+        ),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(body["Subject"], "News for Ursula");
+    assert_eq!(
+        body["TextBody"],
+        "Hi Ursula, this was sent to ursula@example.com"
+    );
+    assert_eq!(body["HtmlBody"], "<p>Hi Ursula</p>");
+}
+
+#[tokio::test]
+async fn custom_fields_are_html_escaped_in_the_html_part_only() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("Ursula", "ursula@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    app.post_subscriber(
+        subscriber_id,
+        &serde_json::json!({
+            "tags": "",
+            "custom_fields": r#"{"company": "Smith & <Sons>"}"#
+        }),
+    )
+    .await;
+
+    // Act
+    let body = deliver(
+        &app,
+        &newsletter(
+            "Newsletter title",
+            "{{ subscriber.fields.company }}",
+            "<p>{{ subscriber.fields.company }}</p>",
+        ),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(body["TextBody"], "Smith & <Sons>");
+    assert_eq!(body["HtmlBody"], "<p>Smith &amp; &lt;Sons&gt;</p>");
+}
+
+#[tokio::test]
+async fn default_values_are_used_for_missing_custom_fields() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("Ursula", "ursula@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let body = deliver(
+        &app,
+        &newsletter(
+            "Newsletter title",
+            r#"Your plan: {{ subscriber.fields.plan | default: "free" }}"#,
+            "<p>Newsletter body as HTML</p>",
+        ),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(body["TextBody"], "Your plan: free");
+}
+
+#[tokio::test]
+async fn the_unsubscribe_url_leads_to_the_preferences_page() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("Ursula", "ursula@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let body = deliver(
+        &app,
+        &newsletter(
+            "Newsletter title",
+            "Unsubscribe: {{ unsubscribe_url }}",
+            "<p>Newsletter body as HTML</p>",
+        ),
+    )
+    .await;
+
+    // Assert
+    let text_body = body["TextBody"].as_str().unwrap();
+    let link = text_body.strip_prefix("Unsubscribe: ").unwrap();
+    assert!(link.starts_with(&format!("{}/preferences?token=", app.address)));
+    let html_page = reqwest::get(link).await.unwrap().text().await.unwrap();
+    assert!(html_page.contains("ursula@example.com"));
+}
+
+#[tokio::test]
+async fn issues_with_invalid_merge_tags_are_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("Ursula", "ursula@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let test_cases = vec![
+        ("Hi {{ subscriber.nmae }}", "an unknown variable"),
+        ("Hi {{ subscriber.name", "an unclosed tag"),
+        ("Hi {{ subscriber.name | upper }}", "an unknown filter"),
+    ];
+
+    for (title, error_message) in test_cases {
+        // Act
+        let response = app
+            .post_newsletters(&newsletter(
+                title,
+                "Newsletter body as plain text",
+                "<p>Newsletter body as HTML</p>",
+            ))
+            .await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the title had {}.",
+            error_message
+        );
+    }
+    app.dispatch_all_pending_emails().await;
+}
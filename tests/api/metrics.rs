@@ -0,0 +1,56 @@
+use secrecy::ExposeSecret;
+
+use crate::helpers::spawn_app;
+
+#[tokio::test]
+async fn metrics_require_the_bearer_token() {
+    // Arrange
+    let app = spawn_app().await;
+
+    for token in [None, Some("not-the-token")] {
+        // Act
+        let response = app.get_metrics(token).await;
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(response.headers()["WWW-Authenticate"], "Bearer");
+    }
+}
+
+#[tokio::test]
+async fn metrics_are_served_in_the_prometheus_text_format() {
+    // Arrange
+    let app = spawn_app().await;
+    app.get_login().await;
+    let response = app
+        .post_login(&serde_json::json!({
+            "username": "random-username",
+            "password": "random-password"
+        }))
+        .await;
+    assert_eq!(response.status().as_u16(), 303);
+
+    // Act
+    let response = app
+        .get_metrics(Some(app.metrics_token.expose_secret()))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain; version=0.0.4"));
+    let body = response.text().await.unwrap();
+    // Requests are labelled with the route pattern, not the path
+    assert!(body.contains(
+        r#"http_requests_total{method="GET",route="/login",status="200"}"#
+    ));
+    assert!(body.contains(
+        r#"http_request_duration_seconds_bucket{method="POST",route="/login",status="303""#
+    ));
+    assert!(body.contains(r#"logins_total{outcome="failure"}"#));
+    assert!(body.contains(r#"delivery_queue_depth{queue="issues"}"#));
+    assert!(body.contains(r#"db_pool_connections{state="in_use"}"#));
+    assert!(body.contains("db_pool_max_connections 10"));
+}
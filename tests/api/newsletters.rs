@@ -1,11 +1,8 @@
 use std::time::Duration;
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+use crate::helpers::{newsletter_form, spawn_app};
 
 use crate::helpers::assert_is_redirect_to;
-use fake::faker::internet::en::SafeEmail;
-use fake::faker::name::en::Name;
-use fake::Fake;
 use uuid::Uuid;
 use wiremock::matchers::{any, method, path};
 use wiremock::{Mock, ResponseTemplate};
@@ -28,7 +25,8 @@ async fn you_must_be_logged_in_to_see_newsletters_form() {
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Arrange
     let app = spawn_app().await;
-    create_unconfirmed_subscriber(&app).await;
+    app.create_unconfirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
     app.test_user.login(&app).await;
 
     Mock::given(any())
@@ -41,12 +39,7 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Act
     // A sketch of the newsletter payload structure.
     // We might change it later on.
-    let newsletter_request_body = FormData {
-        title: "Newsletter title".to_string(),
-        text_content: "Newsletter body as plain text".to_string(),
-        html_content: "<p>Newsletter body as HTML</p>".to_string(),
-        idempotency_key: Uuid::new_v4().to_string(),
-    };
+    let newsletter_request_body = newsletter_form();
 
     let response = app.post_newsletters(&newsletter_request_body).await;
 
@@ -64,61 +57,12 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    // We are working with multiple subscribers now,
-    // their details must be randomised to avoid conflicts
-    let name: String = Name().fake();
-    let email: String = SafeEmail().fake();
-    let body = serde_urlencoded::to_string(&serde_json::json!({
-        "name": name,
-        "email": email,
-    }))
-    .unwrap();
-
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
-        .expect(1)
-        .mount_as_scoped(&app.email_server)
-        .await;
-
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
-
-    // We now inspect the requests received by the mock Postmark server
-    // to retrieve the confirmation link and return it
-    let email_request = &app
-        .email_server
-        .received_requests()
-        .await
-        .unwrap()
-        .pop()
-        .unwrap();
-
-    app.get_confirmation_links(&email_request)
-}
-
-async fn create_confirmed_subscriber(app: &TestApp) {
-    // we can use the same helper and just add
-    // an extra step to actually call the confirmation link!
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_links.html)
-        .await
-        .unwrap()
-        .error_for_status()
-        .unwrap();
-}
-
 #[tokio::test]
 async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // Arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
     // Login
     app.test_user.login(&app).await;
 
@@ -130,12 +74,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         .await;
 
     // Act
-    let newsletter_request_body = FormData {
-        title: "Newsletter title".to_string(),
-        text_content: "Newsletter body as plain text".to_string(),
-        html_content: "<p>Newsletter body as HTML</p>".to_string(),
-        idempotency_key: Uuid::new_v4().to_string(),
-    };
+    let newsletter_request_body = newsletter_form();
 
     let response = app.post_newsletters(&newsletter_request_body).await;
 
@@ -172,6 +111,20 @@ async fn newsletters_returns_400_for_invalid_data() {
             }),
             "missing content",
         ),
+        (
+            serde_json::json!({
+                "title": "Newsletter title",
+                "html_content" : "<p>Newsletter body as HTML</p>",
+            }),
+            "missing text content",
+        ),
+        (
+            serde_json::json!({
+                "title": "Newsletter title",
+                "markdown_content" : "  ",
+            }),
+            "blank markdown content",
+        ),
     ];
 
     for (invalid_body, error_message) in test_cases {
@@ -222,7 +175,8 @@ async fn you_must_be_logged_in_to_go_to_dashboard() {
 async fn newsletter_creation_is_idempotent() {
     //Arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
     app.test_user.login(&app).await;
 
     Mock::given(path("/email"))
@@ -233,12 +187,7 @@ async fn newsletter_creation_is_idempotent() {
         .await;
 
     // Act - Part 1 - Submit newsletter form
-    let newsletter_request_body = FormData {
-        title: "Newsletter title".to_string(),
-        text_content: "Newsletter body as plain text".to_string(),
-        html_content: "<p>Newsletter body as HTML</p>".to_string(),
-        idempotency_key: Uuid::new_v4().to_string(),
-    };
+    let newsletter_request_body = newsletter_form();
     let response = app.post_newsletters(&newsletter_request_body).await;
     assert_is_redirect_to(&response, "/admin/newsletters");
 
@@ -268,7 +217,8 @@ async fn newsletter_creation_is_idempotent() {
 async fn concurrent_form_submisison_is_handled_gracefully() {
     //Arrange
     let app = spawn_app().await;
-    create_confirmed_subscriber(&app).await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
     app.test_user.login(&app).await;
 
     Mock::given(path("/email"))
@@ -281,12 +231,7 @@ async fn concurrent_form_submisison_is_handled_gracefully() {
         .await;
 
     //  Submit newsletter form concurrently
-    let newsletter_request_body = FormData {
-        title: "Newsletter title".to_string(),
-        text_content: "Newsletter body as plain text".to_string(),
-        html_content: "<p>Newsletter body as HTML</p>".to_string(),
-        idempotency_key: Uuid::new_v4().to_string(),
-    };
+    let newsletter_request_body = newsletter_form();
     let response1 = app.post_newsletters(&newsletter_request_body);
     let response2 = app.post_newsletters(&newsletter_request_body);
     let (response1, response2) = tokio::join!(response1, response2);
@@ -300,3 +245,56 @@ async fn concurrent_form_submisison_is_handled_gracefully() {
     app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have sent the newsletter email once
 }
+
+#[tokio::test]
+async fn issues_written_in_markdown_are_sent_as_html_and_text() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let markdown = "# Hello\n\nRead [the post](https://example.com/post).";
+    let newsletter_request_body = FormData {
+        text_content: String::new(),
+        html_content: String::new(),
+        markdown_content: markdown.to_string(),
+        ..newsletter_form()
+    };
+    let response = app.post_newsletters(&newsletter_request_body).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["HtmlBody"],
+        "<h1>Hello</h1>\n<p>Read <a href=\"https://example.com/post\">\
+        the post</a>.</p>\n"
+    );
+    assert_eq!(
+        body["TextBody"],
+        "Hello\n=====\n\nRead the post [1].\n\n[1] https://example.com/post"
+    );
+    let saved = sqlx::query!("SELECT markdown_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.markdown_content.as_deref(), Some(markdown));
+}
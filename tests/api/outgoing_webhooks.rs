@@ -0,0 +1,292 @@
+use uuid::Uuid;
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::outgoing_webhooks::signature;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+/// Add an endpoint listening to the given events and return its id.
+async fn create_endpoint(app: &TestApp, url: &str, events: &[&str]) -> Uuid {
+    let mut form = vec![("url", url)];
+    form.extend(events.iter().map(|event| ("event", *event)));
+    let response = app.post_webhooks("", &form).await;
+    let endpoint_id = sqlx::query!(
+        "SELECT endpoint_id FROM webhook_endpoints ORDER BY created_at DESC"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .endpoint_id;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/webhooks/{}", endpoint_id),
+    );
+    endpoint_id
+}
+
+async fn queued_events(app: &TestApp) -> Vec<String> {
+    sqlx::query!("SELECT event FROM webhook_delivery_queue ORDER BY event")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.event)
+        .collect()
+}
+
+#[tokio::test]
+async fn admins_can_add_and_update_endpoints() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Add an endpoint
+    let endpoint_id = create_endpoint(
+        &app,
+        "https://crm.example.com/hooks",
+        &["subscriber.subscribed"],
+    )
+    .await;
+    let path = format!("/{}", endpoint_id);
+    let html_page = app.get_webhooks_html(&path).await;
+    assert!(html_page.contains("The webhook endpoint has been added."));
+    let secret = sqlx::query!("SELECT secret FROM webhook_endpoints")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .secret;
+    assert!(html_page.contains(&format!("<code>{}</code>", secret)));
+
+    // Act - Part 2 - Listen to more events
+    let response = app
+        .post_webhooks(
+            &path,
+            &[
+                ("url", "https://crm.example.com/hooks"),
+                ("event", "subscriber.subscribed"),
+                ("event", "issue.published"),
+            ],
+        )
+        .await;
+    assert_is_redirect_to(&response, &format!("/admin/webhooks{}", path));
+    let html_page = app.get_webhooks_html("").await;
+    assert!(html_page.contains("subscriber.subscribed, issue.published"));
+
+    // Act - Part 3 - Delete it
+    let response = app
+        .api_client
+        .post(&format!("{}/admin/webhooks{}/delete", app.address, path))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/webhooks");
+    let html_page = app.get_webhooks_html("").await;
+    assert!(html_page.contains("The webhook endpoint has been deleted."));
+    assert!(!html_page.contains("https://crm.example.com/hooks"));
+}
+
+#[tokio::test]
+async fn invalid_endpoints_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (
+            vec![("url", "ftp://example.com"), ("event", "issue.published")],
+            "is not a valid HTTP(S) URL.",
+        ),
+        (
+            vec![("url", "https://example.com")],
+            "Choose at least one event.",
+        ),
+        (
+            vec![("url", "https://example.com"), ("event", "issue.deleted")],
+            "issue.deleted is not a known event.",
+        ),
+    ];
+
+    for (form, error_message) in test_cases {
+        // Act
+        let response = app.post_webhooks("", &form).await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/webhooks");
+        let html_page = app.get_webhooks_html("").await;
+        assert!(html_page.contains(error_message));
+    }
+}
+
+#[tokio::test]
+async fn subscriber_events_are_signed_and_delivered() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    create_endpoint(
+        &app,
+        &format!("{}/hooks", crm.uri()),
+        &["subscriber.subscribed", "subscriber.confirmed"],
+    )
+    .await;
+    Mock::given(path("/hooks"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&crm)
+        .await;
+
+    // Act
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.dispatch_all_pending_webhooks().await;
+
+    // Assert
+    let secret = sqlx::query!("SELECT secret FROM webhook_endpoints")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .secret;
+    let requests = crm.received_requests().await.unwrap();
+    let mut events = Vec::new();
+    for request in &requests {
+        let body = std::str::from_utf8(&request.body).unwrap();
+        // The mock server splits header values on commas
+        let header = request
+            .headers
+            .get(&"X-Webhook-Signature".into())
+            .unwrap()
+            .iter()
+            .map(|value| value.as_str())
+            .collect::<Vec<_>>()
+            .join(",");
+        let timestamp: i64 = header
+            .strip_prefix("t=")
+            .and_then(|h| h.split(',').next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(header, signature(&secret, timestamp, body));
+
+        let payload: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(payload["data"]["email"], "ursula_le_guin@gmail.com");
+        events.push(payload["type"].as_str().unwrap().to_owned());
+    }
+    events.sort();
+    assert_eq!(
+        events,
+        vec!["subscriber.confirmed", "subscriber.subscribed"]
+    );
+    assert!(queued_events(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn endpoints_only_receive_the_events_they_listen_to() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    create_endpoint(
+        &app,
+        "https://crm.example.com/hooks",
+        &["issue.published"],
+    )
+    .await;
+
+    // Act
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+
+    // Assert
+    assert!(queued_events(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_later() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let crm = MockServer::start().await;
+    let endpoint_id = create_endpoint(
+        &app,
+        &format!("{}/hooks", crm.uri()),
+        &["subscriber.subscribed"],
+    )
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&crm)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+    app.dispatch_all_pending_webhooks().await;
+
+    // Assert
+    let queued = sqlx::query!(
+        r#"
+        SELECT n_retries, execute_after > now() AS "delayed!"
+        FROM webhook_delivery_queue
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.n_retries, 1);
+    assert!(queued.delayed);
+    let html_page = app.get_webhooks_html(&format!("/{}", endpoint_id)).await;
+    assert!(html_page.contains("<td>1</td><td>retrying</td><td>503</td>"));
+}
+
+#[tokio::test]
+async fn the_end_of_a_delivery_is_announced_once() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    create_endpoint(
+        &app,
+        "https://crm.example.com/hooks",
+        &["issue.published", "issue.delivery_finished"],
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Publish
+    app.publish_newsletter(&newsletter_form()).await;
+    assert_eq!(queued_events(&app).await, vec!["issue.published"]);
+
+    // Act - Part 2 - Deliver
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(
+        queued_events(&app).await,
+        vec!["issue.delivery_finished", "issue.published"]
+    );
+    let payload = sqlx::query!(
+        r#"
+        SELECT payload FROM webhook_delivery_queue
+        WHERE event = 'issue.delivery_finished'
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .payload;
+    assert_eq!(payload["data"]["title"], "Newsletter title");
+    assert_eq!(payload["data"]["sent"], 1);
+    assert_eq!(payload["data"]["failed"], 0);
+}
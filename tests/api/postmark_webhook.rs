@@ -0,0 +1,209 @@
+use secrecy::ExposeSecret;
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{newsletter_form, spawn_app, TestApp};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+async fn subscriber_status(app: &TestApp) -> String {
+    sqlx::query!("SELECT status FROM subscriptions WHERE email = $1", EMAIL)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .status
+}
+
+fn bounce(kind: &str, inactive: bool) -> serde_json::Value {
+    serde_json::json!({
+        "RecordType": "Bounce",
+        "ID": 4323372036854775807i64,
+        "Type": kind,
+        "TypeCode": 1,
+        "MessageStream": "outbound",
+        "Email": EMAIL,
+        "BouncedAt": "2022-05-25T09:12:04Z",
+        "Description": "The server was unable to deliver your message.",
+        "Details": "smtp;550 5.1.1 The email account does not exist.",
+        "Inactive": inactive,
+        "CanActivate": true
+    })
+}
+
+#[tokio::test]
+async fn requests_without_valid_credentials_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let url = format!("{}/webhooks/postmark", app.address);
+
+    for request in [
+        app.api_client.post(&url),
+        app.api_client
+            .post(&url)
+            .basic_auth(&app.postmark_webhook.username, Some("wrong")),
+        app.api_client
+            .post(&url)
+            .header("X-Webhook-Secret", "wrong"),
+    ] {
+        // Act
+        let response = request
+            .json(&bounce("HardBounce", true))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 401);
+        assert_eq!(
+            r#"Basic realm="postmark""#,
+            response.headers()["WWW-Authenticate"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn hard_bounces_stop_the_deliveries_to_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", EMAIL).await;
+    app.test_user.login(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_postmark_webhook(&bounce("HardBounce", true)).await;
+    app.publish_newsletter(&newsletter_form()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "bounced");
+}
+
+#[tokio::test]
+async fn spam_complaints_remove_the_queued_deliveries() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", EMAIL).await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&newsletter_form()).await;
+
+    // Act
+    let response = app
+        .api_client
+        .post(&format!("{}/webhooks/postmark", app.address))
+        .header(
+            "X-Webhook-Secret",
+            app.postmark_webhook.password.expose_secret(),
+        )
+        .json(&serde_json::json!({
+            "RecordType": "SpamComplaint",
+            "Type": "SpamComplaint",
+            "Email": EMAIL,
+            "BouncedAt": "2022-05-25T09:12:04Z",
+            "Description": null
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "complained");
+    let queue =
+        sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queue.count, 0);
+}
+
+#[tokio::test]
+async fn soft_bounces_are_only_recorded() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app.create_confirmed_subscriber("le guin", EMAIL).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&bounce("SoftBounce", false))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "confirmed");
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains(
+        "<td>bounce</td><td>SoftBounce: The server was unable to deliver \
+        your message.</td><td>-</td>"
+    ));
+}
+
+#[tokio::test]
+async fn manual_suppressions_unsubscribe_the_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app.create_confirmed_subscriber("le guin", EMAIL).await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "SubscriptionChange",
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "ChangedAt": "2022-05-25T09:12:04Z",
+            "Recipient": EMAIL,
+            "Origin": "Recipient",
+            "SuppressSending": true,
+            "SuppressionReason": "ManualSuppression"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_status(&app).await, "unsubscribed");
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains(
+        "<td>subscription_change</td><td>ManualSuppression</td>\
+        <td>unsubscribed</td>"
+    ));
+}
+
+#[tokio::test]
+async fn other_records_are_acknowledged() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Delivery",
+            "Recipient": EMAIL,
+            "DeliveredAt": "2022-05-25T09:12:04Z"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn malformed_records_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .post_postmark_webhook(&serde_json::json!({
+            "RecordType": "Bounce",
+            "Email": EMAIL
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
@@ -0,0 +1,268 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, newsletter_form, spawn_app};
+
+#[tokio::test]
+async fn requesting_a_link_emails_a_preferences_link() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula@example.com")
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for a link
+    let response = app.post_preferences_link("ursula@example.com").await;
+    assert_is_redirect_to(&response, "/preferences/link");
+
+    // Act - Part 2 - Follow the link
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let links = app.get_confirmation_links(email_request);
+    assert_eq!(links.html.path(), "/preferences");
+    let html_page = reqwest::get(links.html)
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("ursula@example.com"));
+}
+
+#[tokio::test]
+async fn requesting_a_link_for_an_unknown_address_sends_nothing() {
+    // Arrange
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_preferences_link("nobody@example.com").await;
+
+    // Assert - the answer does not disclose who is subscribed
+    assert_is_redirect_to(&response, "/preferences/link");
+    let html_page = app
+        .api_client
+        .get(&format!("{}/preferences/link", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(html_page.contains("If this address is subscribed"));
+}
+
+#[tokio::test]
+async fn an_invalid_token_is_sent_back_to_request_a_new_link() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+    let tampered = format!("{}0", token);
+
+    // Act
+    let response = app.get_preferences(&tampered).await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/preferences/link");
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_name() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+
+    // Act
+    let response = app
+        .post_preferences(
+            "name",
+            &serde_json::json!({"token": &token, "name": "Ursula K. Le Guin"}),
+        )
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, &format!("/preferences?token={}", token));
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("Your name has been updated."));
+    let saved = sqlx::query!(
+        "SELECT name FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+}
+
+#[tokio::test]
+async fn paused_subscribers_do_not_receive_newsletters() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+    app.post_preferences(
+        "pause",
+        &serde_json::json!({"token": &token, "weeks": 2}),
+    )
+    .await;
+    app.test_user.login(&app).await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.publish_newsletter(&newsletter_form()).await;
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that we haven't sent the newsletter email
+}
+
+#[tokio::test]
+async fn subscribers_can_resume_a_paused_delivery() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+    app.post_preferences(
+        "pause",
+        &serde_json::json!({"token": &token, "weeks": 4}),
+    )
+    .await;
+
+    // Act
+    app.post_preferences(
+        "pause",
+        &serde_json::json!({"token": &token, "weeks": 0}),
+    )
+    .await;
+
+    // Assert
+    let saved = sqlx::query!(
+        "SELECT paused_until FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(saved.paused_until.is_none());
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+
+    // Act
+    app.post_preferences("unsubscribe", &serde_json::json!({"token": &token}))
+        .await;
+
+    // Assert
+    let html_page = app.get_preferences_html(&token).await;
+    assert!(html_page.contains("You have been unsubscribed."));
+    let saved = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_email_change_takes_effect_once_confirmed() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "old@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act - Part 1 - Ask for the change
+    app.post_preferences(
+        "email",
+        &serde_json::json!({"token": &token, "email": "new@example.com"}),
+    )
+    .await;
+    let saved = sqlx::query!(
+        "SELECT email FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.email, "old@example.com");
+
+    // Act - Part 2 - Click the link sent to the new address
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["To"], "new@example.com");
+    let links = app.get_confirmation_links(email_request);
+    let response = reqwest::get(links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Email address updated"));
+    let saved = sqlx::query!(
+        "SELECT email, status FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.email, "new@example.com");
+    assert_eq!(saved.status, "confirmed");
+}
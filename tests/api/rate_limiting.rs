@@ -0,0 +1,140 @@
+use std::time::Duration;
+
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::RateLimitSettings;
+use zero2prod::domain::SubscriberEmail;
+use zero2prod::rate_limiter::SendRateLimiter;
+
+use crate::helpers::{newsletter_form, spawn_app};
+
+fn email(address: &str) -> SubscriberEmail {
+    SubscriberEmail::parse(address.into()).unwrap()
+}
+
+#[tokio::test]
+async fn the_send_rate_of_a_domain_is_shared_by_every_worker() {
+    // Arrange
+    let app = spawn_app().await;
+    let settings = RateLimitSettings {
+        messages_per_second: 100.0,
+        messages_per_second_per_domain: 2.0,
+    };
+    // As in two worker processes
+    let first = SendRateLimiter::new(&settings);
+    let second = SendRateLimiter::new(&settings);
+
+    // Act - Part 1 - A burst uses up the bucket of the domain
+    let ursula = first
+        .reserve(&app.db_pool, &email("ursula@gmail.com"))
+        .await
+        .unwrap();
+    let octavia = first
+        .reserve(&app.db_pool, &email("octavia@gmail.com"))
+        .await
+        .unwrap();
+    // Assert - Part 1
+    assert_eq!(ursula, Duration::ZERO);
+    assert_eq!(octavia, Duration::ZERO);
+
+    // Act - Part 2 - Other domains are not held back
+    let ted = second
+        .reserve(&app.db_pool, &email("ted@example.com"))
+        .await
+        .unwrap();
+    // Assert - Part 2
+    assert_eq!(ted, Duration::ZERO);
+
+    // Act - Part 3 - The other worker waits for the domain bucket to refill
+    let iain = second
+        .reserve(&app.db_pool, &email("iain@GMAIL.com"))
+        .await
+        .unwrap();
+    // Assert - Part 3
+    assert!(!iain.is_zero());
+    assert!(iain <= Duration::from_millis(500));
+}
+
+#[tokio::test]
+async fn deliveries_are_postponed_when_the_provider_rate_limits_us() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&newsletter_form()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(429).insert_header("Retry-After", "120"),
+        )
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    let task = sqlx::query!(
+        r#"
+        SELECT
+            n_retries,
+            execute_after > now() + interval '100 seconds' AS "postponed!"
+        FROM issue_delivery_queue
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    // Being rate limited does not count as a failed attempt
+    assert_eq!(task.n_retries, 0);
+    assert!(task.postponed);
+    // Every send is held back, not only this one
+    let bucket = sqlx::query!(
+        "SELECT tokens FROM send_rate_buckets WHERE bucket = 'provider'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(bucket.tokens < -1.0);
+}
+
+#[tokio::test]
+async fn idle_domain_buckets_are_forgotten() {
+    // Arrange
+    let app = spawn_app().await;
+    let settings = RateLimitSettings {
+        messages_per_second: 100.0,
+        messages_per_second_per_domain: 2.0,
+    };
+    let rate_limiter = SendRateLimiter::new(&settings);
+    rate_limiter
+        .reserve(&app.db_pool, &email("ursula@gmail.com"))
+        .await
+        .unwrap();
+    // Long enough for the gmail.com bucket to refill
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    for address in ["a@example.com", "b@example.com", "c@example.com"] {
+        rate_limiter
+            .reserve(&app.db_pool, &email(address))
+            .await
+            .unwrap();
+    }
+
+    // Act
+    let forgotten = rate_limiter
+        .forget_idle_buckets(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(forgotten, 1);
+    let buckets = sqlx::query!("SELECT bucket FROM send_rate_buckets")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    let mut buckets: Vec<_> = buckets.into_iter().map(|r| r.bucket).collect();
+    buckets.sort();
+    assert_eq!(buckets, vec!["domain:example.com", "provider"]);
+}
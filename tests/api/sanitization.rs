@@ -0,0 +1,78 @@
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{newsletter_form, spawn_app, TestApp};
+
+fn newsletter(html_content: &str) -> FormData {
+    FormData {
+        html_content: html_content.into(),
+        ..newsletter_form()
+    }
+}
+
+async fn stored_html_content(app: &TestApp) -> String {
+    sqlx::query!("SELECT html_content FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch the newsletter issue")
+        .html_content
+}
+
+#[tokio::test]
+async fn scripts_and_iframes_are_removed_before_storing_an_issue() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.publish_newsletter(&newsletter(
+            r#"<p onclick="steal()">Hi</p><script>alert(1)</script><iframe src="https://tracker.example.com"></iframe>"#,
+        )).await;
+
+    // Assert
+    assert_eq!(stored_html_content(&app).await, "<p>Hi</p>");
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page.contains(
+        "The HTML content was sanitized. Removed 1 &lt;iframe&gt; tag, \
+        1 &lt;script&gt; tag, 1 onclick attribute."
+    ));
+    assert!(html_page.contains("The newsletter issue has been accepted"));
+}
+
+#[tokio::test]
+async fn insecure_urls_are_rewritten_to_https() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    app.publish_newsletter(&newsletter(
+        r#"<p><a href="http://example.com/post">Read</a></p>"#,
+    ))
+    .await;
+
+    // Assert
+    assert_eq!(
+        stored_html_content(&app).await,
+        r#"<p><a href="https://example.com/post">Read</a></p>"#
+    );
+    let html_page = app.get_newsletters_html().await;
+    assert!(html_page
+        .contains("The HTML content was sanitized. Rewrote 1 URL to https."));
+}
+
+#[tokio::test]
+async fn no_warning_is_shown_for_allowed_html() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_content =
+        r#"<p style="color: red">Newsletter <strong>body</strong></p>"#;
+    app.publish_newsletter(&newsletter(html_content)).await;
+
+    // Assert
+    assert_eq!(stored_html_content(&app).await, html_content);
+    let html_page = app.get_newsletters_html().await;
+    assert!(!html_page.contains("sanitized"));
+}
@@ -0,0 +1,219 @@
+use uuid::Uuid;
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+async fn tag(app: &TestApp, subscriber_id: Uuid, tags: &str, fields: &str) {
+    let response = app
+        .post_subscriber(
+            subscriber_id,
+            &serde_json::json!({"tags": tags, "custom_fields": fields}),
+        )
+        .await;
+    assert_is_redirect_to(
+        &response,
+        &format!("/admin/subscribers/{}", subscriber_id),
+    );
+}
+
+fn newsletter(segment: &str) -> FormData {
+    FormData {
+        segment: segment.into(),
+        ..newsletter_form()
+    }
+}
+
+async fn queued_emails(app: &TestApp) -> Vec<String> {
+    let mut emails: Vec<String> =
+        sqlx::query!("SELECT subscriber_email FROM issue_delivery_queue")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.subscriber_email)
+            .collect();
+    emails.sort();
+    emails
+}
+
+#[tokio::test]
+async fn tags_and_custom_fields_can_be_edited() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    tag(&app, subscriber_id, "Beta, vip", r#"{"plan": "pro"}"#).await;
+
+    // Assert
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains("The subscriber has been updated."));
+    assert!(html_page.contains(r#"value="beta, vip""#));
+    let saved = sqlx::query!(
+        "SELECT tags, custom_fields FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.tags, vec!["beta", "vip"]);
+    assert_eq!(saved.custom_fields, serde_json::json!({"plan": "pro"}));
+}
+
+#[tokio::test]
+async fn invalid_custom_fields_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    tag(&app, subscriber_id, "beta", "[1, 2]").await;
+
+    // Assert
+    let html_page = app.get_subscriber_html(subscriber_id).await;
+    assert!(html_page.contains("Custom fields must be a JSON object."));
+    let saved = sqlx::query!(
+        "SELECT tags FROM subscriptions WHERE id = $1",
+        subscriber_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(saved.tags.is_empty());
+}
+
+#[tokio::test]
+async fn the_subscriber_list_can_be_searched() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula@example.com")
+        .await;
+    app.create_confirmed_subscriber("le guin", "octavia@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app
+        .api_client
+        .get(&format!("{}/admin/subscribers?q=ursula", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(html_page.contains("ursula@example.com"));
+    assert!(!html_page.contains("octavia@example.com"));
+}
+
+#[tokio::test]
+async fn issues_with_a_segment_are_only_enqueued_for_matching_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let a = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let b = app
+        .create_confirmed_subscriber("le guin", "b@example.com")
+        .await;
+    let c = app
+        .create_confirmed_subscriber("le guin", "c@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    tag(&app, a, "beta", r#"{"seats": 12}"#).await;
+    tag(&app, b, "beta", r#"{"seats": 2}"#).await;
+    tag(&app, c, "", r#"{"seats": 50}"#).await;
+
+    // Act
+    let response = app
+        .post_newsletters(&newsletter("tag beta and field.seats > 10"))
+        .await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    assert_eq!(queued_emails(&app).await, vec!["a@example.com"]);
+    let saved = sqlx::query!("SELECT segment FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        saved.segment.as_deref(),
+        Some("tag beta and field.seats > 10")
+    );
+}
+
+#[tokio::test]
+async fn segments_can_filter_on_the_subscription_date() {
+    // Arrange
+    let app = spawn_app().await;
+    let old = app
+        .create_confirmed_subscriber("le guin", "old@example.com")
+        .await;
+    app.create_confirmed_subscriber("le guin", "new@example.com")
+        .await;
+    sqlx::query!(
+        "UPDATE subscriptions SET subscribed_at = '2022-01-15' WHERE id = $1",
+        old
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.test_user.login(&app).await;
+
+    // Act
+    app.post_newsletters(&newsletter("subscribed_at >= 2022-03-01"))
+        .await;
+
+    // Assert
+    assert_eq!(queued_emails(&app).await, vec!["new@example.com"]);
+}
+
+#[tokio::test]
+async fn issues_without_a_segment_go_to_everybody() {
+    // Arrange
+    let app = spawn_app().await;
+    let a = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.create_confirmed_subscriber("le guin", "b@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    tag(&app, a, "beta", "").await;
+
+    // Act
+    app.post_newsletters(&newsletter("  ")).await;
+
+    // Assert
+    assert_eq!(
+        queued_emails(&app).await,
+        vec!["a@example.com", "b@example.com"]
+    );
+}
+
+#[tokio::test]
+async fn an_invalid_segment_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let response = app
+        .post_newsletters(&newsletter("tag beta and colour = red"))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(queued_emails(&app).await.is_empty());
+}
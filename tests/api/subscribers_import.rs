@@ -0,0 +1,224 @@
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{assert_is_redirect_to, spawn_app};
+
+#[tokio::test]
+async fn you_must_be_logged_in_to_import_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    let csv = "email,name\nursula_le_guin@gmail.com,le guin\n".to_string();
+
+    // Act
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/login");
+}
+
+#[tokio::test]
+async fn valid_rows_are_imported_as_confirmed_subscribers() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,le guin\n\
+        octavia_butler@gmail.com,Octavia Butler\n"
+        .to_string();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        // No confirmation email for rows imported as confirmed
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["inserted"], 2);
+    assert_eq!(summary["rejected"], 0);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn pending_imports_send_a_confirmation_email_to_each_new_subscriber() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,le guin\n\
+        octavia_butler@gmail.com,Octavia Butler\n"
+        .to_string();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscribers_import(csv, "pending_confirmation")
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_all(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscriptions.");
+    assert!(saved.iter().all(|s| s.status == "pending_confirmation"));
+    // The emails are left to the worker
+    let queued =
+        sqlx::query!("SELECT subscriber_id FROM confirmation_email_queue")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(queued.len(), 2);
+    app.dispatch_all_pending_confirmation_emails().await;
+    // Mock verifies on Drop that both confirmation emails went out
+}
+
+#[tokio::test]
+async fn suppressed_imported_subscribers_get_no_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_suppressions(&serde_json::json!({
+        "kind": "domain",
+        "value": "example.com",
+        "reason": "The domain expired"
+    }))
+    .await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,le guin\n\
+        octavia_butler@example.com,Octavia Butler\n"
+        .to_string();
+    app.post_subscribers_import(csv, "pending_confirmation")
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.dispatch_all_pending_confirmation_emails().await;
+
+    // Assert
+    let queued = sqlx::query!(
+        r#"SELECT COUNT(*) AS "count!" FROM confirmation_email_queue"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(queued.count, 0);
+    let skipped = sqlx::query!("SELECT email, context FROM suppressed_sends")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(skipped.email, "octavia_butler@example.com");
+    assert_eq!(skipped.context, "subscribe");
+}
+
+#[tokio::test]
+async fn invalid_and_duplicate_rows_are_reported() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv = "email,name\n\
+        ursula_le_guin@gmail.com,le guin\n\
+        definitely-not-an-email,Someone\n\
+        ursula_le_guin@gmail.com,Ursula\n\
+        octavia_butler@gmail.com,\n"
+        .to_string();
+
+    // Act - Part 1 - Import
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+    assert_eq!(response.status().as_u16(), 200);
+    let summary: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(summary["inserted"], 1);
+    assert_eq!(summary["duplicates"], 1);
+    assert_eq!(summary["rejected"], 2);
+
+    // Act - Part 2 - Download the report
+    let response = app
+        .get_import_rejections(summary["report_url"].as_str().unwrap())
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let report = response.text().await.unwrap();
+
+    // Assert
+    let lines: Vec<&str> = report.lines().collect();
+    assert_eq!(lines[0], "row_number,email,name,reason");
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1].starts_with("2,definitely-not-an-email,Someone,"));
+    assert!(lines[2].starts_with("3,ursula_le_guin@gmail.com,Ursula,"));
+    assert!(lines[3].starts_with("4,octavia_butler@gmail.com,,"));
+}
+
+#[tokio::test]
+async fn import_without_required_columns_is_rejected_with_a_400() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let csv =
+        "address,full_name\nursula_le_guin@gmail.com,le guin\n".to_string();
+
+    // Act
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn an_import_stopped_by_a_malformed_row_is_marked_as_failed() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    // The second row is not valid UTF-8
+    let csv = b"email,name\n\
+        ursula_le_guin@gmail.com,le guin\n\
+        octavia_butler@gmail.com,Octavia \xff Butler\n"
+        .to_vec();
+
+    // Act
+    let response = app.post_subscribers_import(csv, "confirmed").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let import = sqlx::query!(
+        r#"
+        SELECT
+            completed_at IS NULL AS "not_completed!",
+            failed_at IS NOT NULL AS "failed!",
+            error
+        FROM subscriber_imports
+        "#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Failed to fetch the import.");
+    assert!(import.not_completed);
+    assert!(import.failed);
+    assert!(import
+        .error
+        .unwrap()
+        .starts_with("Failed to read row 2 of the uploaded CSV file"));
+}
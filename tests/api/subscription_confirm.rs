@@ -84,3 +84,132 @@ async fn click_on_link_confirms_a_subscriber() {
     assert_eq!(saved.email, "ursula_le_guin3@gmail.com");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn expired_confirmation_links_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin3%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_requests = &app.email_server.received_requests().await.unwrap();
+    let confirmation_link = app.get_confirmation_links(&email_requests[0]);
+
+    // Age the token past the configured expiry
+    sqlx::query!(
+        "UPDATE subscription_token SET created_at = now() - interval '1 year'"
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Confirmation link expired"));
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription");
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn confirming_twice_shows_the_already_confirmed_page() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin3%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_requests = &app.email_server.received_requests().await.unwrap();
+    let confirmation_link = app.get_confirmation_links(&email_requests[0]);
+
+    // Act - Part 1 - Confirm
+    let response = reqwest::get(confirmation_link.html.clone()).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Subscription confirmed"));
+    assert!(html_page.contains(r#"<a href="/home">"#));
+
+    // Act - Part 2 - Confirm again
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Already confirmed"));
+}
+
+#[tokio::test]
+async fn unknown_tokens_show_the_invalid_link_page() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?subscription_token=not-a-real-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let html_page = response.text().await.unwrap();
+    assert!(html_page.contains("Invalid confirmation link"));
+}
+
+#[tokio::test]
+async fn an_old_link_does_not_resubscribe_someone_who_unsubscribed() {
+    // Arrange
+    let app = spawn_app().await;
+    let confirmation_link = app
+        .create_unconfirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    reqwest::get(confirmation_link.html.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT id FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let token = app.preferences_token(saved.id);
+    app.post_preferences("unsubscribe", &serde_json::json!({"token": &token}))
+        .await;
+
+    // Act
+    let response = reqwest::get(confirmation_link.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+    let suppression = sqlx::query!(
+        "SELECT source FROM suppressions WHERE value = $1",
+        "ursula_le_guin@gmail.com"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(suppression.source, "unsubscribe");
+}
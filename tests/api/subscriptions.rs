@@ -167,3 +167,125 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribing_twice_while_pending_sends_a_fresh_confirmation_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let first_response = app.post_subscriptions(body.into()).await;
+    let second_response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let email_requests = &app.email_server.received_requests().await.unwrap();
+    let first_link = app.get_confirmation_links(&email_requests[0]);
+    let second_link = app.get_confirmation_links(&email_requests[1]);
+    assert_ne!(first_link.html, second_link.html);
+}
+
+#[tokio::test]
+async fn subscribing_again_after_confirming_returns_200_without_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // Only the first subscription triggers an email
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body.into()).await;
+    let email_requests = &app.email_server.received_requests().await.unwrap();
+    let confirmation_link = app.get_confirmation_links(&email_requests[0]);
+    reqwest::get(confirmation_link.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn concurrent_subscriptions_of_the_same_address_both_succeed() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let (first_response, second_response) = tokio::join!(
+        app.post_subscriptions(body.into()),
+        app.post_subscriptions(body.into())
+    );
+
+    // Assert
+    assert_eq!(first_response.status().as_u16(), 200);
+    assert_eq!(second_response.status().as_u16(), 200);
+    let saved =
+        sqlx::query!(r#"SELECT COUNT(*) AS "count!" FROM subscriptions"#)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_after_bouncing_returns_200_without_an_email() {
+    // Arrange
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        // Only the first subscription triggers an email
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_subscriptions(body.into()).await;
+    sqlx::query!("UPDATE subscriptions SET status = 'bounced'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Act
+    let response = app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+    assert_eq!(saved.status, "bounced");
+}
@@ -0,0 +1,300 @@
+use wiremock::matchers::any;
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+/// The contexts in which sends to the address were skipped.
+async fn suppressed_sends(app: &TestApp, email: &str) -> Vec<String> {
+    sqlx::query!(
+        "SELECT context FROM suppressed_sends WHERE email = $1",
+        email
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.context)
+    .collect()
+}
+
+async fn queue_size(app: &TestApp) -> i64 {
+    sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM issue_delivery_queue")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count
+}
+
+#[tokio::test]
+async fn admins_can_add_and_remove_suppressions() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act - Part 1 - Suppress a domain
+    let response = app
+        .post_suppressions(&serde_json::json!({
+            "kind": "domain",
+            "value": "@Example.COM",
+            "reason": "Spam trap"
+        }))
+        .await;
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("example.com has been suppressed."));
+    assert!(html_page.contains(
+        "<td>domain</td><td>example.com</td><td>Spam trap</td><td>admin</td>"
+    ));
+
+    // Act - Part 2 - Suppress it again
+    app.post_suppressions(&serde_json::json!({
+        "kind": "domain",
+        "value": "example.com",
+        "reason": "Spam trap"
+    }))
+    .await;
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("example.com is already suppressed."));
+
+    // Act - Part 3 - Remove it
+    let suppression_id =
+        sqlx::query!("SELECT suppression_id FROM suppressions")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .suppression_id;
+    let response = app
+        .api_client
+        .post(&format!(
+            "{}/admin/suppressions/{}/delete",
+            app.address, suppression_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_is_redirect_to(&response, "/admin/suppressions");
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("The suppression has been removed."));
+    assert!(!html_page.contains("<td>example.com</td>"));
+}
+
+#[tokio::test]
+async fn invalid_suppressions_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    let test_cases = vec![
+        (("address", "not-an-email", "Typo"), "is not a valid"),
+        (("domain", "localhost", "Typo"), "is not a valid domain."),
+        (("address", "a@example.com", " "), "Give a reason"),
+    ];
+
+    for ((kind, value, reason), error_message) in test_cases {
+        // Act
+        let response = app
+            .post_suppressions(&serde_json::json!({
+                "kind": kind,
+                "value": value,
+                "reason": reason
+            }))
+            .await;
+
+        // Assert
+        assert_is_redirect_to(&response, "/admin/suppressions");
+        let html_page = app.get_suppressions_html().await;
+        assert!(html_page.contains(error_message));
+    }
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn subscribing_from_a_suppressed_domain_sends_no_email() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+    app.post_suppressions(&serde_json::json!({
+        "kind": "domain",
+        "value": "gmail.com",
+        "reason": "Spam trap"
+    }))
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app
+        .post_subscriptions("name=le%20guin&email=ursula%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        suppressed_sends(&app, "ursula@gmail.com").await,
+        vec!["subscribe"]
+    );
+    let html_page = app.get_suppressions_html().await;
+    assert!(html_page.contains("The domain gmail.com is suppressed (admin)"));
+}
+
+#[tokio::test]
+async fn suppressed_subscribers_are_not_queued() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.create_confirmed_subscriber("le guin", "b@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    app.post_suppressions(&serde_json::json!({
+        "kind": "address",
+        "value": "a@example.com",
+        "reason": "Asked by phone"
+    }))
+    .await;
+
+    // Act
+    app.publish_newsletter(&newsletter_form()).await;
+
+    // Assert
+    assert_eq!(queue_size(&app).await, 1);
+    assert_eq!(
+        suppressed_sends(&app, "a@example.com").await,
+        vec!["enqueue"]
+    );
+}
+
+#[tokio::test]
+async fn addresses_suppressed_after_publishing_are_skipped() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&newsletter_form()).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_suppressions(&serde_json::json!({
+        "kind": "domain",
+        "value": "example.com",
+        "reason": "The domain expired"
+    }))
+    .await;
+    app.dispatch_all_pending_emails().await;
+
+    // Assert
+    assert_eq!(queue_size(&app).await, 0);
+    assert_eq!(
+        suppressed_sends(&app, "a@example.com").await,
+        vec!["delivery"]
+    );
+}
+
+#[tokio::test]
+async fn unsubscribed_addresses_can_subscribe_again() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+    app.post_preferences("unsubscribe", &serde_json::json!({"token": &token}))
+        .await;
+    let source = sqlx::query!(
+        "SELECT source FROM suppressions WHERE value = 'a@example.com'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .source;
+    assert_eq!(source, "unsubscribe");
+
+    // Act
+    app.create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+
+    // Assert
+    let saved = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM suppressions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_a_preferences_link() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    app.test_user.login(&app).await;
+    app.post_suppressions(&serde_json::json!({
+        "kind": "domain",
+        "value": "example.com",
+        "reason": "The domain expired"
+    }))
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let response = app.post_preferences_link("a@example.com").await;
+
+    // Assert
+    assert_is_redirect_to(&response, "/preferences/link");
+    assert_eq!(
+        suppressed_sends(&app, "a@example.com").await,
+        vec!["preferences_link"]
+    );
+}
+
+#[tokio::test]
+async fn suppressed_addresses_are_not_sent_an_email_change_confirmation() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "a@example.com")
+        .await;
+    let token = app.preferences_token(subscriber_id);
+    app.test_user.login(&app).await;
+    app.post_suppressions(&serde_json::json!({
+        "kind": "address",
+        "value": "b@example.com",
+        "reason": "Asked by phone"
+    }))
+    .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    app.post_preferences(
+        "email",
+        &serde_json::json!({"token": &token, "email": "b@example.com"}),
+    )
+    .await;
+
+    // Assert
+    assert_eq!(
+        suppressed_sends(&app, "b@example.com").await,
+        vec!["email_change"]
+    );
+}
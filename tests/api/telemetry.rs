@@ -0,0 +1,125 @@
+use opentelemetry::trace::TracerProvider as _;
+use tracing_subscriber::layer::SubscriberExt;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::configuration::TelemetrySettings;
+use zero2prod::telemetry::get_tracer_provider;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+// The example of the W3C Trace Context specification
+const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+const TRACEPARENT: &str =
+    "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+/// Publish an issue from a request that is part of the `TRACEPARENT` trace.
+async fn publish_traced_newsletter(app: &TestApp) {
+    let body = serde_html_form::to_string(&newsletter_form()).unwrap();
+    let response = app
+        .api_client
+        .post(&format!("{}/admin/newsletters", &app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("traceparent", TRACEPARENT)
+        .body(body)
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_is_redirect_to(&response, "/admin/newsletters");
+}
+
+#[tokio::test]
+async fn delivery_tasks_store_the_trace_context_of_the_publish_request() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    publish_traced_newsletter(&app).await;
+
+    // Assert
+    let trace_context =
+        sqlx::query!("SELECT trace_context FROM issue_delivery_queue")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .trace_context
+            .unwrap();
+    let traceparent = trace_context["traceparent"].as_str().unwrap();
+    assert!(traceparent.starts_with(&format!("00-{}-", TRACE_ID)));
+    // The span that enqueued the task is a descendant of the remote one
+    assert_ne!(traceparent, TRACEPARENT);
+}
+
+// Multi-threaded, the batch exporter needs the runtime while it is flushed
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn delivery_spans_are_exported_in_the_trace_of_the_publish_request() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    publish_traced_newsletter(&app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // Stands in for an OpenTelemetry collector
+    let collector = MockServer::start().await;
+    Mock::given(path("/v1/traces"))
+        .and(method("POST"))
+        .and(header("Content-Type", "application/x-protobuf"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1..)
+        .mount(&collector)
+        .await;
+    let tracer_provider = get_tracer_provider(
+        "test".into(),
+        &TelemetrySettings {
+            otlp_endpoint: Some(format!("{}/v1/traces", collector.uri())),
+        },
+    )
+    .unwrap();
+    let subscriber = tracing_subscriber::registry().with(
+        tracing_opentelemetry::layer()
+            .with_tracer(tracer_provider.tracer("test")),
+    );
+    // Only for the spans of this test, which runs the worker on this thread
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    // Act
+    app.dispatch_all_pending_emails().await;
+    tokio::task::spawn_blocking(move || {
+        for processor in tracer_provider.span_processors() {
+            processor.force_flush().unwrap();
+        }
+    })
+    .await
+    .unwrap();
+
+    // Assert
+    let trace_id = hex::decode(TRACE_ID).unwrap();
+    let exported = collector
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|request| request.body)
+        .find(|body| {
+            contains(body, b"execute_task") && contains(body, &trace_id)
+        });
+    assert!(exported.is_some());
+}
+
+// The OTLP payload is protobuf-encoded, where span names and trace ids
+// appear as they are
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
@@ -0,0 +1,165 @@
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::domain::TrackingToken;
+use zero2prod::routes::admin::newsletters::FormData;
+
+use crate::helpers::{
+    assert_is_redirect_to, newsletter_form, spawn_app, TestApp,
+};
+
+fn newsletter(tracking: bool) -> FormData {
+    FormData {
+        html_content: r#"<p><a href="https://example.com/post">Read</a> or <a href="{{ unsubscribe_url }}">unsubscribe</a></p>"#.into(),
+        tracking: tracking.then(|| "on".to_string()),
+        ..newsletter_form()
+    }
+}
+
+/// Publish an issue and return the HTML body of the email delivered for it.
+async fn deliver(app: &TestApp, newsletter: &FormData) -> String {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(newsletter).await;
+    assert_is_redirect_to(&response, "/admin/newsletters");
+    app.dispatch_all_pending_emails().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value =
+        serde_json::from_slice(&email_request.body).unwrap();
+    body["HtmlBody"].as_str().unwrap().to_owned()
+}
+
+// The URLs of the links and images of an HTML body
+fn urls(html: &str) -> Vec<String> {
+    html.split('"')
+        .filter(|s| s.starts_with("http"))
+        .map(String::from)
+        .collect()
+}
+
+async fn issue_id(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT newsletter_issue_id FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .newsletter_issue_id
+}
+
+#[tokio::test]
+async fn opens_and_clicks_of_tracked_issues_are_counted() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let html = deliver(&app, &newsletter(true)).await;
+    let urls = urls(&html);
+    let click_url = urls.iter().find(|u| u.contains("/t/c/")).unwrap();
+    let pixel_url = urls.iter().find(|u| u.contains("/t/o/")).unwrap();
+    // The links to the preferences page are not tracked
+    assert!(urls.iter().any(|u| u.contains("/preferences?token=")));
+
+    // Act
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    for _ in 0..2 {
+        let response = client.get(pixel_url).send().await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(response.headers()["Content-Type"], "image/gif");
+    }
+    let response = client.get(click_url).send().await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 302);
+    assert_eq!(response.headers()["Location"], "https://example.com/post");
+    let html_page = app.get_newsletter_issue_html(issue_id(&app).await).await;
+    assert!(html_page.contains("<tr><th>Unique opens</th><td>1</td></tr>"));
+    assert!(html_page.contains("<tr><th>Unique clicks</th><td>1</td></tr>"));
+}
+
+#[tokio::test]
+async fn untracked_issues_have_no_pixel_nor_redirects() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html = deliver(&app, &newsletter(false)).await;
+
+    // Assert
+    assert!(!html.contains("/t/"));
+    assert!(html.contains(r#"<a href="https://example.com/post">"#));
+}
+
+#[tokio::test]
+async fn redirects_to_links_outside_the_issue_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+    let subscriber_id = app
+        .create_confirmed_subscriber("le guin", "ursula_le_guin@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    deliver(&app, &newsletter(true)).await;
+    let token = TrackingToken {
+        newsletter_issue_id: issue_id(&app).await,
+        subscriber_id,
+        link: Some("https://evil.example.com".into()),
+    };
+
+    // Act
+    let response = app
+        .api_client
+        .get(&format!(
+            "{}/t/c/{}",
+            app.address,
+            token.sign(&app.hmac_secret)
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+    let events = sqlx::query!("SELECT COUNT(*) AS count FROM email_events")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(events.count, Some(0));
+}
+
+#[tokio::test]
+async fn tampered_tracking_tokens_are_rejected() {
+    // Arrange
+    let app = spawn_app().await;
+
+    // Act
+    let response = app
+        .api_client
+        .get(&format!(
+            "{}/t/o/{}.{}..abcd",
+            app.address,
+            Uuid::new_v4(),
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::issue_delivery_worker::{try_execute_task, ExecutionOutcome};
+use zero2prod::workers::heartbeat;
+
+use crate::helpers::{newsletter_form, spawn_app, TestApp};
+
+// One consumer of a worker, taking tasks until the queue is empty
+async fn consume_all(app: &TestApp, worker_id: Uuid) -> usize {
+    let mut executed = 0;
+    while let ExecutionOutcome::TaskCompleted = try_execute_task(
+        &app.db_pool,
+        &app.email_client,
+        &app.rate_limiter,
+        &app.circuit_breaker,
+        &app.address,
+        &app.hmac_secret,
+        worker_id,
+    )
+    .await
+    .unwrap()
+    {
+        executed += 1;
+    }
+    executed
+}
+
+#[tokio::test]
+async fn concurrent_consumers_deliver_each_email_once() {
+    // Arrange
+    let app = spawn_app().await;
+    for name in ["ursula", "octavia", "ted", "iain", "becky"] {
+        app.create_confirmed_subscriber(name, &format!("{}@gmail.com", name))
+            .await;
+    }
+    app.test_user.login(&app).await;
+    app.publish_newsletter(&newsletter_form()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(5)
+        .mount(&app.email_server)
+        .await;
+
+    // Act
+    let worker_id = Uuid::new_v4();
+    let (first, second) = tokio::join!(
+        consume_all(&app, worker_id),
+        consume_all(&app, worker_id)
+    );
+
+    // Assert
+    assert_eq!(first + second, 5);
+    let delivered: HashSet<String> = sqlx::query!(
+        "SELECT subscriber_email FROM issue_delivery_log WHERE worker_id = $1",
+        worker_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.subscriber_email)
+    .collect();
+    assert_eq!(delivered.len(), 5);
+    // Mock verifies on Drop that each email was sent once
+}
+
+#[tokio::test]
+async fn the_dashboard_shows_the_live_workers_and_their_throughput() {
+    // Arrange
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("ursula", "ursula@gmail.com")
+        .await;
+    app.test_user.login(&app).await;
+    let worker_id = Uuid::new_v4();
+    heartbeat(&app.db_pool, worker_id, 3).await.unwrap();
+    let gone_id = Uuid::new_v4();
+    heartbeat(&app.db_pool, gone_id, 1).await.unwrap();
+    sqlx::query!(
+        "UPDATE workers SET heartbeat_at = now() - interval '1 hour' \
+        WHERE worker_id = $1",
+        gone_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Act - Part 1 - Nothing was delivered yet
+    let html_page = app.get_admin_dashboard().await.text().await.unwrap();
+
+    // Assert - Part 1
+    assert!(html_page.contains(&worker_id.to_string()));
+    assert!(html_page.contains("<td>3</td><td>0.0</td>"));
+    assert!(!html_page.contains(&gone_id.to_string()));
+
+    // Act - Part 2 - The worker delivered an issue
+    app.publish_newsletter(&newsletter_form()).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    consume_all(&app, worker_id).await;
+    let html_page = app.get_admin_dashboard().await.text().await.unwrap();
+
+    // Assert - Part 2
+    // One delivery over the five minutes of the window
+    assert!(html_page.contains("<td>3</td><td>0.2</td>"));
+}
+
+#[tokio::test]
+async fn the_dashboard_tells_when_no_worker_is_running() {
+    // Arrange
+    let app = spawn_app().await;
+    app.test_user.login(&app).await;
+
+    // Act
+    let html_page = app.get_admin_dashboard().await.text().await.unwrap();
+
+    // Assert
+    assert!(html_page.contains("<p>No delivery worker is running.</p>"));
+}